@@ -0,0 +1,69 @@
+use color_eyre::eyre::{eyre, Context, Result};
+use serde::de::DeserializeOwned;
+use std::path::Path;
+
+/// Read a configuration file (ex: an `hq` script or `hs` rules file). The format is chosen from
+/// the file's extension (`.toml`, `.yaml`, or `.yml`), and `kind` names the file in errors.
+pub fn load<T: DeserializeOwned>(path: &Path, kind: &str) -> Result<T> {
+    let source = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read {kind} file: {:?}", path.display()))?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("toml") => {
+            toml::from_str(&source).wrap_err_with(|| format!("Failed to parse TOML {kind}"))
+        }
+        Some("yaml" | "yml") => {
+            serde_yaml::from_str(&source).wrap_err_with(|| format!("Failed to parse YAML {kind}"))
+        }
+        _ => Err(eyre!(
+            "Unknown {kind} format for {:?}, expected a .toml, .yaml, or .yml file",
+            path.display()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Config {
+        values: Vec<String>,
+    }
+
+    #[test]
+    fn can_load_toml_and_yaml() {
+        let dir = std::env::temp_dir().join(format!("hl7-common-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("can create temp dir");
+        let expected = Config {
+            values: vec!["a".to_string(), "b".to_string()],
+        };
+        for (name, source) in [
+            ("config.toml", "values = [\"a\", \"b\"]\n"),
+            ("config.YAML", "values: [a, b]\n"),
+            ("config.yml", "values:\n  - a\n  - b\n"),
+        ] {
+            let path = dir.join(name);
+            std::fs::write(&path, source).expect("can write file");
+            assert_eq!(
+                load::<Config>(&path, "test").expect("valid config"),
+                expected
+            );
+        }
+
+        let path = dir.join("config.json");
+        std::fs::write(&path, "{}").expect("can write file");
+        let err = load::<Config>(&path, "script").expect_err("unknown format");
+        assert!(
+            err.to_string().starts_with("Unknown script format"),
+            "{err}"
+        );
+        assert!(load::<Config>(&dir.join("missing.toml"), "script").is_err());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
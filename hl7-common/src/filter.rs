@@ -0,0 +1,128 @@
+use regex::Regex;
+
+/// A test of a value: present (`PID-3`), equal to (`PID-3.1=123`) or not equal to
+/// (`PID-3.1!=123`) a value, or matching (`PID-3.1~^9`) or not matching (`PID-3.1!~^9`) a
+/// regular expression
+#[derive(Debug, Clone)]
+pub enum FilterTest {
+    Present,
+    Equals(String),
+    NotEquals(String),
+    Matches(Regex),
+    NotMatches(Regex),
+}
+
+impl FilterTest {
+    /// Whether the test holds for a set of values: any of the values must match (or, for `!=`
+    /// and `!~`, none of them). An empty set is treated as a single empty value.
+    pub fn matches(&self, values: &[&str]) -> bool {
+        let values = if values.is_empty() { &[""] } else { values };
+        match self {
+            FilterTest::Present => values.iter().any(|value| !value.is_empty()),
+            FilterTest::Equals(expected) => values.iter().any(|value| value == expected),
+            FilterTest::NotEquals(expected) => !values.iter().any(|value| value == expected),
+            FilterTest::Matches(pattern) => values.iter().any(|value| pattern.is_match(value)),
+            FilterTest::NotMatches(pattern) => !values.iter().any(|value| pattern.is_match(value)),
+        }
+    }
+}
+
+impl PartialEq for FilterTest {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (FilterTest::Present, FilterTest::Present) => true,
+            (FilterTest::Equals(a), FilterTest::Equals(b))
+            | (FilterTest::NotEquals(a), FilterTest::NotEquals(b)) => a == b,
+            (FilterTest::Matches(a), FilterTest::Matches(b))
+            | (FilterTest::NotMatches(a), FilterTest::NotMatches(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for FilterTest {}
+
+/// Find the first `=` or `~` which isn't part of a `[selector]` in the query
+fn find_operator(s: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, c) in s.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth = depth.saturating_sub(1),
+            '=' | '~' if depth == 0 => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split a test off the end of a filter, returning what is being tested (a query, or a path
+/// within a selector) and the test. Without an operator, the test is whether the value is
+/// present.
+pub fn parse_test(s: &str) -> Result<(&str, FilterTest), String> {
+    let Some(operator) = find_operator(s) else {
+        return Ok((s.trim(), FilterTest::Present));
+    };
+
+    let (subject, value) = (&s[..operator], &s[operator + 1..]);
+    let (subject, negated) = match subject.strip_suffix('!') {
+        Some(subject) => (subject, true),
+        None => (subject, false),
+    };
+    let pattern =
+        || Regex::new(value).map_err(|err| format!("invalid pattern {value:?} in {s:?}: {err}"));
+    let test = match (&s[operator..=operator], negated) {
+        ("=", false) => FilterTest::Equals(value.to_string()),
+        ("=", true) => FilterTest::NotEquals(value.to_string()),
+        (_, false) => FilterTest::Matches(pattern()?),
+        (_, true) => FilterTest::NotMatches(pattern()?),
+    };
+    Ok((subject.trim(), test))
+}
+
+impl std::fmt::Display for FilterTest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FilterTest::Present => Ok(()),
+            FilterTest::Equals(value) => write!(f, "={value}"),
+            FilterTest::NotEquals(value) => write!(f, "!={value}"),
+            FilterTest::Matches(pattern) => write!(f, "~{pattern}"),
+            FilterTest::NotMatches(pattern) => write!(f, "!~{pattern}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_tests() {
+        let test = |s: &str| {
+            let (subject, test) = parse_test(s).expect("valid test");
+            format!("{subject}{test}")
+        };
+        assert_eq!(test("MSH-9.2=A08"), "MSH-9.2=A08");
+        assert_eq!(test(" PID.19 != "), "PID.19!= ");
+        assert_eq!(test("PID-3.1~^9"), "PID-3.1~^9");
+        assert_eq!(
+            test("OBX[3.1=2345-7].5!~^[0-9]"),
+            "OBX[3.1=2345-7].5!~^[0-9]"
+        );
+        assert_eq!(
+            parse_test("PID.5").expect("valid test"),
+            ("PID.5", FilterTest::Present)
+        );
+        assert!(parse_test("PID.3~(").is_err());
+    }
+
+    #[test]
+    fn can_test_values() {
+        let test = |s: &str| parse_test(s).expect("valid test").1;
+        assert!(test("X=A08").matches(&["A01", "A08"]));
+        assert!(!test("X!=A08").matches(&["A01", "A08"]));
+        assert!(test("X!~^9").matches(&["123"]));
+        assert!(test("X=").matches(&[]));
+        assert!(!test("X").matches(&[""]));
+    }
+}
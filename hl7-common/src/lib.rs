@@ -0,0 +1,6 @@
+//! Pieces shared between `hq` and `hs`: the value tests used by `hq filter --where`, script
+//! `when` conditions, and `hs listen` rules, and reading those scripts and rules from TOML or
+//! YAML files.
+
+pub mod config;
+pub mod filter;
@@ -0,0 +1,349 @@
+use crate::{
+    generate::{data_type_at, generate_value_with},
+    map::{ValueMap, ValueMapFrom, ValueMapTo},
+    query::Query,
+    segments::segments_in_order,
+};
+use color_eyre::eyre::{eyre, Context, Result};
+use hl7_parser::{LocationQuery, ParsedMessageOwned};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::ops::Range;
+
+/// The fields treated as PHI when no list of fields is given: patient, next of kin, guarantor,
+/// and insured names, addresses, phone numbers, identifiers (MRNs, account and visit numbers,
+/// SSNs, etc), and birth dates
+pub const DEFAULT_PHI_FIELDS: &[&str] = &[
+    "PID-2", "PID-3", "PID-4", "PID-5", "PID-6", "PID-7", "PID-9", "PID-11", "PID-13", "PID-14",
+    "PID-18", "PID-19", "PID-20", "PID-21", "NK1-2", "NK1-4", "NK1-5", "NK1-6", "NK1-30", "NK1-31",
+    "NK1-32", "NK1-37", "GT1-2", "GT1-3", "GT1-4", "GT1-5", "GT1-6", "GT1-7", "GT1-8", "GT1-12",
+    "IN1-16", "IN1-18", "IN1-19", "IN1-36", "IN1-49", "IN2-1", "IN2-2", "PV1-19", "PV1-50",
+];
+
+pub fn default_phi_fields() -> Vec<Query> {
+    DEFAULT_PHI_FIELDS
+        .iter()
+        .map(|field| Query::parse_location(field).expect("default PHI fields are valid"))
+        .collect()
+}
+
+/// Replace the PHI in the given fields (or components) with fake values. Fields cover every
+/// occurrence of their segment and every repeat unless they select some (ex: `NK1[2].2`).
+///
+/// Names, addresses, and dates are replaced with generated values of the field's data type,
+/// while identifiers and phone numbers keep their shape but have each digit and letter
+/// replaced. Parts of fields which aren't PHI (ex: the assigning authority of an MRN or the
+/// name type code of a name) are kept. Each fake value is chosen using the seed and the
+/// original value, so the same original value is always replaced with the same fake value.
+pub fn anonymize(
+    mut message: ParsedMessageOwned,
+    fields: &[Query],
+    seed: u64,
+) -> Result<ParsedMessageOwned> {
+    // the same field may be listed more than once
+    let mut unique_fields: Vec<&Query> = Vec::new();
+    for field in fields {
+        if !unique_fields.contains(&field) {
+            unique_fields.push(field);
+        }
+    }
+
+    let mut replacements: Vec<(Range<usize>, ValueMap)> = Vec::new();
+    for segment in segments_in_order(&message) {
+        let selected = |query: &&&Query| {
+            query.segment == segment.id
+                && query.selects_segment(&message.source, segment.segment, segment.index)
+        };
+        for query in unique_fields.iter().filter(selected) {
+            let Some(field) = query.field.and_then(|f| segment.segment.field(f)) else {
+                continue;
+            };
+            // look up data types by field (or component) rather than repeat
+            let location = LocationQuery {
+                repeat: None,
+                sub_component: None,
+                ..query.location.clone()
+            };
+            for (i, repeat) in field.repeats.iter().enumerate() {
+                if !query.selects_repeat(&message.source, repeat, i) {
+                    continue;
+                }
+                let range = match location.component {
+                    Some(component) => match repeat.components.get(component.get() - 1) {
+                        Some(component) => component.range.clone(),
+                        None => continue,
+                    },
+                    None => repeat.range.clone(),
+                };
+                let original = &message.source[range.clone()];
+                if original.is_empty() {
+                    continue;
+                }
+                let fake = fake_value(&message, &location, original, seed);
+
+                // a map which sets exactly this occurrence, repeat, and component
+                let mut from = format!(
+                    "{}[{}].{}[{}]",
+                    segment.id,
+                    segment.index + 1,
+                    location.field.expect("fields are selected"),
+                    i + 1
+                );
+                if let Some(component) = location.component {
+                    from.push_str(&format!(".{component}"));
+                }
+                let map = ValueMap {
+                    from: ValueMapFrom(from.parse().map_err(|err: String| eyre!(err))?),
+                    to: ValueMapTo::Explicit(fake),
+                };
+                replacements.push((range, map));
+            }
+        }
+    }
+
+    // a component inside a field which is also replaced (ex: PID-5.1 and PID-5) would be
+    // replaced twice, so only the outer one is kept
+    replacements.sort_by_key(|(range, _)| (range.start, std::cmp::Reverse(range.end)));
+    let mut covered = 0;
+    replacements.retain(|(range, _)| {
+        let contained = range.end <= covered;
+        covered = covered.max(range.end);
+        !contained
+    });
+
+    for (_, map) in replacements {
+        let value = map
+            .to
+            .reify(&map.from, &message)
+            .map_err(|err| eyre!(err))?;
+        message = crate::apply_map(message, &map, &value, false)
+            .wrap_err_with(|| "Failed to anonymize message")?;
+    }
+    Ok(message)
+}
+
+fn fake_value(
+    message: &ParsedMessageOwned,
+    location: &LocationQuery,
+    original: &str,
+    seed: u64,
+) -> String {
+    let data_type = data_type_at(message, location).unwrap_or("ST");
+    let escape = message.separators.escape;
+    let separator = if location.component.is_some() {
+        message.separators.subcomponent
+    } else {
+        message.separators.component
+    };
+
+    let scramble_parts = |parts: &[usize]| -> String {
+        original
+            .split(separator)
+            .enumerate()
+            .map(|(i, part)| {
+                if parts.contains(&(i + 1)) {
+                    scramble(seed, part, escape)
+                } else {
+                    part.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(&separator.to_string())
+    };
+
+    match data_type {
+        // generated names and addresses only fill the first few components, so keep anything
+        // after those (ex: name type codes) and leave empty components empty
+        "XPN" | "XAD" => {
+            let mut rng = StdRng::seed_from_u64(hash(seed, data_type, original));
+            let generated = generate_value_with(&mut rng, message, location);
+            let generated: Vec<&str> = generated.split(separator).collect();
+            original
+                .split(separator)
+                .enumerate()
+                .map(|(i, part)| match generated.get(i) {
+                    Some(generated) if !part.is_empty() => generated,
+                    _ => part,
+                })
+                .collect::<Vec<_>>()
+                .join(&separator.to_string())
+        }
+        "DT" | "DTM" | "TS" => {
+            let mut rng = StdRng::seed_from_u64(hash(seed, data_type, original));
+            generate_value_with(&mut rng, message, location)
+        }
+        // the ID number, not the assigning authority or identifier type
+        "CX" => scramble_parts(&[1]),
+        // the phone number, email address, area code, and local number
+        "XTN" => scramble_parts(&[1, 4, 6, 7]),
+        _ => scramble(seed, original, escape),
+    }
+}
+
+/// Replace each digit and letter in a value with a random digit or letter, keeping
+/// punctuation and escape sequences (ex: `\F\`) as they are
+///
+/// The replacements only depend on the seed and the value, so an identifier is scrambled the
+/// same way wherever it appears (ex: an MRN in both PID-3 and PID-18).
+fn scramble(seed: u64, value: &str, escape: char) -> String {
+    let mut rng = StdRng::seed_from_u64(hash(seed, "", value));
+    let mut in_escape = false;
+    value
+        .chars()
+        .map(|c| {
+            if c == escape {
+                in_escape = !in_escape;
+                c
+            } else if in_escape {
+                c
+            } else if c.is_ascii_digit() {
+                char::from(b'0' + rng.gen_range(0..10))
+            } else if c.is_ascii_uppercase() {
+                char::from(b'A' + rng.gen_range(0..26))
+            } else if c.is_ascii_lowercase() {
+                char::from(b'a' + rng.gen_range(0..26))
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// A stable (FNV-1a) hash of the seed, the kind of value, and the original value, used to seed
+/// the generation of each fake value
+fn hash(seed: u64, kind: &str, original: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let bytes = seed
+        .to_le_bytes()
+        .into_iter()
+        .chain(kind.bytes())
+        .chain([0])
+        .chain(original.bytes());
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::{validate_message, Severity};
+
+    const MESSAGE: &str = "MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.5.1\rEVN|A01|20050110045502\rPID|1||10006579^^^HOSP^MR~555^^^SSA^SS||DUCK^DONALD^D^^^^L||19241010|M||1|111 DUCK ST^APT 2^FOWL^CA^999990000^USA^H||(888)555-1212||||||123-45-6789\rNK1|1|DUCK^HUEY|SO\rNK1|2|DUCK^DEWEY|SO\rPV1|1|I||||||||||||||||||V123";
+
+    fn anonymized(seed: u64) -> ParsedMessageOwned {
+        let message = ParsedMessageOwned::parse(MESSAGE, false).expect("can parse message");
+        anonymize(message, &default_phi_fields(), seed).expect("can anonymize")
+    }
+
+    fn value<'m>(message: &'m ParsedMessageOwned, query: &str) -> &'m str {
+        message
+            .query_value(query)
+            .expect("valid query")
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn replaces_phi() {
+        let message = anonymized(1);
+        assert_ne!(value(&message, "PID.3.1"), "10006579");
+        assert_eq!(value(&message, "PID.3.1").len(), 8);
+        assert_eq!(value(&message, "PID.3.4"), "HOSP");
+        assert_eq!(value(&message, "PID.3[2].4"), "SSA");
+        assert_ne!(value(&message, "PID.5.1"), "DUCK");
+        assert_eq!(value(&message, "PID.5.4"), "");
+        assert_eq!(value(&message, "PID.5.7"), "L");
+        assert_ne!(value(&message, "PID.7"), "19241010");
+        assert_ne!(value(&message, "PID.11.1"), "111 DUCK ST");
+        assert_eq!(value(&message, "PID.11.6"), "USA");
+        assert!(value(&message, "PID.13").starts_with('('));
+        assert_ne!(value(&message, "PID.13"), "(888)555-1212");
+        assert_ne!(value(&message, "PID.19"), "123-45-6789");
+        assert_eq!(value(&message, "PID.19").len(), 11);
+        assert_ne!(value(&message, "PV1.19"), "V123");
+        // not PHI
+        assert_eq!(value(&message, "PID.8"), "M");
+        assert_eq!(value(&message, "NK1.3"), "SO");
+
+        // every occurrence of repeated segments
+        let segments = message.segments.get("NK1").expect("has NK1 segments");
+        for segment in segments.iter() {
+            let name = segment.fields[1].source(&message.source);
+            assert!(!name.starts_with("DUCK"), "{name}");
+        }
+
+        let errors: Vec<_> = validate_message(&message)
+            .into_iter()
+            .filter(|issue| issue.severity == Severity::Error)
+            .collect();
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn substitution_is_consistent() {
+        assert_eq!(anonymized(7).source, anonymized(7).source);
+        assert_ne!(anonymized(7).source, anonymized(8).source);
+
+        let fields = vec![
+            Query::parse_location("PID-3.1").expect("valid field"),
+            Query::parse_location("PID-18").expect("valid field"),
+        ];
+        let message = ParsedMessageOwned::parse(
+            "MSH|^~\\&|A|1|||20050110045504||ADT^A01|1|P|2.5.1\rPID|1||123^^^HOSP||DUCK|||||||||||||123",
+            false,
+        )
+        .expect("can parse message");
+        let message = anonymize(message, &fields, 42).expect("can anonymize");
+        assert_eq!(value(&message, "PID.5"), "DUCK");
+        assert_eq!(value(&message, "PID.3.1"), value(&message, "PID.18"));
+        assert_ne!(value(&message, "PID.18"), "123");
+
+        // selectors limit the occurrences and repeats which are replaced
+        let fields = vec![
+            Query::parse_location("PID-3[2].1").expect("valid field"),
+            Query::parse_location("NK1[3.1=SPO].2").expect("valid field"),
+        ];
+        let message = ParsedMessageOwned::parse(
+            "MSH|^~\\&|A|1|||20050110045504||ADT^A01|1|P|2.5.1\rPID|1||123~456\rNK1|1|DUCK^DAISY|SPO\rNK1|2|DUCK^HUEY|CHD",
+            false,
+        )
+        .expect("can parse message");
+        let message = anonymize(message, &fields, 42).expect("can anonymize");
+        assert_eq!(value(&message, "PID.3[1]"), "123");
+        assert_ne!(value(&message, "PID.3[2]"), "456");
+        assert_ne!(value(&message, "NK1.2.1"), "DUCK");
+        assert_eq!(
+            message.segment_n("NK1", 1).expect("second NK1").fields[1].source(&message.source),
+            "DUCK^HUEY"
+        );
+    }
+
+    #[test]
+    fn replaces_overlapping_fields_once() {
+        let message =
+            "MSH|^~\\&|A|1|||20050110045504||ADT^A01|1|P|2.5.1\rPID|1||123||DÜCK^DÖNALD^J";
+        let original = ParsedMessageOwned::parse(message, false).expect("can parse message");
+        let once = anonymize(
+            original.clone(),
+            &[Query::parse_location("PID-5").expect("valid field")],
+            42,
+        )
+        .expect("can anonymize");
+
+        for fields in [
+            ["PID-5", "PID-5"],
+            ["PID-5", "PID-5.1"],
+            ["PID-5.1", "PID-5"],
+        ] {
+            let fields: Vec<Query> = fields
+                .iter()
+                .map(|field| Query::parse_location(field).expect("valid field"))
+                .collect();
+            let message = anonymize(original.clone(), &fields, 42).expect("can anonymize");
+            assert_eq!(message.source, once.source, "{fields:?}");
+        }
+        assert_ne!(value(&once, "PID.5.1"), "DÜCK");
+        assert_eq!(value(&once, "PID.3"), "123");
+    }
+}
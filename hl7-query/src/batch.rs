@@ -0,0 +1,186 @@
+use std::io::BufRead;
+
+/// Segments which wrap messages in HL7 batch files but aren't part of any message
+const ENVELOPE_SEGMENTS: [&str; 4] = ["FHS", "BHS", "BTS", "FTS"];
+
+fn segment_id(segment: &str) -> &str {
+    segment.get(..3).unwrap_or(segment)
+}
+
+/// Split an input (with `\r` segment separators) into the individual messages it contains
+///
+/// A new message is started at every `MSH` segment. Batch envelope segments (`FHS`, `BHS`,
+/// `BTS`, and `FTS`) are dropped, as are any blank lines between messages. Anything before the
+/// first `MSH` segment is kept as its own chunk so that it fails to parse rather than being
+/// silently ignored.
+pub fn split_messages(input: &str) -> Vec<String> {
+    let mut messages: Vec<String> = Vec::new();
+    let mut current: Option<String> = None;
+
+    for segment in input.split('\r') {
+        if segment.trim().is_empty() {
+            continue;
+        }
+
+        let id = segment_id(segment);
+        if ENVELOPE_SEGMENTS.contains(&id) {
+            if let Some(message) = current.take() {
+                messages.push(message);
+            }
+            continue;
+        }
+
+        match current.as_mut() {
+            Some(message) if id != "MSH" => {
+                message.push('\r');
+                message.push_str(segment);
+            }
+            _ => {
+                if let Some(message) = current.replace(segment.to_string()) {
+                    messages.push(message);
+                }
+            }
+        }
+    }
+
+    if let Some(message) = current {
+        messages.push(message);
+    }
+    messages
+}
+
+/// Reads the messages in an input one at a time, without reading the whole input into memory
+///
+/// Messages are split in the same way as [split_messages], except that segments may end with
+/// `\r`, `\n`, or `\r\n`, and each message is returned exactly as it appears in the input
+/// (including the end of each segment).
+pub struct MessageReader<R> {
+    reader: R,
+    /// The MSH segment starting the next message, read while looking for the end of the last one
+    next: Option<Vec<u8>>,
+}
+
+impl<R: BufRead> MessageReader<R> {
+    pub fn new(reader: R) -> Self {
+        MessageReader { reader, next: None }
+    }
+
+    /// Read the next segment (including its `\r`, `\n`, or `\r\n`), or `None` at the end of
+    /// the input
+    fn read_segment(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        let mut segment = Vec::new();
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok((!segment.is_empty()).then_some(segment));
+            }
+            if segment.last() == Some(&b'\r') {
+                if buf[0] == b'\n' {
+                    segment.push(b'\n');
+                    self.reader.consume(1);
+                }
+                return Ok(Some(segment));
+            }
+
+            match buf.iter().position(|b| *b == b'\r' || *b == b'\n') {
+                Some(end) => {
+                    let newline = buf[end] == b'\n';
+                    segment.extend_from_slice(&buf[..=end]);
+                    self.reader.consume(end + 1);
+                    if newline {
+                        return Ok(Some(segment));
+                    }
+                }
+                None => {
+                    let len = buf.len();
+                    segment.extend_from_slice(buf);
+                    self.reader.consume(len);
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for MessageReader<R> {
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut message = self.next.take();
+        loop {
+            let segment = match self.read_segment() {
+                Ok(Some(segment)) => segment,
+                Ok(None) => return message.map(Ok),
+                Err(err) => return Some(Err(err)),
+            };
+            if segment.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+
+            let id = segment.get(..3).unwrap_or(&segment);
+            if ENVELOPE_SEGMENTS
+                .iter()
+                .any(|envelope| envelope.as_bytes() == id)
+            {
+                if message.is_some() {
+                    return message.map(Ok);
+                }
+                continue;
+            }
+
+            match message.as_mut() {
+                Some(message) if id != b"MSH" => message.extend_from_slice(&segment),
+                Some(_) => {
+                    self.next = Some(segment);
+                    return message.map(Ok);
+                }
+                None => message = Some(segment),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_split_batches() {
+        let input =
+            "FHS|^~\\&|A\rBHS|^~\\&|A\rMSH|^~\\&|1\rPID|1\r\rMSH|^~\\&|2\rPID|2\rBTS|2\rFTS|1";
+        let messages = split_messages(input);
+        assert_eq!(
+            messages,
+            vec![
+                "MSH|^~\\&|1\rPID|1".to_string(),
+                "MSH|^~\\&|2\rPID|2".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_leading_garbage() {
+        let messages = split_messages("Hello world\rMSH|^~\\&|1");
+        assert_eq!(
+            messages,
+            vec!["Hello world".to_string(), "MSH|^~\\&|1".to_string()]
+        );
+    }
+
+    #[test]
+    fn can_stream_messages() {
+        let input = "FHS|^~\\&|A\r\nMSH|^~\\&|1\r\nPID|1\r\n\r\nMSH|^~\\&|2\nPID|2\nBTS|2\rMSH|^~\\&|3\rPID|3";
+        // a tiny buffer, so that segments (and `\r\n`) are split across reads
+        let reader = std::io::BufReader::with_capacity(4, input.as_bytes());
+        let messages = MessageReader::new(reader)
+            .map(|message| String::from_utf8(message.expect("can read")).expect("valid UTF-8"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "MSH|^~\\&|1\r\nPID|1\r\n".to_string(),
+                "MSH|^~\\&|2\nPID|2\n".to_string(),
+                "MSH|^~\\&|3\rPID|3".to_string(),
+            ]
+        );
+    }
+}
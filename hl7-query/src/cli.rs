@@ -1,6 +1,12 @@
-use crate::map::ValueMap;
-use clap::{ColorChoice, Parser, ValueEnum};
-use hl7_parser::LocationQuery;
+use crate::{
+    dictionary::Version,
+    filter::Filter,
+    map::{Deletion, Insertion, Operation, ValueMap},
+    query::Query,
+};
+use clap::{
+    Args, ColorChoice, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+};
 use std::{path::PathBuf, str::FromStr};
 
 #[derive(Parser, Debug)]
@@ -26,43 +32,456 @@ pub struct Cli {
     ///
     /// Example: `MSH-10=1234`
     ///
+    /// Example: `PV1-19={PID-3.1}-{substr(MSH-7, 0, 8)}`
+    ///
     /// The `hl7_field` is a location query, see https://docs.rs/hl7-parser/0.1.0/hl7_parser/struct.LocationQuery.html
+    /// Repeated segments can be picked out with a 1-based index, as in the paths printed by
+    /// `--output table` (ex: `OBX[2].5`)
     ///
     /// The `value` is a string value to use for the fields
     ///
-    /// The `auto` keyword will attempt to generate an appropriate value for the field
-    ///  (for example, a date for a date field, a control ID for MSH-10, etc.)
+    /// The `value` may contain `{expressions}` which are evaluated against the message before
+    /// any maps are applied. Expressions can be field references (`{MSH-3}`), string literals
+    /// (`{"text"}`), or the functions `upper(value)`, `lower(value)`,
+    /// `substr(value, start[, length])`, `default(value, fallback, ...)`, and
+    /// `concat(value, ...)`. Use `{{` and `}}` for literal braces alongside expressions; values
+    /// without any valid expressions (ex: JSON) are used as they are.
+    ///
+    /// The `auto` keyword will generate an appropriate value for the field based on its data type
+    /// in the HL7 dictionary for the message's version (for example, a birth date for PID-7, a
+    /// code from table 0001 for PID-8, a control ID for MSH-10, etc.)
+    ///
+    /// Fields, repeats, components, and sub-components which don't exist yet are added to the
+    /// segment. Maps to segments which aren't in the message are skipped with a warning unless
+    /// `--create-segments` is used.
     pub map: Vec<ValueMap>,
 
+    #[arg(short, long, value_parser = clap::value_parser!(Deletion))]
+    /// Delete fields or segments from the HL7 message
+    ///
+    /// Fields, repeats, components, and sub-components are cleared (so that the positions of the
+    /// fields after them don't change), while a segment on its own removes every segment with
+    /// that identifier. Locations use the same syntax as `--query`, so occurrences of a segment
+    /// can be picked out with an index, `[*]`, or a filter
+    ///
+    /// Example: `PID-19` (clear the patient's SSN)
+    ///
+    /// Example: `NTE` (remove every NTE segment)
+    ///
+    /// Example: `OBX[2]` (remove the second OBX segment)
+    ///
+    /// Example: `OBX[3.1=2345-7].5` (clear the value of every glucose OBX)
+    ///
+    /// Deletions, insertions, and maps are applied in the order they're given on the command line
+    pub delete: Vec<Deletion>,
+
+    #[arg(long, value_parser = clap::value_parser!(Insertion))]
+    /// Insert a segment after an occurrence of another segment
+    ///
+    /// Format: `segment_id[occurrence]=segment`
+    ///
+    /// Example: `OBX[2]=NTE|1||Checked by Dr Duck` (add a note after the second OBX)
+    ///
+    /// The occurrence is 1-based, and defaults to the first occurrence of the segment. The new
+    /// segment is written as-is, so must use the message's separators.
+    pub insert: Vec<Insertion>,
+
+    #[arg(long)]
+    /// Read a list of maps, deletions, and insertions to apply from a TOML or YAML file
+    ///
+    /// The operations in the script are applied in order, before any given on the command line.
+    /// Each operation has one of `map`, `delete`, or `insert` (using the same syntax as the
+    /// command line arguments), and maps can have a `when` condition so that they are only
+    /// applied to some messages. Conditions use the same syntax as `hq filter --where`
+    /// (`query=value`, `query!=value`, `query~pattern`, `query!~pattern`, or `query` to check
+    /// that the value is present), and are checked against the message before any edits are
+    /// made.
+    ///
+    /// Example (TOML):
+    ///
+    ///     [[operations]]
+    ///     map = "MSH-10=<auto>"
+    ///
+    ///     [[operations]]
+    ///     delete = "NTE"
+    ///
+    ///     [[operations]]
+    ///     map = "PV1-3.1=ICU"
+    ///     when = "PV1-2=I"
+    pub script: Option<PathBuf>,
+
+    #[arg(skip)]
+    /// The maps, deletions, and insertions in the order they were given on the command line
+    pub operations: Vec<Operation>,
+
+    #[arg(long, default_value_t = false)]
+    /// Add segments targeted by `--map` which aren't in the message
+    ///
+    /// New segments are inserted where they belong in the message structure for the message
+    /// type in MSH-9 (or at the end of the message if the structure isn't known)
+    pub create_segments: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Replace PHI in the message with fake but realistic values
+    ///
+    /// Names, addresses, phone numbers, identifiers (MRNs, account and visit numbers, SSNs,
+    /// etc), and birth dates in the PID, NK1, GT1, IN1, IN2, and PV1 segments are replaced,
+    /// keeping the values valid for their data types. Every occurrence of repeated segments and
+    /// fields is replaced, and the same original value is always replaced with the same fake
+    /// value. Anonymization happens after any maps, deletions, and insertions.
+    pub anonymize: bool,
+
+    #[arg(long, requires = "anonymize")]
+    /// The seed to use when anonymizing, so that the fake values are the same between runs
+    ///
+    /// Defaults to a random seed (fake values are still consistent within a run)
+    pub anonymize_seed: Option<u64>,
+
+    #[arg(long, requires = "anonymize", value_delimiter = ',', value_parser = Query::parse_location)]
+    /// The fields (or components) to treat as PHI when anonymizing, instead of the default list
+    ///
+    /// Fields use the same syntax as `--query`, but cover every occurrence of the segment and
+    /// every repeat of the field unless they select some with `[...]`
+    ///
+    /// Example: `PID-3.1,PID-5,PID-7,NK1-2`
+    ///
+    /// Example: `NK1[3.1=SPO].2` (only the names of spouses)
+    ///
+    /// Default: PID-2, PID-3, PID-4, PID-5, PID-6, PID-7, PID-9, PID-11, PID-13, PID-14, PID-18,
+    /// PID-19, PID-20, PID-21, NK1-2, NK1-4, NK1-5, NK1-6, NK1-30, NK1-31, NK1-32, NK1-37,
+    /// GT1-2, GT1-3, GT1-4, GT1-5, GT1-6, GT1-7, GT1-8, GT1-12, IN1-16, IN1-18, IN1-19, IN1-36,
+    /// IN1-49, IN2-1, IN2-2, PV1-19, PV1-50
+    pub anonymize_fields: Vec<Query>,
+
     #[arg(short, long)]
     /// Extract fields from the HL7 message and print the Result
     ///
     /// Query the HL7 message (after any mappings have been applied) and print the result
-    /// Multiple queries can be specified and will be reported on separate lines
-    pub query: Vec<LocationQuery>,
+    /// Multiple queries can be specified and will be reported on separate lines, once for each
+    /// message in the input. Repeated segments can be picked out with a 1-based index, as in the
+    /// paths printed by `--output table` (ex: `OBX[2].5`)
+    ///
+    /// Segment occurrences and field repeats can also be selected with `[*]` (all of them) or a
+    /// filter on a value relative to the segment or repeat, printing a line for each match (or an
+    /// empty line if nothing matches):
+    ///
+    /// Example: `IN1[*].4` (every insurance company name)
+    ///
+    /// Example: `OBX[3.1=2345-7].5` (the value of every OBX whose OBX-3.1 is 2345-7)
+    ///
+    /// Example: `PID.3[5=MR].1` (every patient identifier whose identifier type code is MR)
+    ///
+    /// Example: `PID.3[5!=MR].1` (every patient identifier except MRs)
+    ///
+    /// Example: `OBX[5~^[0-9]+$].3` (the observation identifier of every numeric OBX)
+    ///
+    /// Filters use the same operators as `hq filter --where`: `=`, `!=`, `~` (matches a regular
+    /// expression), and `!~`
+    ///
+    /// With `--output json`, `csv`, or `tsv`, the results are printed as one record per message
+    /// instead, keyed by the queries as they were written. Queries with many matches are printed
+    /// as arrays in JSON, and as one value per line within the cell in CSV and TSV
+    pub query: Vec<Query>,
 
-    #[arg(short, long, default_value_t = false)]
+    #[arg(long, default_value_t = false, conflicts_with = "query")]
+    /// Validate the HL7 message against the bundled HL7 dictionary and print any issues found
+    ///
+    /// The dictionary version is chosen from MSH-12. Segment order and required segments are
+    /// checked against the message structure for the message type in MSH-9, and fields are
+    /// checked for optionality, maximum length, repetition, and data type. The dictionary only
+    /// covers common segments and the ADT, ORM, ORU, and ACK message structures; anything else is
+    /// reported as a warning and not checked. Each issue is printed with its severity and
+    /// location. Exits with a non-zero status if any errors are found.
+    pub validate: bool,
+
+    #[arg(short, long, global = true, default_value_t = false)]
     /// Don't correct newlines in the HL7 message
     ///
-    /// By default, \r\n and \n will be converted to \r to separate segments
+    /// By default, \r\n and \n will be converted to \r to separate segments (HL7 input only)
     pub no_correct_newlines: bool,
 
-    #[arg(short, long, default_value_t = ColorChoice::Auto)]
+    #[arg(short, long, global = true, default_value_t = ColorChoice::Auto)]
     /// Colorize output
     pub colour: ColorChoice,
 
+    #[arg(short, long, global = true, default_value_t = InputFormat::HL7)]
+    /// The format of the input
+    pub input_format: InputFormat,
+
     #[arg(short, long, default_value_t = OutputMode::HL7)]
     /// How to output the HL7 message
     pub output: OutputMode,
 
-    /// The input file to read an HL7 message from
+    #[arg(short, long, default_value_t = false)]
+    /// Annotate table output with field names, data types, and coded values
+    ///
+    /// Each row is followed by the name of the field (and component) and its data type from the
+    /// bundled HL7 dictionary for the version in MSH-12. Values from HL7 tables are followed by
+    /// their description, ex: `PID.8    M (Male)    Administrative Sex    IS`. Only used with
+    /// `--output table`.
+    pub annotate: bool,
+
+    /// The input file to read HL7 messages from
+    ///
+    /// If not specified, the messages will be read from stdin. The input may contain any number
+    /// of messages, each starting with an MSH segment. Batch envelope segments (FHS, BHS, BTS,
+    /// FTS) are ignored.
+    pub input: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Compare two HL7 messages (or batches of messages) field by field
+    ///
+    /// Each difference is printed on its own line with a marker (`-` for values only in the first
+    /// message, `+` for values only in the second message, and `~` for changed values), the
+    /// path to the value (as printed by `--output table`), and the decoded values from each
+    /// message. Repeated segments are paired with the most similar segment in the other message,
+    /// so an extra segment in the middle of a message is reported as added rather than changing
+    /// every segment after it; a changed value in a segment paired with one at a different index
+    /// is printed with both paths (ex: `OBX[2].5 -> OBX[3].5`). Any maps, deletions, insertions,
+    /// or anonymization are applied to both messages before they're compared. Exits with status
+    /// 1 if the messages differ.
+    Diff(DiffArgs),
+
+    /// Search the decoded values of HL7 messages for a pattern
+    ///
+    /// Each matching value is printed on its own line with the file it was found in, the
+    /// 1-based index of the message within the file, the path to the value (as printed by
+    /// `--output table`), and the value itself. Directories are searched recursively, and files
+    /// which can't be read as HL7 messages are skipped with a warning. Exits with a non-zero
+    /// status if nothing matches.
+    Grep(GrepArgs),
+
+    /// Print only the messages which match every `--where` filter
+    ///
+    /// Messages are read one at a time, so large files (such as interface logs) can be filtered
+    /// without reading them into memory, and are printed exactly as they appear in the input.
+    /// Edits (`--map`, `--delete`, etc.) aren't applied. Messages which can't be parsed are
+    /// skipped with a warning. Exits with a non-zero status if no messages match.
+    Filter(FilterArgs),
+
+    /// Report what a set of HL7 messages actually contains
+    ///
+    /// Prints the number of messages of each message type and trigger event, how many messages
+    /// contain each segment (including `Z` segments), and for each field how often it is
+    /// populated, how many distinct values it has, and its most common values. Fields with
+    /// values longer than the maximum length in the HL7 dictionary are listed separately.
+    /// Directories are searched recursively, and files which can't be read as HL7 messages are
+    /// skipped with a warning. Prints tables, or JSON with `--output json`.
+    Stats(StatsArgs),
+
+    /// Generate synthetic HL7 messages of a message type
+    ///
+    /// Messages follow the message structure for the message type in the bundled HL7
+    /// dictionary, which has structures for ADT, ORM, ORU, and ACK messages. Required segments
+    /// and fields are always included, while optional ones are included at random, and values
+    /// are generated as for `--map field=<auto>`. The messages are printed using `--output` (and
+    /// `--map` and other edits are applied) as if they had been read from a file.
+    #[command(disable_version_flag = true)]
+    Generate(GenerateArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    #[arg(long, value_delimiter = ',', value_parser = Query::parse_location)]
+    /// Fields, components, or segments to ignore when comparing the messages
+    ///
+    /// Locations use the same syntax as `--query`, but cover every occurrence of the segment and
+    /// every repeat of the field unless they select some with `[...]`
+    ///
+    /// Example: `MSH-7,MSH-10,EVN` (ignore the message time, control ID, and event segment)
+    ///
+    /// Example: `OBX[3.1=2345-7].5` (ignore the values of glucose results)
+    pub ignore: Vec<Query>,
+
+    /// The first file to compare
+    pub a: PathBuf,
+
+    /// The second file to compare
+    pub b: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct FilterArgs {
+    #[arg(short = 'w', long = "where", required = true)]
+    /// A test which messages must pass to be printed
+    ///
+    /// Format: `query=value`, `query!=value`, `query~pattern`, `query!~pattern`, or `query` (the
+    /// value is present)
+    ///
+    /// Example: `MSH-9.2=A08` (only A08 messages)
+    ///
+    /// Example: `PID-3.1~^12` (only patients whose ID starts with `12`)
     ///
-    /// If not specified, the message will be read from stdin
+    /// The `query` can be anything `--query` accepts, and passes if any of the values it selects
+    /// match (ex: `PID-3[*].1=1234` checks every patient ID). Patterns are regular expressions,
+    /// and values which aren't in the message are treated as empty.
+    pub filters: Vec<Filter>,
+
+    /// The input file to read HL7 messages from. If not specified, messages are read from stdin.
     pub input: Option<PathBuf>,
 }
 
+#[derive(Args, Debug)]
+pub struct StatsArgs {
+    #[arg(long, default_value_t = 5)]
+    /// The number of most common values to print for each field
+    pub top: usize,
+
+    /// The files or directories to read messages from. If not specified, messages are read from
+    /// stdin.
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct GenerateArgs {
+    /// The message type and trigger event to generate, ex: `ADT^A01`
+    pub message_type: String,
+
+    #[arg(long, default_value_t = Version::V2_5_1)]
+    /// The HL7 version of the messages
+    pub version: Version,
+
+    #[arg(long, default_value_t = 1)]
+    /// The number of messages to generate
+    pub count: usize,
+
+    #[arg(long)]
+    /// Generate the same messages every time for the same seed
+    ///
+    /// With a seed, dates and times are generated as if it were midnight on 1 January 2025
+    /// rather than the current time, so that they are reproducible too
+    pub seed: Option<u64>,
+
+    #[arg(long)]
+    /// A message whose values override the generated ones
+    ///
+    /// Every segment in the template is included in the generated messages, and every field
+    /// with a value in the template is copied into the same field of the same occurrence of
+    /// the segment (re-encoded for the `|^~\&` separators of the generated messages, if the
+    /// template uses others). Fields which are empty in the template are generated as usual.
+    ///
+    /// Example: `MSH|^~\&|MYAPP|MYFACILITY` (set the sending application and facility)
+    pub template: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct GrepArgs {
+    #[arg(short = 'F', long, default_value_t = false)]
+    /// Treat the pattern as a literal string rather than a regular expression
+    pub fixed_strings: bool,
+
+    #[arg(long, default_value_t = false)]
+    /// Ignore case when matching the pattern
+    pub ignore_case: bool,
+
+    #[arg(long, value_delimiter = ',')]
+    /// Only search these segments
+    ///
+    /// Example: `PID,NK1`
+    pub segment: Vec<String>,
+
+    #[arg(long, value_delimiter = ',', value_parser = Query::parse_location)]
+    /// Only search these fields, components, or segments
+    ///
+    /// Locations use the same syntax as `--query`, but cover every occurrence of the segment and
+    /// every repeat of the field unless they select some with `[...]`
+    ///
+    /// Example: `PID-5,NK1-2.1`
+    ///
+    /// Example: `OBX[3.1=2345-7].5` (only search the values of glucose results)
+    pub field: Vec<Query>,
+
+    #[arg(long, value_delimiter = ',')]
+    /// Only search values of these data types (from the bundled HL7 dictionary)
+    ///
+    /// A value matches if it or any field or component containing it has one of the data
+    /// types, ex: `XPN` searches every part of every person name.
+    pub data_type: Vec<String>,
+
+    /// The regular expression (or string, with `--fixed-strings`) to search for
+    pub pattern: String,
+
+    /// The files or directories to search. If not specified, messages are read from stdin.
+    pub paths: Vec<PathBuf>,
+}
+
 pub fn cli() -> Cli {
-    Cli::parse()
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+
+    // clap collects each argument separately, so use the positions of the arguments to put the
+    // maps, deletions, and insertions back into command line order
+    let indices = |id: &str| -> Vec<usize> {
+        matches
+            .indices_of(id)
+            .map(|indices| indices.collect())
+            .unwrap_or_default()
+    };
+    let mut operations: Vec<(usize, Operation)> = Vec::new();
+    operations.extend(
+        indices("map")
+            .into_iter()
+            .zip(cli.map.iter().cloned().map(Operation::Map)),
+    );
+    operations.extend(
+        indices("delete")
+            .into_iter()
+            .zip(cli.delete.iter().cloned().map(Operation::Delete)),
+    );
+    operations.extend(
+        indices("insert")
+            .into_iter()
+            .zip(cli.insert.iter().cloned().map(Operation::Insert)),
+    );
+    operations.sort_by_key(|(index, _)| *index);
+    cli.operations = operations.into_iter().map(|(_, op)| op).collect();
+    cli
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[derive(ValueEnum)]
+pub enum InputFormat {
+    #[default]
+    /// Read HL7 messages (the default)
+    HL7,
+    /// Read messages in the JSON format printed by `--output json`
+    ///
+    /// The JSON is converted back into HL7, rebuilding MSH-1 and MSH-2 and escaping any
+    /// separator characters found in values. This allows messages to be edited with tools such
+    /// as `jq` before being passed on
+    Json,
+    /// Read messages in the HL7 v2.xml encoding (such as printed by `--output xml`)
+    ///
+    /// Segment groups are flattened, and a new message is started at each MSH segment
+    Xml,
+}
+
+impl std::fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputFormat::HL7 => write!(f, "hl7"),
+            InputFormat::Json => write!(f, "json"),
+            InputFormat::Xml => write!(f, "xml"),
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hl7" => Ok(InputFormat::HL7),
+            "json" => Ok(InputFormat::Json),
+            "xml" => Ok(InputFormat::Xml),
+            _ => Err(format!("invalid input format: {}", s)),
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
@@ -81,20 +500,34 @@ pub enum OutputMode {
     HL7,
     /// Print the HL7 message as JSON
     ///
+    /// The output is an array of messages, even if the input contains only one message. Each
+    /// message is an array of segments in the order they appear in the message. Values are
+    /// unescaped, and repeated fields are printed as arrays
+    ///
+    /// The output can be read back in with `--input-format json`
+    ///
     /// Example:
     /// ```json
-    /// {
-    ///  "MSH": {
-    ///    "1": "|",
-    ///    "2": "^~\\&",
-    ///    "3": "EPICADT",
-    ///   }
-    /// }
+    /// [
+    ///   [
+    ///     {
+    ///       "MSH": {
+    ///         "1": "|",
+    ///         "2": "^~\\&",
+    ///         "3": "EPICADT",
+    ///       }
+    ///     }
+    ///   ]
+    /// ]
     /// ```
     Json,
 
     /// Print the HL7 message as a list of rows specifying the field name and value
     ///
+    /// Rows are printed in the order the segments appear in the message
+    /// If the input contains more than one message, each row is prefixed with the 1-based index
+    /// of the message
+    ///
     /// Example:
     ///
     /// ```text
@@ -102,6 +535,54 @@ pub enum OutputMode {
     /// MSH.15 AL
     /// ```
     Table,
+
+    /// Print the HL7 message using the HL7 v2.xml encoding
+    ///
+    /// Segments are grouped according to the message structure when the message type is known.
+    /// If the input contains more than one message, the messages are wrapped in a `<MESSAGES>`
+    /// element
+    ///
+    /// Example:
+    ///
+    /// ```xml
+    /// <ADT_A01 xmlns="urn:hl7-org:v2xml">
+    ///   <PID>
+    ///     <PID.5>
+    ///       <XPN.1>
+    ///         <FN.1>DUCK</FN.1>
+    ///       </XPN.1>
+    ///     </PID.5>
+    ///   </PID>
+    /// </ADT_A01>
+    /// ```
+    Xml,
+
+    /// Print query results as comma-separated values (requires `--query`)
+    ///
+    /// A header row lists the queries, followed by one row per message. Empty values are
+    /// printed as `""`, while values missing from the message are left blank
+    ///
+    /// Example:
+    ///
+    /// ```text
+    /// MSH.10,PID.5.1,PID.19
+    /// 599102,DUCK,""
+    /// ```
+    Csv,
+
+    /// Print query results as tab-separated values (requires `--query`)
+    ///
+    /// A header row lists the queries, followed by one row per message. Values missing from the
+    /// message are printed as `\N`, and tabs, newlines, and backslashes in values are escaped as
+    /// `\t`, `\n`, and `\\`
+    ///
+    /// Example:
+    ///
+    /// ```text
+    /// MSH.10  PID.5.1  PID.19
+    /// 599102  DUCK     \N
+    /// ```
+    Tsv,
 }
 
 impl std::fmt::Display for OutputMode {
@@ -110,6 +591,9 @@ impl std::fmt::Display for OutputMode {
             OutputMode::HL7 => write!(f, "hl7"),
             OutputMode::Json => write!(f, "json"),
             OutputMode::Table => write!(f, "table"),
+            OutputMode::Xml => write!(f, "xml"),
+            OutputMode::Csv => write!(f, "csv"),
+            OutputMode::Tsv => write!(f, "tsv"),
         }
     }
 }
@@ -122,6 +606,9 @@ impl FromStr for OutputMode {
             "hl7" => Ok(OutputMode::HL7),
             "json" => Ok(OutputMode::Json),
             "table" => Ok(OutputMode::Table),
+            "xml" => Ok(OutputMode::Xml),
+            "csv" => Ok(OutputMode::Csv),
+            "tsv" => Ok(OutputMode::Tsv),
             _ => Err(format!("invalid output mode: {}", s)),
         }
     }
@@ -0,0 +1,525 @@
+use hl7_parser::ParsedMessageOwned;
+use serde::Deserialize;
+use std::{collections::HashMap, str::FromStr, sync::OnceLock};
+
+const SEGMENTS: &str = include_str!("../assets/dictionary/segments.json");
+const DATA_TYPES: &str = include_str!("../assets/dictionary/data_types.json");
+const TABLES: &str = include_str!("../assets/dictionary/tables.json");
+const MESSAGES: &str = include_str!("../assets/dictionary/messages.json");
+
+/// The HL7 versions covered by the bundled dictionary
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Version {
+    V2_3,
+    V2_3_1,
+    V2_4,
+    V2_5,
+    V2_5_1,
+    V2_6,
+    V2_7,
+    V2_7_1,
+    V2_8,
+}
+
+impl Version {
+    const ALL: [Version; 9] = [
+        Version::V2_3,
+        Version::V2_3_1,
+        Version::V2_4,
+        Version::V2_5,
+        Version::V2_5_1,
+        Version::V2_6,
+        Version::V2_7,
+        Version::V2_7_1,
+        Version::V2_8,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Version::V2_3 => "2.3",
+            Version::V2_3_1 => "2.3.1",
+            Version::V2_4 => "2.4",
+            Version::V2_5 => "2.5",
+            Version::V2_5_1 => "2.5.1",
+            Version::V2_6 => "2.6",
+            Version::V2_7 => "2.7",
+            Version::V2_7_1 => "2.7.1",
+            Version::V2_8 => "2.8",
+        }
+    }
+
+    /// Find the closest version covered by the dictionary for a version string (such as the
+    /// contents of MSH-12). Versions older than 2.3 map to 2.3, and versions newer than 2.8 map
+    /// to 2.8. Returns `None` if the string doesn't look like a version number at all.
+    pub fn nearest(s: &str) -> Option<Version> {
+        fn numbers(s: &str) -> Option<Vec<u32>> {
+            s.split('.').map(|n| n.parse::<u32>().ok()).collect()
+        }
+
+        let wanted = numbers(s.trim())?;
+        Version::ALL
+            .iter()
+            .rev()
+            .find(|v| numbers(v.as_str()).expect("versions are numeric") <= wanted)
+            .or(Version::ALL.first())
+            .copied()
+    }
+
+    /// Whether maximum lengths are conformance constraints in this version (HL7 v2.7 and later
+    /// only publish them as guidance)
+    pub fn enforces_max_length(&self) -> bool {
+        *self < Version::V2_7
+    }
+
+    /// Map a data type from the dictionary (which uses the v2.5 names) onto the data type used
+    /// in this version
+    pub fn data_type<'d>(&self, data_type: &'d str) -> &'d str {
+        if *self >= Version::V2_7 {
+            match data_type {
+                "TS" => "DTM",
+                "CE" => "CWE",
+                data_type => data_type,
+            }
+        } else {
+            data_type
+        }
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Version::ALL
+            .iter()
+            .find(|v| v.as_str() == s)
+            .copied()
+            .ok_or_else(|| format!("unsupported HL7 version: {s}"))
+    }
+}
+
+impl TryFrom<String> for Version {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The version of the dictionary to use for a message, based on MSH-12
+pub fn message_version(message: &ParsedMessageOwned) -> Option<Version> {
+    message
+        .query_value("MSH.12.1")
+        .expect("valid query")
+        .and_then(Version::nearest)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+pub enum Optionality {
+    #[serde(rename = "R")]
+    Required,
+    #[serde(rename = "O")]
+    Optional,
+    #[serde(rename = "C")]
+    Conditional,
+    #[serde(rename = "B")]
+    BackwardCompatible,
+    #[serde(rename = "X")]
+    NotSupported,
+    #[serde(rename = "W")]
+    Withdrawn,
+}
+
+/// A difference from the v2.5 definition of a field, for a range of versions
+#[derive(Debug, Clone, Deserialize)]
+struct FieldOverride {
+    since: Option<Version>,
+    until: Option<Version>,
+    optionality: Option<Optionality>,
+    max_length: Option<usize>,
+}
+
+impl FieldOverride {
+    fn applies_to(&self, version: Version) -> bool {
+        self.since.map(|since| since <= version).unwrap_or(true)
+            && self.until.map(|until| version <= until).unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FieldDefinition {
+    pub name: String,
+    data_type: String,
+    optionality: Optionality,
+    max_length: Option<usize>,
+    #[serde(default)]
+    repeatable: bool,
+    max_repeats: Option<usize>,
+    pub table: Option<String>,
+    since: Option<Version>,
+    #[serde(default)]
+    overrides: Vec<FieldOverride>,
+}
+
+impl FieldDefinition {
+    /// The data type of the field in the given version
+    pub fn data_type(&self, version: Version) -> &str {
+        version.data_type(&self.data_type)
+    }
+
+    /// The optionality of the field in the given version
+    pub fn optionality(&self, version: Version) -> Optionality {
+        self.overrides(version)
+            .find_map(|o| o.optionality)
+            .unwrap_or(self.optionality)
+    }
+
+    /// The maximum length of the field in the given version, or `None` if there is no limit
+    pub fn max_length(&self, version: Version) -> Option<usize> {
+        self.overrides(version)
+            .find_map(|o| o.max_length)
+            .or(self.max_length)
+    }
+
+    fn overrides(&self, version: Version) -> impl Iterator<Item = &FieldOverride> {
+        self.overrides.iter().filter(move |o| o.applies_to(version))
+    }
+
+    /// The maximum number of times the field may repeat, or `None` if there is no limit
+    pub fn max_repeats(&self) -> Option<usize> {
+        if self.repeatable {
+            None
+        } else {
+            Some(self.max_repeats.unwrap_or(1))
+        }
+    }
+
+    fn defined_in(&self, version: Version) -> bool {
+        self.since.map(|since| since <= version).unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SegmentDefinition {
+    pub name: String,
+    fields: Vec<FieldDefinition>,
+}
+
+impl SegmentDefinition {
+    /// The fields of the segment which are defined in the given version
+    pub fn fields(&self, version: Version) -> impl Iterator<Item = &FieldDefinition> {
+        // fields are only ever added to the end of a segment, so this is always a prefix
+        self.fields
+            .iter()
+            .take_while(move |f| f.defined_in(version))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ComponentDefinition {
+    pub name: String,
+    pub data_type: String,
+    pub table: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DataTypeDefinition {
+    pub components: Vec<ComponentDefinition>,
+}
+
+impl DataTypeDefinition {
+    pub fn is_primitive(&self) -> bool {
+        self.components.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TableValue {
+    pub code: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TableDefinition {
+    pub name: String,
+    pub values: Vec<TableValue>,
+}
+
+impl TableDefinition {
+    pub fn contains(&self, code: &str) -> bool {
+        self.values.iter().any(|v| v.code == code)
+    }
+}
+
+/// An entry in a message structure: either a single segment or a named group of entries
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum StructureItem {
+    Segment {
+        segment: String,
+        #[serde(default)]
+        required: bool,
+        #[serde(default)]
+        repeatable: bool,
+        since: Option<Version>,
+    },
+    Group {
+        group: String,
+        #[serde(default)]
+        required: bool,
+        #[serde(default)]
+        repeatable: bool,
+        items: Vec<StructureItem>,
+    },
+}
+
+impl StructureItem {
+    pub fn required(&self) -> bool {
+        match self {
+            StructureItem::Segment { required, .. } | StructureItem::Group { required, .. } => {
+                *required
+            }
+        }
+    }
+
+    pub fn repeatable(&self) -> bool {
+        match self {
+            StructureItem::Segment { repeatable, .. } | StructureItem::Group { repeatable, .. } => {
+                *repeatable
+            }
+        }
+    }
+
+    pub fn defined_in(&self, version: Version) -> bool {
+        match self {
+            StructureItem::Segment { since, .. } => since.map(|s| s <= version).unwrap_or(true),
+            StructureItem::Group { .. } => true,
+        }
+    }
+
+    /// Whether the segment appears anywhere within this item
+    pub fn contains_segment(&self, id: &str) -> bool {
+        match self {
+            StructureItem::Segment { segment, .. } => segment == id,
+            StructureItem::Group { items, .. } => items.iter().any(|i| i.contains_segment(id)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageDefinitions {
+    events: HashMap<String, HashMap<String, String>>,
+    structures: HashMap<String, Vec<StructureItem>>,
+}
+
+/// A dictionary of HL7 segments, data types, tables, and message structures
+#[derive(Debug)]
+pub struct Dictionary {
+    segments: HashMap<String, SegmentDefinition>,
+    data_types: HashMap<String, DataTypeDefinition>,
+    tables: HashMap<String, TableDefinition>,
+    messages: MessageDefinitions,
+}
+
+/// The dictionary bundled with `hq`
+///
+/// The dictionary covers the commonly used segments, data types, and tables of HL7 v2.3 to v2.8,
+/// and the message structures of the ADT, ORM, ORU, and ACK messages. Definitions are taken from
+/// v2.5, with the fields added in each version, and overrides where the length or optionality of
+/// a field differs in other versions. Segments and message structures which aren't listed here
+/// aren't checked.
+pub fn dictionary() -> &'static Dictionary {
+    static DICTIONARY: OnceLock<Dictionary> = OnceLock::new();
+    DICTIONARY.get_or_init(|| Dictionary {
+        segments: serde_json::from_str(SEGMENTS).expect("bundled segments are valid"),
+        data_types: serde_json::from_str(DATA_TYPES).expect("bundled data types are valid"),
+        tables: serde_json::from_str(TABLES).expect("bundled tables are valid"),
+        messages: serde_json::from_str(MESSAGES).expect("bundled messages are valid"),
+    })
+}
+
+impl Dictionary {
+    pub fn segment(&self, id: &str) -> Option<&SegmentDefinition> {
+        self.segments.get(id)
+    }
+
+    pub fn data_type(&self, version: Version, id: &str) -> Option<&DataTypeDefinition> {
+        self.data_types.get(version.data_type(id))
+    }
+
+    pub fn table(&self, id: &str) -> Option<&TableDefinition> {
+        self.tables.get(id)
+    }
+
+    pub fn structure(&self, id: &str) -> Option<&[StructureItem]> {
+        self.messages.structures.get(id).map(|s| s.as_slice())
+    }
+
+    /// Look up a message structure identifier, returning the dictionary's copy of it
+    pub fn structure_id(&self, id: &str) -> Option<&str> {
+        self.messages
+            .structures
+            .get_key_value(id)
+            .map(|(id, _)| id.as_str())
+    }
+
+    /// Find the message structure used by a message type and trigger event (ex: `ADT` and
+    /// `A04` use the `ADT_A01` structure)
+    pub fn message_structure(&self, message_code: &str, trigger_event: &str) -> Option<&str> {
+        let events = self.messages.events.get(message_code)?;
+        events
+            .get(trigger_event)
+            .or_else(|| events.get("*"))
+            .map(|s| s.as_str())
+    }
+}
+
+/// The dictionary's description of a field, component, or sub-component
+#[derive(Debug, Clone)]
+pub struct Annotation<'d> {
+    /// The names of the field and any components leading to the value, ex: `["Patient Name",
+    /// "Family Name"]`
+    pub names: Vec<&'d str>,
+    /// The data types of the field and any components leading to the value, ex: `["XPN",
+    /// "FN"]`
+    pub data_types: Vec<&'d str>,
+    pub data_type: &'d str,
+    pub table: Option<&'d TableDefinition>,
+}
+
+impl Annotation<'_> {
+    /// Look up the description of a value in the annotation's table, if there is one
+    pub fn describe(&self, code: &str) -> Option<&str> {
+        self.table?
+            .values
+            .iter()
+            .find(|v| v.code == code)
+            .map(|v| v.description.as_str())
+    }
+}
+
+impl Dictionary {
+    /// Describe a field, or a component or sub-component of a field, using the 1-based field,
+    /// component, and sub-component numbers
+    pub fn annotate(
+        &self,
+        version: Version,
+        segment: &str,
+        field: usize,
+        component: Option<usize>,
+        sub_component: Option<usize>,
+    ) -> Option<Annotation<'_>> {
+        let field = self
+            .segment(segment)?
+            .fields(version)
+            .nth(field.checked_sub(1)?)?;
+        let mut annotation = Annotation {
+            names: vec![&field.name],
+            data_types: vec![field.data_type(version)],
+            data_type: field.data_type(version),
+            table: field.table.as_deref().and_then(|t| self.table(t)),
+        };
+        for index in [component, sub_component].into_iter().flatten() {
+            let component = self
+                .data_type(version, annotation.data_type)?
+                .components
+                .get(index.checked_sub(1)?)?;
+            annotation.names.push(&component.name);
+            annotation.data_type = version.data_type(&component.data_type);
+            annotation.data_types.push(annotation.data_type);
+            annotation.table = component.table.as_deref().and_then(|t| self.table(t));
+        }
+        Some(annotation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_load_dictionary() {
+        let dictionary = dictionary();
+        let pid = dictionary.segment("PID").expect("PID is defined");
+        let name = pid.fields(Version::V2_5).nth(4).expect("PID-5 is defined");
+        assert_eq!(name.name, "Patient Name");
+        assert_eq!(name.data_type(Version::V2_5), "XPN");
+        assert_eq!(pid.fields(Version::V2_3).count(), 30);
+        assert_eq!(pid.fields(Version::V2_7).count(), 40);
+
+        for structure in dictionary.messages.events.values().flat_map(|e| e.values()) {
+            assert!(dictionary.structure(structure).is_some(), "{structure}");
+        }
+        for segment in dictionary.segments.values() {
+            for field in segment.fields.iter() {
+                assert!(dictionary.data_types.contains_key(&field.data_type));
+            }
+        }
+        for data_type in dictionary.data_types.values() {
+            for component in data_type.components.iter() {
+                assert!(dictionary.data_types.contains_key(&component.data_type));
+            }
+        }
+    }
+
+    #[test]
+    fn can_override_fields_by_version() {
+        let pid = dictionary().segment("PID").expect("PID is defined");
+        let name = pid.fields(Version::V2_3).nth(4).expect("PID-5 is defined");
+        assert_eq!(name.max_length(Version::V2_3), Some(48));
+        assert_eq!(name.max_length(Version::V2_3_1), Some(48));
+        assert_eq!(name.max_length(Version::V2_5), Some(250));
+
+        let ssn = pid
+            .fields(Version::V2_7)
+            .nth(18)
+            .expect("PID-19 is defined");
+        assert_eq!(
+            ssn.optionality(Version::V2_5),
+            Optionality::BackwardCompatible
+        );
+        assert_eq!(ssn.optionality(Version::V2_7), Optionality::Withdrawn);
+        assert_eq!(ssn.optionality(Version::V2_8), Optionality::Withdrawn);
+    }
+
+    #[test]
+    fn can_find_nearest_version() {
+        assert_eq!(Version::nearest("2.5.1"), Some(Version::V2_5_1));
+        assert_eq!(Version::nearest("2.4.1"), Some(Version::V2_4));
+        assert_eq!(Version::nearest("2.1"), Some(Version::V2_3));
+        assert_eq!(Version::nearest("2.9"), Some(Version::V2_8));
+        assert_eq!(Version::nearest("banana"), None);
+    }
+
+    #[test]
+    fn can_annotate_fields() {
+        let dictionary = dictionary();
+        let name = dictionary
+            .annotate(Version::V2_5_1, "PID", 5, Some(1), None)
+            .expect("PID-5.1 is defined");
+        assert_eq!(name.names, vec!["Patient Name", "Family Name"]);
+        assert_eq!(name.data_types, vec!["XPN", "FN"]);
+        assert_eq!(name.data_type, "FN");
+
+        let sex = dictionary
+            .annotate(Version::V2_5_1, "PID", 8, None, None)
+            .expect("PID-8 is defined");
+        assert_eq!(sex.data_type, "IS");
+        assert_eq!(sex.describe("M"), Some("Male"));
+        assert_eq!(sex.describe("?"), None);
+
+        assert!(dictionary
+            .annotate(Version::V2_5_1, "PID", 500, None, None)
+            .is_none());
+        assert!(dictionary
+            .annotate(Version::V2_5_1, "ZZZ", 1, None, None)
+            .is_none());
+    }
+}
@@ -0,0 +1,358 @@
+use crate::{
+    query::Query,
+    segments::{segment_leaves, segments_in_order, Leaf, SegmentRef},
+};
+use hl7_parser::ParsedMessageOwned;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DifferenceKind {
+    /// The value is only in the first message
+    Removed,
+    /// The value is only in the second message
+    Added,
+    /// The value is different between the messages
+    Changed,
+}
+
+/// A value which differs between two messages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub kind: DifferenceKind,
+    /// The path to the value, as printed in tables. Changed and removed values use the path in
+    /// the first message, and added values use the path in the second message.
+    pub path: Vec<String>,
+    /// The path to a changed value in the second message, if its segment is at a different index
+    /// than in the first message (ex: `OBX[3].5` for a value at `OBX[2].5` in the first message)
+    pub path_b: Option<Vec<String>>,
+    /// The decoded value in the first message
+    pub a: Option<String>,
+    /// The decoded value in the second message
+    pub b: Option<String>,
+}
+
+/// Compare two messages field by field
+///
+/// Segments are lined up between the messages so that segments which are only in one message
+/// (ex: an extra OBX in the middle of a result) are reported as added or removed rather than
+/// shifting every following segment. Repeated segments are paired with the most similar
+/// segment with the same identifier. Values matching any of the `ignore` locations are skipped.
+pub fn diff_messages(
+    a: &ParsedMessageOwned,
+    b: &ParsedMessageOwned,
+    ignore: &[Query],
+) -> Vec<Difference> {
+    let segments_a = segments_in_order(a);
+    let segments_b = segments_in_order(b);
+
+    let mut differences = Vec::new();
+    for pair in align(a, &segments_a, b, &segments_b) {
+        match pair {
+            (Some(i), Some(j)) => diff_segments(
+                (a, &segments_a[i]),
+                (b, &segments_b[j]),
+                ignore,
+                &mut differences,
+            ),
+            (Some(i), None) => only_in_one(
+                a,
+                &segments_a[i],
+                DifferenceKind::Removed,
+                ignore,
+                &mut differences,
+            ),
+            (None, Some(j)) => only_in_one(
+                b,
+                &segments_b[j],
+                DifferenceKind::Added,
+                ignore,
+                &mut differences,
+            ),
+            (None, None) => {}
+        }
+    }
+    differences
+}
+
+fn is_ignored(
+    ignore: &[Query],
+    (message, segment): (&ParsedMessageOwned, &SegmentRef),
+    position: Option<[usize; 4]>,
+) -> bool {
+    ignore
+        .iter()
+        .any(|query| query.contains(&message.source, segment, position))
+}
+
+fn full_path(segment: &SegmentRef, leaf: &Leaf) -> Vec<String> {
+    std::iter::once(segment.path())
+        .chain(leaf.path.iter().cloned())
+        .collect()
+}
+
+fn only_in_one(
+    message: &ParsedMessageOwned,
+    segment: &SegmentRef,
+    kind: DifferenceKind,
+    ignore: &[Query],
+    differences: &mut Vec<Difference>,
+) {
+    if is_ignored(ignore, (message, segment), None) {
+        return;
+    }
+    let leaves = segment_leaves(&message.source, segment);
+    if leaves.is_empty() {
+        // still report segments without any values
+        differences.push(Difference {
+            kind,
+            path: vec![segment.path()],
+            path_b: None,
+            a: None,
+            b: None,
+        });
+    }
+    for leaf in leaves {
+        if is_ignored(ignore, (message, segment), Some(leaf.position)) {
+            continue;
+        }
+        let value = Some(message.separators.decode(leaf.value));
+        let (a, b) = match kind {
+            DifferenceKind::Removed => (value, None),
+            _ => (None, value),
+        };
+        differences.push(Difference {
+            kind,
+            path: full_path(segment, &leaf),
+            path_b: None,
+            a,
+            b,
+        });
+    }
+}
+
+fn diff_segments(
+    (message_a, segment_a): (&ParsedMessageOwned, &SegmentRef),
+    (message_b, segment_b): (&ParsedMessageOwned, &SegmentRef),
+    ignore: &[Query],
+    differences: &mut Vec<Difference>,
+) {
+    if is_ignored(ignore, (message_a, segment_a), None) {
+        return;
+    }
+    let leaves_a = segment_leaves(&message_a.source, segment_a);
+    let leaves_b = segment_leaves(&message_b.source, segment_b);
+
+    // both lists are in position order, so walk them together
+    let (mut i, mut j) = (0, 0);
+    while i < leaves_a.len() || j < leaves_b.len() {
+        let a = leaves_a.get(i);
+        let b = leaves_b.get(j);
+        let difference = match (a, b) {
+            (Some(a), Some(b)) if a.position == b.position => {
+                i += 1;
+                j += 1;
+                let value_a = message_a.separators.decode(a.value);
+                let value_b = message_b.separators.decode(b.value);
+                if value_a == value_b
+                    || is_ignored(ignore, (message_a, segment_a), Some(a.position))
+                {
+                    continue;
+                }
+                // use the more specific of the paths (ex: `PID.5.1` rather than `PID.5` if
+                // only one of the names has components)
+                let leaf = if b.path.len() > a.path.len() { b } else { a };
+                Difference {
+                    kind: DifferenceKind::Changed,
+                    path: full_path(segment_a, leaf),
+                    path_b: (segment_a.index != segment_b.index)
+                        .then(|| full_path(segment_b, leaf)),
+                    a: Some(value_a),
+                    b: Some(value_b),
+                }
+            }
+            (Some(a), Some(b)) if a.position < b.position => {
+                i += 1;
+                removed(message_a, segment_a, a)
+            }
+            (Some(a), None) => {
+                i += 1;
+                removed(message_a, segment_a, a)
+            }
+            (_, Some(b)) => {
+                j += 1;
+                Difference {
+                    kind: DifferenceKind::Added,
+                    path: full_path(segment_b, b),
+                    path_b: None,
+                    a: None,
+                    b: Some(message_b.separators.decode(b.value)),
+                }
+            }
+            (None, None) => unreachable!("loop ends when both lists are exhausted"),
+        };
+        let ignored = match difference.kind {
+            DifferenceKind::Added => {
+                is_ignored(ignore, (message_b, segment_b), b.map(|b| b.position))
+            }
+            _ => is_ignored(ignore, (message_a, segment_a), a.map(|a| a.position)),
+        };
+        if !ignored {
+            differences.push(difference);
+        }
+    }
+}
+
+fn removed(message: &ParsedMessageOwned, segment: &SegmentRef, leaf: &Leaf) -> Difference {
+    Difference {
+        kind: DifferenceKind::Removed,
+        path: full_path(segment, leaf),
+        path_b: None,
+        a: Some(message.separators.decode(leaf.value)),
+        b: None,
+    }
+}
+
+/// How alike two segments with the same identifier are, from 1 (no fields in common) to 2
+/// (identical)
+fn similarity(
+    (message_a, segment_a): (&ParsedMessageOwned, &SegmentRef),
+    (message_b, segment_b): (&ParsedMessageOwned, &SegmentRef),
+) -> f64 {
+    let fields_a = &segment_a.segment.fields;
+    let fields_b = &segment_b.segment.fields;
+    let count = fields_a.len().max(fields_b.len());
+    if count == 0 {
+        return 2.0;
+    }
+    let same = fields_a
+        .iter()
+        .zip(fields_b.iter())
+        .filter(|(a, b)| a.source(&message_a.source) == b.source(&message_b.source))
+        .count();
+    1.0 + same as f64 / count as f64
+}
+
+/// Line up the segments of two messages, pairing segments with the same identifier so that the
+/// total similarity of the pairs is as high as possible (keeping the segments in order)
+fn align(
+    a: &ParsedMessageOwned,
+    segments_a: &[SegmentRef],
+    b: &ParsedMessageOwned,
+    segments_b: &[SegmentRef],
+) -> Vec<(Option<usize>, Option<usize>)> {
+    let (n, m) = (segments_a.len(), segments_b.len());
+    let mut scores = vec![vec![0.0f64; m + 1]; n + 1];
+    for i in 1..=n {
+        for j in 1..=m {
+            let mut score = scores[i - 1][j].max(scores[i][j - 1]);
+            if segments_a[i - 1].id == segments_b[j - 1].id {
+                let pair = similarity((a, &segments_a[i - 1]), (b, &segments_b[j - 1]));
+                score = score.max(scores[i - 1][j - 1] + pair);
+            }
+            scores[i][j] = score;
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (n, m);
+    while i > 0 || j > 0 {
+        if i > 0 && j > 0 && segments_a[i - 1].id == segments_b[j - 1].id {
+            let pair = similarity((a, &segments_a[i - 1]), (b, &segments_b[j - 1]));
+            if scores[i][j] == scores[i - 1][j - 1] + pair {
+                pairs.push((Some(i - 1), Some(j - 1)));
+                i -= 1;
+                j -= 1;
+                continue;
+            }
+        }
+        if i > 0 && (j == 0 || scores[i][j] == scores[i - 1][j]) {
+            pairs.push((Some(i - 1), None));
+            i -= 1;
+        } else {
+            pairs.push((None, Some(j - 1)));
+            j -= 1;
+        }
+    }
+    pairs.reverse();
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(a: &str, b: &str, ignore: &[&str]) -> Vec<(DifferenceKind, String)> {
+        let a = ParsedMessageOwned::parse(a, false).expect("can parse message");
+        let b = ParsedMessageOwned::parse(b, false).expect("can parse message");
+        let ignore: Vec<Query> = ignore
+            .iter()
+            .map(|q| Query::parse_location(q).expect("valid query"))
+            .collect();
+        diff_messages(&a, &b, &ignore)
+            .into_iter()
+            .map(|d| match d.path_b {
+                Some(path_b) => (
+                    d.kind,
+                    format!("{} -> {}", d.path.join("."), path_b.join(".")),
+                ),
+                None => (d.kind, d.path.join(".")),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn can_diff_fields() {
+        let a = "MSH|^~\\&|A|1|||20050110045504||ADT^A01|1|P|2.5.1\rPID|1||123||DUCK^DONALD|||M";
+        let b = "MSH|^~\\&|A|1|||20050110045505||ADT^A01|2|P|2.5.1\rPID|1||123||DUCK|||F||x";
+        assert_eq!(
+            diff(a, b, &["MSH-7", "MSH.10"]),
+            vec![
+                (DifferenceKind::Removed, "PID.5.2".to_string()),
+                (DifferenceKind::Changed, "PID.8".to_string()),
+                (DifferenceKind::Added, "PID.10".to_string()),
+            ]
+        );
+        assert_eq!(diff(a, a, &[]), vec![]);
+    }
+
+    #[test]
+    fn can_match_repeated_segments() {
+        let a = "MSH|^~\\&|A\rOBX|1|NM|A||1\rOBX|2|NM|B||2\rNTE|1||x";
+        let b = "MSH|^~\\&|A\rOBX|1|NM|A||1\rOBX|2|NM|C||3\rOBX|3|NM|B||2";
+        assert_eq!(
+            diff(a, b, &[]),
+            vec![
+                (DifferenceKind::Added, "OBX[2].1".to_string()),
+                (DifferenceKind::Added, "OBX[2].2".to_string()),
+                (DifferenceKind::Added, "OBX[2].3".to_string()),
+                (DifferenceKind::Added, "OBX[2].5".to_string()),
+                (DifferenceKind::Changed, "OBX[2].1 -> OBX[3].1".to_string()),
+                (DifferenceKind::Removed, "NTE.1".to_string()),
+                (DifferenceKind::Removed, "NTE.3".to_string()),
+            ]
+        );
+        assert_eq!(
+            diff(a, b, &["NTE", "OBX.1"]),
+            vec![
+                (DifferenceKind::Added, "OBX[2].2".to_string()),
+                (DifferenceKind::Added, "OBX[2].3".to_string()),
+                (DifferenceKind::Added, "OBX[2].5".to_string()),
+            ]
+        );
+        assert_eq!(
+            diff(a, b, &["NTE", "OBX[3=C]"]),
+            vec![(DifferenceKind::Changed, "OBX[2].1 -> OBX[3].1".to_string())]
+        );
+    }
+
+    #[test]
+    fn compares_decoded_values() {
+        let a = ParsedMessageOwned::parse("MSH|^~\\&|A\rPID|1||A\\T\\B|x", false)
+            .expect("can parse message");
+        let b = ParsedMessageOwned::parse("MSH|^~\\&|A\rPID|1||A\\S\\B|x", false)
+            .expect("can parse message");
+        let differences = diff_messages(&a, &b, &[]);
+        assert_eq!(differences.len(), 1, "{differences:?}");
+        assert_eq!(differences[0].a.as_deref(), Some("A&B"));
+        assert_eq!(differences[0].b.as_deref(), Some("A^B"));
+    }
+}
@@ -0,0 +1,355 @@
+use crate::query::Query;
+use hl7_parser::{ParsedMessageOwned, Separators};
+use nom::{
+    branch::alt,
+    bytes::complete::{escaped_transform, is_not, tag, take_while1},
+    character::complete::{alpha1, char, digit1, multispace0},
+    combinator::{all_consuming, cut, map, map_res, value, verify},
+    error::{context, VerboseError},
+    multi::{many0, separated_list1},
+    sequence::{delimited, preceded, terminated},
+    Finish, IResult,
+};
+use std::str::FromStr;
+
+type ParseResult<'s, T> = IResult<&'s str, T, VerboseError<&'s str>>;
+
+/// A value built from literal text and `{expression}`s
+///
+/// Expressions can be:
+///
+/// * a field reference (ex: `{MSH-3}`, `{PID-3.1}`)
+/// * a string literal (ex: `{"text"}`) or a number (ex: `{8}`)
+/// * a function call (ex: `{upper(PID-5.1)}`):
+///   * `upper(value)` / `lower(value)`: change the case of a value
+///   * `substr(value, start[, length])`: take part of a value (`start` is 0-based)
+///   * `default(value, fallback, ...)`: the first of the values which isn't empty
+///   * `concat(value, ...)`: join the values together
+///
+/// Literal braces can be written as `{{` and `}}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Expression(Expression),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expression {
+    Reference(Query),
+    Literal(String),
+    Upper(Box<Expression>),
+    Lower(Box<Expression>),
+    Substring {
+        value: Box<Expression>,
+        start: Box<Expression>,
+        length: Option<Box<Expression>>,
+    },
+    Default(Vec<Expression>),
+    Concat(Vec<Expression>),
+}
+
+impl Template {
+    /// Whether the template contains any expressions, rather than only literal text
+    pub fn has_expressions(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Expression(_)))
+    }
+
+    /// Evaluate the template against a message
+    ///
+    /// Field references are copied as they appear in the message (so any components or escape
+    /// sequences are kept), and references to missing fields evaluate to an empty string
+    pub fn evaluate(&self, message: &ParsedMessageOwned) -> Result<String, String> {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => Ok(literal.clone()),
+                Part::Expression(expression) => expression.evaluate(message),
+            })
+            .collect()
+    }
+}
+
+impl Expression {
+    fn evaluate(&self, message: &ParsedMessageOwned) -> Result<String, String> {
+        Ok(match self {
+            Expression::Reference(query) => query.value(message).unwrap_or_default().to_string(),
+            Expression::Literal(literal) => literal.clone(),
+            Expression::Upper(value) => {
+                change_case(&value.evaluate(message)?, &message.separators, true)
+            }
+            Expression::Lower(value) => {
+                change_case(&value.evaluate(message)?, &message.separators, false)
+            }
+            Expression::Substring {
+                value,
+                start,
+                length,
+            } => {
+                let value = value.evaluate(message)?;
+                let start = parse_count(&start.evaluate(message)?, "start")?;
+                let chars = value.chars().skip(start);
+                match length {
+                    Some(length) => {
+                        let length = parse_count(&length.evaluate(message)?, "length")?;
+                        chars.take(length).collect()
+                    }
+                    None => chars.collect(),
+                }
+            }
+            Expression::Default(values) => {
+                for value in values {
+                    let value = value.evaluate(message)?;
+                    if !value.is_empty() {
+                        return Ok(value);
+                    }
+                }
+                String::new()
+            }
+            Expression::Concat(values) => values
+                .iter()
+                .map(|v| v.evaluate(message))
+                .collect::<Result<String, String>>()?,
+        })
+    }
+}
+
+/// Parse a `substr` start or length, which may come from a field
+fn parse_count(value: &str, name: &str) -> Result<usize, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("substr {name} must be a number, not {value:?}"))
+}
+
+/// Change the case of a value, leaving escape sequences (ex: `\F\`) alone
+fn change_case(value: &str, separators: &Separators, upper: bool) -> String {
+    let mut in_escape = false;
+    value
+        .chars()
+        .map(|c| {
+            if c == separators.escape {
+                in_escape = !in_escape;
+                c
+            } else if in_escape {
+                c
+            } else if upper {
+                c.to_ascii_uppercase()
+            } else {
+                c.to_ascii_lowercase()
+            }
+        })
+        .collect()
+}
+
+fn parse_reference(s: &str) -> ParseResult<'_, Expression> {
+    context(
+        "field reference",
+        map_res(
+            take_while1(|c: char| c.is_ascii_alphanumeric() || "-.[]".contains(c)),
+            |reference: &str| {
+                if !reference.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                    return Err(format!("invalid field reference: {reference}"));
+                }
+                let query = Query::from_str(reference)?;
+                if !query.is_single() {
+                    return Err(format!(
+                        "field references must select a single value: {reference}"
+                    ));
+                }
+                Ok(Expression::Reference(query))
+            },
+        ),
+    )(s)
+}
+
+fn parse_string(s: &str) -> ParseResult<'_, Expression> {
+    context(
+        "string",
+        map(
+            delimited(
+                char('"'),
+                alt((
+                    escaped_transform(
+                        is_not("\\\""),
+                        '\\',
+                        alt((value("\\", tag("\\")), value("\"", tag("\"")))),
+                    ),
+                    map(tag(""), |_| String::new()),
+                )),
+                char('"'),
+            ),
+            Expression::Literal,
+        ),
+    )(s)
+}
+
+fn parse_number(s: &str) -> ParseResult<'_, Expression> {
+    map(digit1, |n: &str| Expression::Literal(n.to_string()))(s)
+}
+
+fn parse_call(s: &str) -> ParseResult<'_, Expression> {
+    let (s, name) = terminated(alpha1, preceded(multispace0, char('(')))(s)?;
+    let (s, args) = cut(terminated(
+        separated_list1(
+            char(','),
+            delimited(multispace0, parse_expression, multispace0),
+        ),
+        char(')'),
+    ))(s)?;
+
+    let arity_error = |expected: &'static str| {
+        nom::Err::Failure(VerboseError {
+            errors: vec![(
+                s,
+                nom::error::VerboseErrorKind::Context(if expected.is_empty() {
+                    "unknown function"
+                } else {
+                    expected
+                }),
+            )],
+        })
+    };
+
+    let mut args = args.into_iter();
+    let expression = match (name, args.len()) {
+        ("upper", 1) => Expression::Upper(Box::new(args.next().expect("1 argument"))),
+        ("lower", 1) => Expression::Lower(Box::new(args.next().expect("1 argument"))),
+        ("upper" | "lower", _) => return Err(arity_error("function takes 1 argument")),
+        ("substr", 2 | 3) => Expression::Substring {
+            value: Box::new(args.next().expect("value argument")),
+            start: Box::new(args.next().expect("start argument")),
+            length: args.next().map(Box::new),
+        },
+        ("substr", _) => return Err(arity_error("function takes 2 or 3 arguments")),
+        ("default", 2..) => Expression::Default(args.collect()),
+        ("default", _) => return Err(arity_error("function takes at least 2 arguments")),
+        ("concat", _) => Expression::Concat(args.collect()),
+        _ => return Err(arity_error("")),
+    };
+    Ok((s, expression))
+}
+
+fn parse_expression(s: &str) -> ParseResult<'_, Expression> {
+    alt((parse_string, parse_number, parse_call, parse_reference))(s)
+}
+
+fn parse_literal(s: &str) -> ParseResult<'_, Part> {
+    map(
+        many0(alt((
+            map(is_not("{}"), |s: &str| s.to_string()),
+            value("{".to_string(), tag("{{")),
+            value("}".to_string(), tag("}}")),
+        ))),
+        |parts| Part::Literal(parts.concat()),
+    )(s)
+}
+
+fn parse_part(s: &str) -> ParseResult<'_, Part> {
+    alt((
+        verify(parse_literal, |part| part != &Part::Literal(String::new())),
+        map(
+            delimited(
+                terminated(char('{'), multispace0),
+                cut(parse_expression),
+                cut(preceded(multispace0, char('}'))),
+            ),
+            Part::Expression,
+        ),
+    ))(s)
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match all_consuming(many0(parse_part))(s).finish() {
+            Ok((_, parts)) => Ok(Template {
+                source: s.to_string(),
+                parts,
+            }),
+            Err(err) => Err(format!(
+                "invalid expression {s:?}:\n{}",
+                nom::error::convert_error(s, err)
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> ParsedMessageOwned {
+        ParsedMessageOwned::parse(
+            "MSH|^~\\&|AccMgr|1|Lab|2|20050110045504||ADT^A01|599102|P|2.3\rPID|1||10006579^^^1^MR^1||Duck\\T\\Sons^Donald",
+            false,
+        )
+        .expect("can parse message")
+    }
+
+    fn evaluate(template: &str) -> String {
+        let template = Template::from_str(template).expect("can parse template");
+        template
+            .evaluate(&message())
+            .expect("can evaluate template")
+    }
+
+    #[test]
+    fn can_reference_fields() {
+        assert_eq!(evaluate("{MSH-3}"), "AccMgr");
+        assert_eq!(evaluate("{PID-3.1}-{MSH-7}"), "10006579-20050110045504");
+        assert_eq!(evaluate("{MSH.9}"), "ADT^A01");
+        assert_eq!(evaluate("x{PID-19}x"), "xx");
+        assert_eq!(evaluate("{{literal}}"), "{literal}");
+    }
+
+    #[test]
+    fn can_call_functions() {
+        assert_eq!(evaluate("{upper(PID-5.1)}"), "DUCK\\T\\SONS");
+        assert_eq!(evaluate("{lower(PID-5)}"), "duck\\T\\sons^donald");
+        assert_eq!(evaluate("{substr(MSH-7, 0, 8)}"), "20050110");
+        assert_eq!(evaluate("{substr(MSH-7, 8)}"), "045504");
+        assert_eq!(evaluate(r#"{default(PID-19, "NONE")}"#), "NONE");
+        assert_eq!(evaluate(r#"{default(PID-19, MSH-3, "NONE")}"#), "AccMgr");
+        assert_eq!(
+            evaluate(r#"{concat(MSH-5, "/", lower(MSH-3))}"#),
+            "Lab/accmgr"
+        );
+        assert_eq!(evaluate(r#"{ upper( "a \"b\"" ) }"#), "A \"B\"");
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert!(Template::from_str("{MSH-3").is_err());
+        assert!(Template::from_str("{upper(MSH-3, MSH-4)}").is_err());
+        assert!(Template::from_str("{nope(MSH-3)}").is_err());
+        assert!(Template::from_str("{}").is_err());
+        assert!(Template::from_str("a}b").is_err());
+    }
+
+    #[test]
+    fn rejects_non_numeric_substr_arguments() {
+        let template = Template::from_str("{substr(MSH-7, MSH-5)}").expect("can parse template");
+        assert_eq!(
+            template.evaluate(&message()),
+            Err("substr start must be a number, not \"Lab\"".to_string())
+        );
+        let template =
+            Template::from_str(r#"{substr(MSH-7, 0, "x")}"#).expect("can parse template");
+        assert!(template.evaluate(&message()).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use crate::query::Query;
+pub use hl7_common::filter::{parse_test, FilterTest};
+use hl7_parser::ParsedMessageOwned;
+use std::str::FromStr;
+
+/// A test of the values a query selects in a message, used to pick out messages with
+/// `hq filter --where` and to only apply script maps to some messages with `when`
+///
+/// Formats: `query=value`, `query!=value`, `query~pattern`, `query!~pattern`, or `query` (the
+/// value is present), where patterns are regular expressions. The filters in query selectors
+/// (ex: `OBX[3.1~^2345].5`) use the same operators.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    pub query: Query,
+    pub test: FilterTest,
+}
+
+impl Filter {
+    /// Whether the filter holds for the message. Missing values are treated as empty, and
+    /// queries which select many values (ex: `PID.3[*].1`) hold if any of the values match (or,
+    /// for `!=` and `!~`, if none of them do).
+    pub fn matches(&self, message: &ParsedMessageOwned) -> bool {
+        self.test.matches(&self.query.values(message))
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (query, test) = parse_test(s)?;
+        Ok(Filter {
+            query: query.parse()?,
+            test,
+        })
+    }
+}
+
+impl std::fmt::Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.query, self.test)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_filters() {
+        let filter = |s: &str| s.parse::<Filter>().expect("valid filter").to_string();
+        assert_eq!(filter("MSH-9.2=A08"), "MSH.9[1].2=A08");
+        assert_eq!(filter("PID-3.1~^12"), "PID.3[1].1~^12");
+        assert_eq!(filter("PID.19!="), "PID.19[1]!=");
+        assert_eq!(
+            filter("OBX[3.1=2345-7].5!~^[0-9]"),
+            "OBX[3.1=2345-7].5[1]!~^[0-9]"
+        );
+        assert_eq!(filter("PID.3[5=MR].1"), "PID.3[5=MR].1");
+        assert!("PID.3~(".parse::<Filter>().is_err());
+        assert!("PID.x=1".parse::<Filter>().is_err());
+        assert!("=I".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn can_filter_messages() {
+        let message = ParsedMessageOwned::parse(
+            "MSH|^~\\&|A|1|||20050110045504||ADT^A08|1|P|2.5.1\rPID|1||123^^^A^MR~456^^^B^SS||DUCK^DONALD",
+            false,
+        )
+        .expect("can parse message");
+        let matches = |s: &str| s.parse::<Filter>().expect("valid filter").matches(&message);
+        assert!(matches("MSH-9.2=A08"));
+        assert!(!matches("MSH-9.2=A01"));
+        assert!(matches("MSH-9.2!=A01"));
+        assert!(matches("PID-3.1~^12"));
+        assert!(!matches("PID-3.1~^45"));
+        assert!(matches("PID.3[*].1~^45"));
+        assert!(matches("PID.3[5=SS].1=456"));
+        assert!(matches("PID.5"));
+        assert!(!matches("PID.19"));
+        assert!(matches("PID.19="));
+        assert!(matches("PID.5.1!~^M"));
+    }
+}
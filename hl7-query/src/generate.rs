@@ -0,0 +1,672 @@
+use crate::dictionary::{dictionary, message_version, Optionality, StructureItem, Version};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime};
+use hl7_parser::{LocationQuery, ParsedMessageOwned, Segment, Separators};
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    seq::SliceRandom,
+    Rng,
+};
+use std::{collections::HashMap, num::NonZeroUsize};
+
+const FAMILY_NAMES: &[&str] = &[
+    "SMITH", "JOHNSON", "WILLIAMS", "BROWN", "JONES", "GARCIA", "MILLER", "DAVIS", "MARTIN", "LEE",
+    "WALKER", "HALL", "YOUNG", "KING", "WRIGHT", "LOPEZ", "HILL", "SCOTT", "GREEN",
+];
+const GIVEN_NAMES: &[&str] = &[
+    "JAMES",
+    "MARY",
+    "JOHN",
+    "PATRICIA",
+    "ROBERT",
+    "JENNIFER",
+    "MICHAEL",
+    "LINDA",
+    "DAVID",
+    "ELIZABETH",
+    "WILLIAM",
+    "SUSAN",
+    "RICHARD",
+    "JESSICA",
+    "JOSEPH",
+    "SARAH",
+    "THOMAS",
+    "KAREN",
+];
+const STREETS: &[&str] = &[
+    "MAIN ST", "OAK AVE", "PINE ST", "MAPLE DR", "CEDAR LN", "ELM ST", "LAKE RD", "HILL ST",
+];
+const CITIES: &[(&str, &str, &str)] = &[
+    ("SPRINGFIELD", "IL", "62701"),
+    ("MADISON", "WI", "53703"),
+    ("FRANKLIN", "TN", "37064"),
+    ("SALEM", "OR", "97301"),
+    ("GEORGETOWN", "TX", "78626"),
+    ("CLINTON", "IA", "52732"),
+];
+
+/// Everything known about the piece of the message a value is being generated for
+#[derive(Debug, Copy, Clone)]
+struct Target<'d> {
+    version: Version,
+    data_type: &'d str,
+    table: Option<&'d str>,
+    /// The name of the field or component, used to pick sensible values (ex: a birth date
+    /// should be in the past)
+    name: &'d str,
+    /// The maximum length of the field (components don't have their own maximum lengths)
+    max_length: Option<usize>,
+}
+
+/// Generate an appropriate value for the location in the message, based on the data type of
+/// the field (or component or sub-component) in the dictionary
+///
+/// Composite values are joined using the message's separators. If the location isn't in the
+/// dictionary, a short random string is generated instead.
+pub fn generate_value(message: &ParsedMessageOwned, location: &LocationQuery) -> String {
+    generate_value_with(&mut rand::thread_rng(), message, location)
+}
+
+/// Generate a value for the location as [`generate_value`] does, using the given random number
+/// generator (so that the values can be reproduced from a seed)
+pub fn generate_value_with<R: Rng>(
+    rng: &mut R,
+    message: &ParsedMessageOwned,
+    location: &LocationQuery,
+) -> String {
+    generate_value_at(rng, Local::now().naive_local(), message, location)
+}
+
+/// Generate a value for the location as [`generate_value_with`] does, with dates and times
+/// (such as the message time or a birth date) relative to `now` rather than the current time
+pub fn generate_value_at<R: Rng>(
+    rng: &mut R,
+    now: NaiveDateTime,
+    message: &ParsedMessageOwned,
+    location: &LocationQuery,
+) -> String {
+    if location.segment == "MSH" && location.component.is_none() {
+        match location.field.map(|f| f.get()) {
+            // message time
+            Some(7) => return timestamp(now),
+            // control ID
+            Some(10) => return Alphanumeric.sample_string(rng, 20),
+            _ => {}
+        }
+    }
+
+    let version = message_version(message).unwrap_or(Version::V2_5_1);
+    // composite values are joined by component or sub-component separators depending on how
+    // deep into the field the location is
+    let depth = [location.component, location.sub_component]
+        .iter()
+        .flatten()
+        .count();
+    let Some(target) = target(version, location) else {
+        return Alphanumeric.sample_string(rng, 8);
+    };
+    let value = generate(rng, now, &message.separators, target, depth);
+    // random codes can be longer than short fields allow, which can only be fixed for values
+    // that aren't made up of components
+    match target.max_length {
+        Some(max_length) if !value.contains(message.separators.component) => {
+            value.chars().take(max_length).collect()
+        }
+        _ => value,
+    }
+}
+
+/// The data type of the location in the message's version of the dictionary (descending into
+/// components and sub-components)
+pub fn data_type_at(
+    message: &ParsedMessageOwned,
+    location: &LocationQuery,
+) -> Option<&'static str> {
+    let version = message_version(message).unwrap_or(Version::V2_5_1);
+    target(version, location).map(|target| target.data_type)
+}
+
+/// Find the data type of the location being generated, descending into components and
+/// sub-components of composite data types
+fn target(version: Version, location: &LocationQuery) -> Option<Target<'static>> {
+    let dictionary = dictionary();
+    let field = location.field?.get();
+    let field = dictionary
+        .segment(&location.segment)?
+        .fields(version)
+        .nth(field - 1)?;
+    let mut target = Target {
+        version,
+        data_type: field.data_type(version),
+        table: field.table.as_deref(),
+        name: &field.name,
+        max_length: field.max_length(version),
+    };
+
+    for index in [location.component, location.sub_component]
+        .into_iter()
+        .flatten()
+    {
+        let component = dictionary
+            .data_type(version, target.data_type)?
+            .components
+            .get(index.get() - 1)?;
+        target = Target {
+            version,
+            data_type: version.data_type(&component.data_type),
+            table: component.table.as_deref(),
+            name: &component.name,
+            max_length: None,
+        };
+    }
+    Some(target)
+}
+
+fn timestamp(time: NaiveDateTime) -> String {
+    time.format("%Y%m%d%H%M%S").to_string()
+}
+
+fn birth_date<R: Rng>(rng: &mut R, now: NaiveDateTime) -> NaiveDate {
+    let today = now.date();
+    let age_in_days = rng.gen_range(0..(365 * 95));
+    today - Duration::days(age_in_days)
+}
+
+fn digits<R: Rng>(rng: &mut R, count: usize) -> String {
+    (0..count)
+        .map(|_| char::from(b'0' + rng.gen_range(0..10)))
+        .collect()
+}
+
+fn pick<'a, R: Rng>(rng: &mut R, values: &[&'a str]) -> &'a str {
+    values.choose(rng).copied().unwrap_or_default()
+}
+
+/// Generate a value for a data type. `depth` is 0 for a field, 1 for a component, and 2 for a
+/// sub-component, and decides which separator joins the parts of composite values.
+fn generate<R: Rng>(
+    rng: &mut R,
+    now: NaiveDateTime,
+    separators: &Separators,
+    target: Target,
+    depth: usize,
+) -> String {
+    let is_birth = target.name.contains("Birth");
+
+    let parts: Vec<String> = match target.data_type {
+        "DTM" | "TS" if is_birth => vec![birth_date(rng, now).format("%Y%m%d").to_string()],
+        "DTM" | "TS" => vec![timestamp(now)],
+        "DT" if is_birth => vec![birth_date(rng, now).format("%Y%m%d").to_string()],
+        "DT" => vec![now.format("%Y%m%d").to_string()],
+        "TM" => vec![now.format("%H%M%S").to_string()],
+        "NM" => vec![rng.gen_range(1..1000).to_string()],
+        "SI" => vec!["1".to_string()],
+        "ID" | "IS" => vec![table_code(rng, target.table)
+            .unwrap_or_else(|| Alphanumeric.sample_string(rng, 3).to_uppercase())],
+        "XPN" => vec![
+            pick(rng, FAMILY_NAMES).to_string(),
+            pick(rng, GIVEN_NAMES).to_string(),
+            pick(rng, GIVEN_NAMES)[..1].to_string(),
+        ],
+        "XCN" => vec![
+            digits(rng, 6),
+            pick(rng, FAMILY_NAMES).to_string(),
+            pick(rng, GIVEN_NAMES).to_string(),
+        ],
+        "XAD" => {
+            let (city, state, zip) = *CITIES.choose(rng).expect("cities aren't empty");
+            vec![
+                format!("{} {}", rng.gen_range(1..9999), pick(rng, STREETS)),
+                String::new(),
+                city.to_string(),
+                state.to_string(),
+                zip.to_string(),
+            ]
+        }
+        "XTN" => vec![format!("555{}", digits(rng, 7))],
+        "CX" => vec![
+            digits(rng, 8),
+            String::new(),
+            String::new(),
+            "HOSP".to_string(),
+            "MR".to_string(),
+        ],
+        "CE" | "CWE" | "CNE" => match target.table.and_then(|t| table_value(rng, t)) {
+            Some((code, description)) => vec![
+                code.to_string(),
+                description.to_string(),
+                format!("HL7{}", target.table.expect("table value has a table")),
+            ],
+            None => vec![Alphanumeric.sample_string(rng, 5).to_uppercase()],
+        },
+        "PL" => vec![
+            format!("WARD{}", rng.gen_range(1..10)),
+            digits(rng, 3),
+            rng.gen_range(1..5).to_string(),
+        ],
+        "HD" | "EI" => vec![Alphanumeric.sample_string(rng, 10).to_uppercase()],
+        data_type => {
+            // for any other composite, fill in the first component
+            let first = dictionary()
+                .data_type(target.version, data_type)
+                .and_then(|d| d.components.first());
+            match first {
+                Some(first) => {
+                    let component = Target {
+                        version: target.version,
+                        data_type: target.version.data_type(&first.data_type),
+                        table: first.table.as_deref(),
+                        name: &first.name,
+                        max_length: None,
+                    };
+                    vec![generate(rng, now, separators, component, depth + 1)]
+                }
+                _ => vec![Alphanumeric.sample_string(rng, 8)],
+            }
+        }
+    };
+
+    match depth {
+        0 => parts.join(&separators.component.to_string()),
+        1 => parts.join(&separators.subcomponent.to_string()),
+        _ => parts.into_iter().next().unwrap_or_default(),
+    }
+}
+
+fn table_value<R: Rng>(rng: &mut R, table: &str) -> Option<(&'static str, &'static str)> {
+    dictionary()
+        .table(table)
+        .and_then(|t| t.values.choose(rng))
+        .map(|v| (v.code.as_str(), v.description.as_str()))
+}
+
+fn table_code<R: Rng>(rng: &mut R, table: Option<&str>) -> Option<String> {
+    table
+        .and_then(|t| table_value(rng, t))
+        .map(|(code, _)| code.to_string())
+}
+
+/// The chance of including an optional segment (or group), or filling in an optional field,
+/// when generating a message
+const OPTIONAL_CHANCE: f64 = 1.0 / 3.0;
+
+/// Pick the segments of a message from its structure, including every required segment and
+/// group, a random selection of the optional ones, and any segments in the template
+fn choose_segments<R: Rng>(
+    rng: &mut R,
+    version: Version,
+    items: &'static [StructureItem],
+    template: Option<&ParsedMessageOwned>,
+    segments: &mut Vec<&'static str>,
+) {
+    for item in items.iter().filter(|item| item.defined_in(version)) {
+        let in_template = match item {
+            StructureItem::Segment { segment, .. } => {
+                template.map_or(0, |template| template.segment_count(segment))
+            }
+            StructureItem::Group { .. } => 0,
+        };
+        let known = match item {
+            StructureItem::Segment { segment, .. } => dictionary().segment(segment).is_some(),
+            StructureItem::Group { .. } => true,
+        };
+        let include =
+            item.required() || in_template > 0 || (known && rng.gen_bool(OPTIONAL_CHANCE));
+        if !include {
+            continue;
+        }
+
+        let count = if item.repeatable() {
+            rng.gen_range(1..=2).max(in_template)
+        } else {
+            1
+        };
+        for _ in 0..count {
+            match item {
+                StructureItem::Segment { segment, .. } => segments.push(segment),
+                StructureItem::Group { items, .. } => {
+                    choose_segments(rng, version, items, template, segments)
+                }
+            }
+        }
+    }
+}
+
+/// Re-encode a value from a template message using the generated message's separators, so that
+/// (ex:) a template using `@` as its component separator has its components kept apart, and a
+/// literal `^` in it is escaped
+fn reencode(value: &str, from: &Separators) -> String {
+    let to = Separators::default();
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        let c = if c == from.component {
+            to.component
+        } else if c == from.repeat {
+            to.repeat
+        } else if c == from.subcomponent {
+            to.subcomponent
+        } else if c == from.escape {
+            to.escape
+        } else {
+            let sequence = if c == to.field {
+                "F"
+            } else if c == to.component {
+                "S"
+            } else if c == to.repeat {
+                "R"
+            } else if c == to.subcomponent {
+                "T"
+            } else if c == to.escape {
+                "E"
+            } else {
+                out.push(c);
+                continue;
+            };
+            out.push(to.escape);
+            out.push_str(sequence);
+            to.escape
+        };
+        out.push(c);
+    }
+    out
+}
+
+/// Generate the fields of a segment (starting from MSH-2 for the header), filling in every
+/// required field and a random selection of the optional ones. `set_id` is the 1-based
+/// occurrence of the segment, used for its Set ID field.
+fn generate_segment<R: Rng>(
+    rng: &mut R,
+    now: NaiveDateTime,
+    header: &ParsedMessageOwned,
+    id: &str,
+    set_id: usize,
+    template: Option<(&ParsedMessageOwned, &Segment)>,
+) -> Vec<String> {
+    let version = message_version(header).unwrap_or(Version::V2_5_1);
+    let definitions: Vec<_> = dictionary()
+        .segment(id)
+        .map(|segment| segment.fields(version).collect())
+        .unwrap_or_default();
+    let template_fields = template.map_or(0, |(_, segment)| segment.fields.len());
+
+    // MSH-1 is the field separator itself, so the header's fields start from MSH-2
+    let first = if id == "MSH" { 1 } else { 0 };
+    let mut fields: Vec<String> = Vec::new();
+    for i in first..definitions.len().max(template_fields) {
+        let field_n = NonZeroUsize::new(i + 1).expect("field numbers are 1-based");
+        let template_value = template
+            .and_then(|(template, segment)| {
+                segment
+                    .field(field_n)
+                    .map(|field| (template, field.source(&template.source)))
+            })
+            .filter(|(_, value)| !value.is_empty());
+        if let Some((template, value)) = template_value {
+            fields.push(reencode(value, &template.separators));
+            continue;
+        }
+
+        let Some(definition) = definitions.get(i) else {
+            fields.push(String::new());
+            continue;
+        };
+        let fill = match definition.optionality(version) {
+            Optionality::Required => true,
+            Optionality::Optional => rng.gen_bool(OPTIONAL_CHANCE),
+            _ => false,
+        };
+        let value = if !fill {
+            String::new()
+        } else if definition.data_type(version) == "SI" {
+            set_id.to_string()
+        } else {
+            let location = LocationQuery {
+                segment: id.to_string(),
+                field: Some(field_n),
+                repeat: None,
+                component: None,
+                sub_component: None,
+            };
+            generate_value_at(rng, now, header, &location)
+        };
+        fields.push(value);
+    }
+
+    while fields.last().is_some_and(|field| field.is_empty()) {
+        fields.pop();
+    }
+    fields
+}
+
+/// Generate a message for a message type and trigger event (ex: `ADT` and `A01`), following its
+/// message structure in the dictionary, with values generated as for `<auto>` maps
+///
+/// Required segments and fields are always generated, while optional ones are included at
+/// random. Segments in the template message (if there is one) are always included, and any
+/// fields with values in the template are copied (re-encoded with the `|^~\&` separators)
+/// instead of being generated. Returns
+/// `None` if the dictionary doesn't have a message structure for the message type and trigger
+/// event.
+pub fn generate_message<R: Rng>(
+    rng: &mut R,
+    now: NaiveDateTime,
+    version: Version,
+    message_code: &str,
+    trigger_event: &str,
+    template: Option<&ParsedMessageOwned>,
+) -> Option<String> {
+    let dictionary = dictionary();
+    let structure_id = dictionary.message_structure(message_code, trigger_event)?;
+    let structure = dictionary.structure(structure_id)?;
+
+    // values are generated against a bare header so that they use the new message's version
+    // (the parser doesn't accept a message with only one segment, hence the empty EVN)
+    let header = ParsedMessageOwned::parse(format!("MSH|^~\\&||||||||||{version}\rEVN|"), false)
+        .expect("header is valid");
+    let mut segments = Vec::new();
+    choose_segments(rng, version, structure, template, &mut segments);
+
+    let mut occurrences: HashMap<&str, usize> = HashMap::new();
+    let mut lines = Vec::with_capacity(segments.len());
+    for id in segments {
+        let occurrence = occurrences.entry(id).or_default();
+        *occurrence += 1;
+        let template_segment = template.and_then(|template| {
+            template
+                .segment_n(id, *occurrence - 1)
+                .map(|segment| (template, segment))
+        });
+        let mut fields = generate_segment(rng, now, &header, id, *occurrence, template_segment);
+
+        if id != "MSH" {
+            lines.push(format!("{id}|{}", fields.join("|")));
+            continue;
+        }
+        // the header's separators, message type, and version aren't generated, though the
+        // message type and version can still be overridden by the template. `fields[n]` is
+        // MSH-(n + 2), as MSH-1 is the field separator itself
+        fields.resize(fields.len().max(11), String::new());
+        fields[0] = "^~\\&".to_string();
+        let has_structure = dictionary
+            .annotate(version, "MSH", 9, Some(3), None)
+            .is_some();
+        let template_value = |field: usize| {
+            template
+                .and_then(|template| {
+                    let value = template
+                        .query_value(format!("MSH.{field}").as_str())
+                        .ok()??;
+                    Some(reencode(value, &template.separators))
+                })
+                .filter(|value| !value.is_empty())
+        };
+        fields[7] = template_value(9).unwrap_or_else(|| match has_structure {
+            true => format!("{message_code}^{trigger_event}^{structure_id}"),
+            false => format!("{message_code}^{trigger_event}"),
+        });
+        fields[10] = template_value(12).unwrap_or_else(|| version.to_string());
+        while fields.last().is_some_and(|field| field.is_empty()) {
+            fields.pop();
+        }
+        lines.push(format!("MSH|{}", fields.join("|")));
+    }
+    Some(lines.join("\r"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::{validate_message, Severity};
+    use std::str::FromStr;
+
+    const MESSAGE: &str = "MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.5.1\rEVN|A01|20050110045502\rPID|1||10006579^^^1^MR^1||DUCK^DONALD^D||19241010|M\rPV1|1|I";
+
+    fn generate_at(query: &str) -> String {
+        let message = ParsedMessageOwned::parse(MESSAGE, false).expect("can parse message");
+        generate_value(
+            &message,
+            &LocationQuery::from_str(query).expect("valid query"),
+        )
+    }
+
+    #[test]
+    fn can_generate_birth_dates() {
+        for _ in 0..50 {
+            let value = generate_at("PID.7");
+            let date = NaiveDate::parse_from_str(&value, "%Y%m%d").expect("value is a date");
+            assert!(date <= Local::now().date_naive());
+        }
+    }
+
+    #[test]
+    fn can_generate_table_values() {
+        let codes = ["F", "M", "O", "U", "A", "N"];
+        for _ in 0..50 {
+            assert!(codes.contains(&generate_at("PID.8").as_str()));
+        }
+    }
+
+    #[test]
+    fn can_generate_components() {
+        let name = generate_at("PID.5");
+        assert_eq!(name.split('^').count(), 3);
+        assert!(!generate_at("PID.5.1").contains('^'));
+        let identifier_types = dictionary().table("0203").expect("table 0203 exists");
+        assert!(identifier_types.contains(&generate_at("PID.3.5")));
+    }
+
+    #[test]
+    fn generated_values_are_valid() {
+        let source = "MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.5.1\rEVN|A01|20050110045502\rPID|1||X||X||X|X||X|X||X\rPV1|1|I|X||||X";
+        let mut message = ParsedMessageOwned::parse(source, false).expect("can parse message");
+        for query in [
+            "PID.3", "PID.5", "PID.7", "PID.8", "PID.10", "PID.11", "PID.13", "PV1.3", "PV1.7",
+        ] {
+            let query = LocationQuery::from_str(query).expect("valid query");
+            let value = generate_value(&message, &query);
+            let mut source = message.source.clone();
+            match message.query(&query).expect("can query") {
+                Some(range) => source.replace_range(range, &value),
+                None => panic!("{query} isn't in the message"),
+            }
+            message = ParsedMessageOwned::parse(source, false).expect("can parse message");
+        }
+
+        let errors: Vec<_> = validate_message(&message)
+            .into_iter()
+            .filter(|issue| issue.severity == Severity::Error)
+            .collect();
+        assert_eq!(errors, vec![]);
+    }
+
+    fn generate_messages(seed: u64, message_type: &str, template: Option<&str>) -> Vec<String> {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let (message_code, trigger_event) = message_type.split_once('^').expect("valid type");
+        let template = template
+            .map(|template| ParsedMessageOwned::parse(template, false).expect("valid template"));
+        let now = NaiveDate::from_ymd_opt(2025, 1, 1)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .expect("valid date");
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..20)
+            .map(|_| {
+                generate_message(
+                    &mut rng,
+                    now,
+                    Version::V2_5_1,
+                    message_code,
+                    trigger_event,
+                    template.as_ref(),
+                )
+                .expect("message type is known")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn generated_messages_are_valid() {
+        for message_type in [
+            "ADT^A01", "ADT^A08", "ADT^A39", "ORM^O01", "ORU^R01", "ACK^A01",
+        ] {
+            for source in generate_messages(1, message_type, None) {
+                let message = ParsedMessageOwned::parse(&source, false).expect("can parse message");
+                let (message_code, trigger_event) = message_type.split_once('^').unwrap();
+                assert_eq!(message.query_value("MSH.9.1").unwrap(), Some(message_code));
+                assert_eq!(message.query_value("MSH.9.2").unwrap(), Some(trigger_event));
+                assert_eq!(message.query_value("MSH.12").unwrap(), Some("2.5.1"));
+
+                let errors: Vec<_> = validate_message(&message)
+                    .into_iter()
+                    .filter(|issue| issue.severity == Severity::Error)
+                    .collect();
+                assert_eq!(errors, vec![], "{source}");
+            }
+        }
+        assert!(dictionary().message_structure("XYZ", "A01").is_none());
+    }
+
+    #[test]
+    fn can_reproduce_messages_from_a_seed() {
+        assert_eq!(
+            generate_messages(1, "ADT^A01", None),
+            generate_messages(1, "ADT^A01", None)
+        );
+        assert_ne!(
+            generate_messages(1, "ADT^A01", None),
+            generate_messages(2, "ADT^A01", None)
+        );
+    }
+
+    #[test]
+    fn can_override_generated_values_with_a_template() {
+        let template = "MSH|^~\\&|MYAPP|MYFACILITY\rPID|||||DUCK^DONALD\rNK1|1|DUCK^DAISY|SPO";
+        for source in generate_messages(1, "ADT^A01", Some(template)) {
+            let message = ParsedMessageOwned::parse(&source, false).expect("can parse message");
+            assert_eq!(message.query_value("MSH.3").unwrap(), Some("MYAPP"));
+            assert_eq!(message.query_value("MSH.4").unwrap(), Some("MYFACILITY"));
+            assert_eq!(
+                message.query_value("MSH.9").unwrap(),
+                Some("ADT^A01^ADT_A01")
+            );
+            assert_eq!(message.query_value("PID.5").unwrap(), Some("DUCK^DONALD"));
+            assert_eq!(message.query_value("NK1.2").unwrap(), Some("DUCK^DAISY"));
+            assert_eq!(message.query_value("NK1.3").unwrap(), Some("SPO"));
+        }
+    }
+
+    #[test]
+    fn can_use_templates_with_other_separators() {
+        let template = "MSH#@~\\&#MY^APP#MYFACILITY#####ADT@A08\rPID#####DUCK@DONALD&D~DUCK@D";
+        for source in generate_messages(1, "ADT^A01", Some(template)) {
+            assert!(
+                source.starts_with("MSH|^~\\&|MY\\S\\APP|MYFACILITY|"),
+                "{source}"
+            );
+            let message = ParsedMessageOwned::parse(&source, false).expect("can parse message");
+            assert_eq!(message.query_value("MSH.3").unwrap(), Some("MY\\S\\APP"));
+            assert_eq!(message.query_value("MSH.9").unwrap(), Some("ADT^A08"));
+            assert!(source.contains("\rPID|"), "{source}");
+            assert!(source.contains("|DUCK^DONALD&D~DUCK^D"), "{source}");
+        }
+    }
+}
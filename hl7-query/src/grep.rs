@@ -0,0 +1,197 @@
+use crate::{
+    cli::GrepArgs,
+    dictionary::{message_version, Version},
+    segments::{segment_leaves, segments_in_order},
+};
+use color_eyre::eyre::{Context, Result};
+use hl7_parser::ParsedMessageOwned;
+use regex::{Regex, RegexBuilder};
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+/// A value which matched the pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrepMatch {
+    /// The file the message was read from, or `-` for stdin
+    pub file: String,
+    /// The 1-based index of the message within the file
+    pub message: usize,
+    /// The path to the value, as printed in tables
+    pub path: Vec<String>,
+    /// The decoded value
+    pub value: String,
+    /// Where the pattern matched within the value
+    pub spans: Vec<Range<usize>>,
+}
+
+/// Build the pattern to search for from the command line arguments
+pub fn build_pattern(args: &GrepArgs) -> Result<Regex> {
+    let pattern = if args.fixed_strings {
+        regex::escape(&args.pattern)
+    } else {
+        args.pattern.clone()
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(args.ignore_case)
+        .build()
+        .wrap_err_with(|| format!("Invalid pattern: {:?}", args.pattern))
+}
+
+/// List the files to search, descending into directories. Files within a directory are
+/// searched in order of their names.
+pub fn files_to_search(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    fn visit(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        if !path.is_dir() {
+            files.push(path.to_path_buf());
+            return Ok(());
+        }
+        let mut entries = std::fs::read_dir(path)
+            .wrap_err_with(|| format!("Failed to read directory: {:?}", path.display()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .wrap_err_with(|| format!("Failed to read directory: {:?}", path.display()))?;
+        entries.sort();
+        for entry in entries {
+            visit(&entry, files)?;
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    for path in paths {
+        visit(path, &mut files)?;
+    }
+    Ok(files)
+}
+
+/// Search the decoded values of a message for the pattern, limited to the segments, fields,
+/// and data types given in the arguments
+pub fn grep_message(
+    file: &str,
+    message_index: usize,
+    message: &ParsedMessageOwned,
+    pattern: &Regex,
+    args: &GrepArgs,
+) -> Vec<GrepMatch> {
+    let version = message_version(message).unwrap_or(Version::V2_5_1);
+    let mut matches = Vec::new();
+    for segment in segments_in_order(message) {
+        if !args.segment.is_empty() && !args.segment.iter().any(|s| s == segment.id) {
+            continue;
+        }
+        for leaf in segment_leaves(&message.source, &segment) {
+            if !args.field.is_empty()
+                && !args
+                    .field
+                    .iter()
+                    .any(|field| field.contains(&message.source, &segment, Some(leaf.position)))
+            {
+                continue;
+            }
+            if !args.data_type.is_empty() {
+                let Some(annotation) = leaf.annotation(version, &segment) else {
+                    continue;
+                };
+                let wanted = |data_type: &&str| {
+                    args.data_type
+                        .iter()
+                        .any(|wanted| wanted.eq_ignore_ascii_case(data_type))
+                };
+                if !annotation.data_types.iter().any(wanted) {
+                    continue;
+                }
+            }
+
+            let value = message.separators.decode(leaf.value);
+            let spans: Vec<Range<usize>> = pattern.find_iter(&value).map(|m| m.range()).collect();
+            if spans.is_empty() {
+                continue;
+            }
+            matches.push(GrepMatch {
+                file: file.to_string(),
+                message: message_index,
+                path: std::iter::once(segment.path())
+                    .chain(leaf.path.iter().cloned())
+                    .collect(),
+                value,
+                spans,
+            });
+        }
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn grep(args: &[&str]) -> Vec<(String, String)> {
+        let message = ParsedMessageOwned::parse(
+            "MSH|^~\\&|A|1|||20050110045504||ADT^A01|1|P|2.5.1\rPID|1||123^^^DUCK&1.2&ISO^MR||DUCK^DONALD\rNK1|1|DUCK^DAISY|SPO\rNK1|2|MOUSE^MICKEY|FND",
+            false,
+        )
+        .expect("can parse message");
+        let cli = crate::cli::Cli::parse_from(["hq", "grep"].iter().chain(args));
+        let Some(crate::cli::Command::Grep(args)) = cli.command else {
+            panic!("grep command");
+        };
+        let pattern = build_pattern(&args).expect("valid pattern");
+        grep_message("-", 1, &message, &pattern, &args)
+            .into_iter()
+            .map(|m| (m.path.join("."), m.value))
+            .collect()
+    }
+
+    fn pair(path: &str, value: &str) -> (String, String) {
+        (path.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn can_grep_values() {
+        assert_eq!(
+            grep(&["DUCK"]),
+            vec![
+                pair("PID.3.4.1", "DUCK"),
+                pair("PID.5.1", "DUCK"),
+                pair("NK1[1].2.1", "DUCK"),
+            ]
+        );
+        assert_eq!(grep(&["-F", "1.2"]), vec![pair("PID.3.4.2", "1.2")]);
+        assert_eq!(
+            grep(&["^m", "--ignore-case"]),
+            vec![
+                pair("PID.3.5", "MR"),
+                pair("NK1[2].2.1", "MOUSE"),
+                pair("NK1[2].2.2", "MICKEY"),
+            ]
+        );
+    }
+
+    #[test]
+    fn can_limit_grep_to_segments_fields_and_data_types() {
+        assert_eq!(
+            grep(&["--segment", "NK1", "DUCK"]),
+            vec![pair("NK1[1].2.1", "DUCK")]
+        );
+        assert_eq!(
+            grep(&["--field", "PID.5", "DUCK"]),
+            vec![pair("PID.5.1", "DUCK")]
+        );
+        assert_eq!(
+            grep(&["--field", "NK1[3=FND].2", "^M"]),
+            vec![pair("NK1[2].2.1", "MOUSE"), pair("NK1[2].2.2", "MICKEY")]
+        );
+        assert_eq!(grep(&["--field", "NK1[1].2.2", "^M"]), vec![]);
+        assert_eq!(
+            grep(&["--data-type", "XPN", "DUCK"]),
+            vec![pair("PID.5.1", "DUCK"), pair("NK1[1].2.1", "DUCK")]
+        );
+        assert_eq!(
+            grep(&["--data-type", "cx", "DUCK"]),
+            vec![pair("PID.3.4.1", "DUCK")]
+        );
+    }
+}
@@ -0,0 +1,301 @@
+use color_eyre::eyre::{eyre, Context, Result};
+use hl7_parser::Separators;
+use serde_json::{Map, Value};
+
+/// Rebuild HL7 messages from the JSON produced by `--output json`
+///
+/// The input may either be a single message (an array of segments) or an array of messages.
+/// Values are escaped using the separators from MSH-1 and MSH-2 (or the default separators if
+/// they aren't present).
+pub fn messages_from_json(input: &str) -> Result<Vec<String>> {
+    let json: Value = serde_json::from_str(input).wrap_err_with(|| "Input is not valid JSON")?;
+    let Value::Array(items) = json else {
+        return Err(eyre!(
+            "Expected an array of segments or an array of messages"
+        ));
+    };
+
+    if items.iter().all(Value::is_array) && !items.is_empty() {
+        items
+            .iter()
+            .enumerate()
+            .map(|(i, message)| {
+                let segments = message.as_array().expect("items are arrays");
+                message_from_json(segments)
+                    .wrap_err_with(|| format!("Failed to convert message #{} from JSON", i + 1))
+            })
+            .collect()
+    } else {
+        Ok(vec![message_from_json(&items)?])
+    }
+}
+
+/// Rebuild a single HL7 message from an array of segment objects
+pub(super) fn message_from_json(segments: &[Value]) -> Result<String> {
+    let segments = segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            let Some((id, fields)) = segment.as_object().and_then(|s| s.iter().next()) else {
+                return Err(eyre!(
+                    "Segment #{} must be an object with a segment identifier as its only key",
+                    i + 1
+                ));
+            };
+            if segment.as_object().map(Map::len) != Some(1) {
+                return Err(eyre!(
+                    "Segment #{} ({id}) must only have a single key",
+                    i + 1
+                ));
+            }
+            Ok((id.as_str(), fields))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let separators = match segments.first() {
+        Some(("MSH", fields)) => separators_from_json(fields)?,
+        _ => return Err(eyre!("The first segment of a message must be MSH")),
+    };
+
+    let mut message = String::new();
+    for (i, (id, fields)) in segments.into_iter().enumerate() {
+        if i > 0 {
+            message.push('\r');
+        }
+        write_segment(&mut message, id, fields, &separators)
+            .wrap_err_with(|| format!("Failed to convert segment #{} ({id})", i + 1))?;
+    }
+    Ok(message)
+}
+
+fn separators_from_json(fields: &Value) -> Result<Separators> {
+    let defaults = Separators::default();
+    let field = match fields.get("1").and_then(Value::as_str) {
+        Some(field) => {
+            let mut chars = field.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => return Err(eyre!("MSH.1 must be a single character, not {field:?}")),
+            }
+        }
+        None => defaults.field,
+    };
+    let encoding: Vec<char> = match fields.get("2").and_then(Value::as_str) {
+        Some(encoding) => encoding.chars().collect(),
+        None => vec![
+            defaults.component,
+            defaults.repeat,
+            defaults.escape,
+            defaults.subcomponent,
+        ],
+    };
+    if encoding.len() != 4 {
+        return Err(eyre!(
+            "MSH.2 must contain exactly 4 encoding characters, not {:?}",
+            encoding.iter().collect::<String>()
+        ));
+    }
+    Ok(Separators {
+        field,
+        component: encoding[0],
+        repeat: encoding[1],
+        escape: encoding[2],
+        subcomponent: encoding[3],
+    })
+}
+
+/// Parse the 1-based index keys of a JSON object, returning the values in order alongside
+/// their index
+fn indexed_values(object: &Map<String, Value>) -> Result<Vec<(usize, &Value)>> {
+    let mut values = object
+        .iter()
+        .map(|(key, value)| match key.parse::<usize>() {
+            Ok(index) if index > 0 => Ok((index, value)),
+            _ => Err(eyre!("Expected a 1-based index as a key, not {key:?}")),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    values.sort_by_key(|(index, _)| *index);
+    Ok(values)
+}
+
+/// Join indexed values with a separator, leaving gaps empty
+fn write_indexed<F>(
+    out: &mut String,
+    object: &Map<String, Value>,
+    separator: char,
+    mut write: F,
+) -> Result<()>
+where
+    F: FnMut(&mut String, &Value) -> Result<()>,
+{
+    let mut position = 1;
+    for (index, value) in indexed_values(object)? {
+        while position < index {
+            out.push(separator);
+            position += 1;
+        }
+        write(out, value)?;
+    }
+    Ok(())
+}
+
+fn write_segment(
+    out: &mut String,
+    id: &str,
+    fields: &Value,
+    separators: &Separators,
+) -> Result<()> {
+    out.push_str(id);
+    let fields = match fields {
+        Value::Object(fields) => fields,
+        Value::Null => return Ok(()),
+        _ => return Err(eyre!("Expected an object of fields")),
+    };
+
+    if id == "MSH" {
+        // MSH-1 is the field separator itself, and MSH-2 must not be escaped
+        out.push(separators.field);
+        out.push_str(&String::from_iter([
+            separators.component,
+            separators.repeat,
+            separators.escape,
+            separators.subcomponent,
+        ]));
+        let rest: Map<String, Value> = indexed_values(fields)?
+            .into_iter()
+            .filter(|(index, _)| *index > 2)
+            .map(|(index, value)| (format!("{}", index - 2), value.clone()))
+            .collect();
+        if !rest.is_empty() {
+            out.push(separators.field);
+        }
+        return write_indexed(out, &rest, separators.field, |out, value| {
+            write_field(out, value, separators)
+        });
+    }
+
+    if !fields.is_empty() {
+        out.push(separators.field);
+    }
+    write_indexed(out, fields, separators.field, |out, value| {
+        write_field(out, value, separators)
+    })
+}
+
+fn write_field(out: &mut String, field: &Value, separators: &Separators) -> Result<()> {
+    match field {
+        Value::Array(repeats) => {
+            for (i, repeat) in repeats.iter().enumerate() {
+                if i > 0 {
+                    out.push(separators.repeat);
+                }
+                write_components(out, repeat, separators)?;
+            }
+            Ok(())
+        }
+        field => write_components(out, field, separators),
+    }
+}
+
+fn write_components(out: &mut String, repeat: &Value, separators: &Separators) -> Result<()> {
+    match repeat {
+        Value::Object(components) => {
+            write_indexed(out, components, separators.component, |out, component| {
+                write_sub_components(out, component, separators)
+            })
+        }
+        value => write_value(out, value, separators),
+    }
+}
+
+fn write_sub_components(
+    out: &mut String,
+    component: &Value,
+    separators: &Separators,
+) -> Result<()> {
+    match component {
+        Value::Object(sub_components) => write_indexed(
+            out,
+            sub_components,
+            separators.subcomponent,
+            |out, value| write_value(out, value, separators),
+        ),
+        value => write_value(out, value, separators),
+    }
+}
+
+fn write_value(out: &mut String, value: &Value, separators: &Separators) -> Result<()> {
+    match value {
+        Value::Null => {}
+        Value::String(value) => escape(out, value, separators),
+        Value::Number(value) => escape(out, &value.to_string(), separators),
+        Value::Bool(value) => escape(out, &value.to_string(), separators),
+        Value::Array(_) | Value::Object(_) => {
+            return Err(eyre!("Values nested too deeply: {value}"));
+        }
+    }
+    Ok(())
+}
+
+/// Escape the separators (and newlines) in a value
+fn escape(out: &mut String, value: &str, separators: &Separators) {
+    let escape = separators.escape;
+    for c in value.chars() {
+        let sequence = if c == separators.field {
+            "F"
+        } else if c == separators.component {
+            "S"
+        } else if c == separators.repeat {
+            "R"
+        } else if c == separators.subcomponent {
+            "T"
+        } else if c == escape {
+            "E"
+        } else if c == '\r' {
+            "X0D"
+        } else if c == '\n' {
+            "X0A"
+        } else {
+            out.push(c);
+            continue;
+        };
+        out.push(escape);
+        out.push_str(sequence);
+        out.push(escape);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_convert_json_to_hl7() {
+        let json = r#"[
+            {"MSH": {"1": "|", "2": "^~\\&", "3": "AccMgr", "9": {"1": "ADT", "2": "A01"}, "12": "2.3"}},
+            {"PID": {"1": 1, "3": [{"1": "123", "5": "MR"}, null, "456"], "5": {"1": {"1": "DUCK", "2": "VON"}, "2": "D|^~\\&"}}},
+            {"ZZZ": {}}
+        ]"#;
+        let messages = messages_from_json(json).expect("can convert JSON");
+        assert_eq!(
+            messages,
+            vec![
+                "MSH|^~\\&|AccMgr||||||ADT^A01|||2.3\rPID|1||123^^^^MR~~456||DUCK&VON^D\\F\\\\S\\\\R\\\\E\\\\T\\\rZZZ"
+                    .to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn can_use_custom_separators() {
+        let json = r##"[[{"MSH": {"1": "#", "2": "$%!@", "3": "A#B"}}], [{"MSH": {"3": "A|B"}}]]"##;
+        let messages = messages_from_json(json).expect("can convert JSON");
+        assert_eq!(
+            messages,
+            vec![
+                "MSH#$%!@#A!F!B".to_string(),
+                "MSH|^~\\&|A\\F\\B".to_string()
+            ]
+        );
+    }
+}
@@ -0,0 +1,4 @@
+mod json;
+pub use json::messages_from_json;
+mod xml;
+pub use xml::messages_from_xml;
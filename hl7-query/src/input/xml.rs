@@ -0,0 +1,232 @@
+use super::json::message_from_json;
+use color_eyre::eyre::{eyre, Context, Result};
+use quick_xml::events::Event;
+use serde_json::{Map, Value};
+
+#[derive(Debug, Default)]
+struct Node {
+    name: String,
+    children: Vec<Node>,
+    text: String,
+}
+
+/// Rebuild HL7 messages from the HL7 v2.xml encoding
+///
+/// Any elements which aren't segments (the message element, segment groups, or a wrapper around
+/// several messages) are flattened, and a new message is started at each MSH segment. Fields,
+/// components, and sub-components are positioned by the number at the end of their element
+/// name (ex: `PID.5`, `XPN.1`), so no dictionary is needed to read the XML.
+pub fn messages_from_xml(input: &str) -> Result<Vec<String>> {
+    let roots = parse_xml(input)?;
+
+    let mut segments = Vec::new();
+    for root in roots.iter() {
+        collect_segments(root, &mut segments)?;
+    }
+
+    let mut messages: Vec<Vec<Value>> = Vec::new();
+    for segment in segments {
+        let is_header = segment.get("MSH").is_some();
+        match messages.last_mut() {
+            Some(message) if !is_header => message.push(segment),
+            _ => messages.push(vec![segment]),
+        }
+    }
+
+    messages
+        .iter()
+        .enumerate()
+        .map(|(i, segments)| {
+            message_from_json(segments)
+                .wrap_err_with(|| format!("Failed to convert message #{} from XML", i + 1))
+        })
+        .collect()
+}
+
+fn parse_xml(input: &str) -> Result<Vec<Node>> {
+    let mut reader = quick_xml::Reader::from_str(input);
+    let mut stack: Vec<Node> = Vec::new();
+    let mut roots: Vec<Node> = Vec::new();
+
+    let name = |name: quick_xml::name::LocalName| -> Result<String> {
+        std::str::from_utf8(name.as_ref())
+            .map(|name| name.to_string())
+            .wrap_err_with(|| "Element name is not valid UTF-8")
+    };
+
+    loop {
+        let event = reader
+            .read_event()
+            .wrap_err_with(|| format!("Invalid XML at position {}", reader.buffer_position()))?;
+        match event {
+            Event::Start(start) => stack.push(Node {
+                name: name(start.local_name())?,
+                ..Default::default()
+            }),
+            Event::Empty(empty) => {
+                let node = Node {
+                    name: name(empty.local_name())?,
+                    ..Default::default()
+                };
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(node),
+                    None => roots.push(node),
+                }
+            }
+            Event::End(_) => {
+                let node = stack.pop().ok_or_else(|| eyre!("Unexpected closing tag"))?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(node),
+                    None => roots.push(node),
+                }
+            }
+            Event::Text(text) => {
+                if let Some(node) = stack.last_mut() {
+                    let text = text
+                        .unescape()
+                        .wrap_err_with(|| "Failed to unescape XML text")?;
+                    node.text.push_str(&text);
+                }
+            }
+            Event::CData(data) => {
+                if let Some(node) = stack.last_mut() {
+                    let data =
+                        std::str::from_utf8(&data).wrap_err_with(|| "CDATA is not valid UTF-8")?;
+                    node.text.push_str(data);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    if let Some(node) = stack.last() {
+        return Err(eyre!("Element {} is never closed", node.name));
+    }
+    Ok(roots)
+}
+
+/// Whether the element is a segment: a three character identifier whose children are all
+/// fields of that segment (ex: `<PID><PID.1>`), as opposed to a message or group (ex:
+/// `<ACK><MSH>`)
+fn is_segment(node: &Node) -> bool {
+    let mut chars = node.name.chars();
+    node.name.len() == 3
+        && chars.next().is_some_and(|c| c.is_ascii_uppercase())
+        && chars.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        && node.children.iter().all(|child| {
+            child
+                .name
+                .strip_prefix(&node.name)
+                .and_then(|rest| rest.strip_prefix('.'))
+                .is_some_and(|index| index.parse::<usize>().is_ok())
+        })
+}
+
+fn collect_segments(node: &Node, segments: &mut Vec<Value>) -> Result<()> {
+    if !is_segment(node) {
+        for child in node.children.iter() {
+            collect_segments(child, segments)?;
+        }
+        return Ok(());
+    }
+
+    let mut fields = Map::new();
+    for field in node.children.iter() {
+        let index = element_index(&field.name)?;
+        let value = components_to_json(field)
+            .wrap_err_with(|| format!("Failed to read field {}", field.name))?;
+        match fields.get_mut(&index) {
+            Some(Value::Array(repeats)) => repeats.push(value),
+            Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+            None => {
+                fields.insert(index, value);
+            }
+        }
+    }
+
+    let mut segment = Map::new();
+    segment.insert(node.name.clone(), Value::Object(fields));
+    segments.push(Value::Object(segment));
+    Ok(())
+}
+
+/// The index at the end of an element name (ex: `5` for `PID.5` or `2` for `ZPI.1.2`)
+fn element_index(name: &str) -> Result<String> {
+    name.rsplit_once('.')
+        .map(|(_, index)| index)
+        .filter(|index| index.parse::<usize>().is_ok_and(|i| i > 0))
+        .map(|index| index.to_string())
+        .ok_or_else(|| eyre!("Element {name} doesn't end with a 1-based index"))
+}
+
+fn components_to_json(node: &Node) -> Result<Value> {
+    if node.children.is_empty() {
+        return Ok(Value::String(node.text.clone()));
+    }
+    let mut components = Map::new();
+    for component in node.children.iter() {
+        components.insert(
+            element_index(&component.name)?,
+            sub_components_to_json(component)?,
+        );
+    }
+    Ok(Value::Object(components))
+}
+
+fn sub_components_to_json(node: &Node) -> Result<Value> {
+    if node.children.is_empty() {
+        return Ok(Value::String(node.text.clone()));
+    }
+    let mut sub_components = Map::new();
+    for sub_component in node.children.iter() {
+        if !sub_component.children.is_empty() {
+            return Err(eyre!(
+                "Element {} is nested deeper than a sub-component",
+                sub_component.name
+            ));
+        }
+        sub_components.insert(
+            element_index(&sub_component.name)?,
+            Value::String(sub_component.text.clone()),
+        );
+    }
+    Ok(Value::Object(sub_components))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_convert_xml_to_hl7() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ACK xmlns="urn:hl7-org:v2xml">
+  <MSH>
+    <MSH.1>|</MSH.1>
+    <MSH.2>^~\&amp;</MSH.2>
+    <MSH.9><MSG.1>ACK</MSG.1></MSH.9>
+    <MSH.12><VID.1>2.5.1</VID.1></MSH.12>
+  </MSH>
+  <MSA>
+    <MSA.1>AA</MSA.1>
+    <MSA.2>1|2</MSA.2>
+  </MSA>
+  <ACK.GROUP>
+    <ERR>
+      <ERR.2><ERL.1>PID</ERL.1><ERL.2>5</ERL.2></ERR.2>
+      <ERR.2><ERL.1>PID</ERL.1></ERR.2>
+      <ERR.3><CWE.1><X.1>A</X.1><X.2>B</X.2></CWE.1></ERR.3>
+    </ERR>
+  </ACK.GROUP>
+  <ZZZ/>
+</ACK>"#;
+        let messages = messages_from_xml(xml).expect("can convert XML");
+        assert_eq!(
+            messages,
+            vec![
+                "MSH|^~\\&|||||||ACK|||2.5.1\rMSA|AA|1\\F\\2\rERR||PID^5~PID|A&B\rZZZ".to_string()
+            ]
+        );
+    }
+}
@@ -1,21 +1,119 @@
-use std::io::IsTerminal;
+use std::{
+    io::IsTerminal,
+    path::{Path, PathBuf},
+};
 
 use cli::Cli;
-use color_eyre::eyre::{Context, Result};
-use hl7_parser::ParsedMessageOwned;
+use color_eyre::eyre::{eyre, Context, Result};
+use dictionary::Version;
+use hl7_parser::{LocationQuery, ParsedMessageOwned};
+use map::{Deletion, Insertion, Operation, ValueMap};
 use termcolor::StandardStream;
 
+mod anonymize;
+mod batch;
 mod cli;
+mod dictionary;
+mod diff;
+mod expression;
+mod filter;
+mod generate;
+mod grep;
+mod input;
 mod map;
 mod print;
+mod query;
+mod script;
+mod segments;
+mod stats;
+mod structure;
+mod validate;
 pub use print::*;
 
 fn main() -> Result<()> {
     color_eyre::install()?;
 
-    let cli = cli::cli();
+    let mut cli = cli::cli();
+    if let Some(script) = &cli.script {
+        let operations = script::load_script(script)
+            .wrap_err_with(|| format!("Failed to load script: {:?}", script.display()))?;
+        cli.operations.splice(0..0, operations);
+    }
+    if cli.anonymize {
+        // choose the seed once so that values are replaced consistently across a batch
+        cli.anonymize_seed.get_or_insert_with(rand::random);
+    }
+
+    let messages = match &cli.command {
+        Some(cli::Command::Diff(args)) => return run_diff(args, &cli),
+        Some(cli::Command::Grep(args)) => return run_grep(args, &cli),
+        Some(cli::Command::Filter(args)) => return run_filter(args, &cli),
+        Some(cli::Command::Stats(args)) => return run_stats(args, &cli),
+        Some(cli::Command::Generate(args)) => generate_messages(args, &cli)?,
+        None => read_messages(cli.input.as_deref(), &cli)?,
+    };
+
+    if cli.validate {
+        let issues: Vec<_> = messages.iter().map(validate::validate_message).collect();
+        print_validation_results(&issues, &cli)
+            .wrap_err_with(|| "Failed to print validation results")?;
+        let errors = issues
+            .iter()
+            .flatten()
+            .filter(|issue| issue.severity == validate::Severity::Error)
+            .count();
+        if errors > 0 {
+            return Err(eyre!("Validation failed with {errors} error(s)"));
+        }
+        Ok(())
+    } else if cli.query.is_empty() {
+        match cli.output {
+            cli::OutputMode::HL7 => {
+                print_message_hl7(&messages, &cli).wrap_err_with(|| "Failed to print message")
+            }
+            cli::OutputMode::Json => {
+                print_message_json(&messages, &cli).wrap_err_with(|| "Failed to print queries")
+            }
+            cli::OutputMode::Table => {
+                print_message_table(&messages, &cli).wrap_err_with(|| "Failed to print queries")
+            }
+            cli::OutputMode::Xml => {
+                print_message_xml(&messages, &cli).wrap_err_with(|| "Failed to print message")
+            }
+            cli::OutputMode::Csv | cli::OutputMode::Tsv => Err(eyre!(
+                "--output {} prints query results, use it with --query",
+                cli.output
+            )),
+        }
+    } else {
+        print_query_results(&messages, &cli).wrap_err_with(|| "Failed to print queries")
+    }
+}
 
-    let input = if let Some(input) = &cli.input {
+/// Map newlines to `\r` segment separators (unless `--no-correct-newlines` is used) and drop
+/// any trailing separators
+fn correct_newlines(input: String, cli: &Cli) -> String {
+    let input = if cli.no_correct_newlines {
+        input
+    } else {
+        input.replace("\r\n", "\r").replace('\n', "\r")
+    };
+    input.trim_end_matches('\r').to_string()
+}
+
+/// Remove any ANSI escape sequences (ex: from coloured `hq` output) from the input, keeping the
+/// `\r` segment separators which `strip_ansi_escapes` drops along with other control characters
+fn strip_ansi(input: &str) -> String {
+    input
+        .split('\r')
+        .map(strip_ansi_escapes::strip_str)
+        .collect::<Vec<_>>()
+        .join("\r")
+}
+
+/// Read, parse, and apply any edits to the messages in a file (or stdin)
+fn read_messages(input: Option<&Path>, cli: &Cli) -> Result<Vec<ParsedMessageOwned>> {
+    let input = if let Some(input) = input {
         std::fs::read_to_string(input)
             .wrap_err_with(|| format!("Failed to read input file: {:?}", input.display()))?
     } else {
@@ -26,60 +124,406 @@ fn main() -> Result<()> {
             .wrap_err_with(|| "Failed to read from stdin")?;
         input
     };
-    let input = strip_ansi_escapes::strip_str(input);
+    let input = strip_ansi(&input);
 
-    let input = if cli.no_correct_newlines {
-        input
-    } else {
-        input.replace("\r\n", "\r").replace('\n', "\r")
+    let sources = match cli.input_format {
+        cli::InputFormat::HL7 => batch::split_messages(&correct_newlines(input, cli)),
+        cli::InputFormat::Json => input::messages_from_json(&input)
+            .wrap_err_with(|| "Failed to convert JSON input to HL7")?,
+        cli::InputFormat::Xml => input::messages_from_xml(&input)
+            .wrap_err_with(|| "Failed to convert XML input to HL7")?,
     };
-    let input = input.trim_end_matches('\r').to_string();
+    if sources.is_empty() {
+        return Err(eyre!(
+            "Failed to parse input as HL7 message: no messages found"
+        ));
+    }
 
-    let message = ParsedMessageOwned::parse(input, false)
-        .wrap_err_with(|| "Failed to parse input as HL7 message")?;
+    sources
+        .into_iter()
+        .enumerate()
+        .map(|(i, source)| {
+            let message = ParsedMessageOwned::parse(source, false).wrap_err_with(|| {
+                format!("Failed to parse input as HL7 message (message #{})", i + 1)
+            })?;
+            apply_operations(message, cli)
+                .wrap_err_with(|| format!("Failed to apply edits (message #{})", i + 1))
+        })
+        .collect::<Result<Vec<_>>>()
+}
 
-    let message = apply_maps(message, &cli).wrap_err_with(|| "Failed to apply value mappings")?;
+/// Generate synthetic messages and apply any edits to them
+fn generate_messages(args: &cli::GenerateArgs, cli: &Cli) -> Result<Vec<ParsedMessageOwned>> {
+    use rand::{rngs::StdRng, SeedableRng};
 
-    if cli.query.is_empty() {
-        match cli.output {
-            cli::OutputMode::HL7 => {
-                print_message_hl7(message, &cli).wrap_err_with(|| "Failed to print message")
-            }
-            cli::OutputMode::Json => {
-                print_message_json(message, &cli).wrap_err_with(|| "Failed to print queries")
-            }
-            cli::OutputMode::Table => {
-                print_message_table(message, &cli).wrap_err_with(|| "Failed to print queries")
+    let (message_code, trigger_event) =
+        args.message_type.split_once(['^', '_']).ok_or_else(|| {
+            eyre!(
+                "Invalid message type {:?}, expected ex: ADT^A01",
+                args.message_type
+            )
+        })?;
+    let template = args
+        .template
+        .as_ref()
+        .map(|template| -> Result<ParsedMessageOwned> {
+            let source = std::fs::read_to_string(template).wrap_err_with(|| {
+                format!("Failed to read template file: {:?}", template.display())
+            })?;
+            let source = correct_newlines(strip_ansi(&source), cli);
+            ParsedMessageOwned::parse(source, false)
+                .wrap_err_with(|| "Failed to parse template as HL7 message")
+        })
+        .transpose()?;
+
+    let (mut rng, now) = match args.seed {
+        Some(seed) => (
+            StdRng::seed_from_u64(seed),
+            chrono::NaiveDate::from_ymd_opt(2025, 1, 1)
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .expect("valid date"),
+        ),
+        None => (StdRng::from_entropy(), chrono::Local::now().naive_local()),
+    };
+
+    (0..args.count)
+        .map(|i| {
+            let source = generate::generate_message(
+                &mut rng,
+                now,
+                args.version,
+                message_code,
+                trigger_event,
+                template.as_ref(),
+            )
+            .ok_or_else(|| {
+                eyre!("No message structure for {message_code}^{trigger_event} in the dictionary")
+            })?;
+            let message = ParsedMessageOwned::parse(source, false).wrap_err_with(|| {
+                format!("Failed to parse generated message (message #{})", i + 1)
+            })?;
+            apply_operations(message, cli)
+                .wrap_err_with(|| format!("Failed to apply edits (message #{})", i + 1))
+        })
+        .collect()
+}
+
+fn run_filter(args: &cli::FilterArgs, cli: &Cli) -> Result<()> {
+    use std::io::{BufRead, Write};
+
+    if cli.input_format != cli::InputFormat::HL7 {
+        return Err(eyre!("hq filter can only read HL7 messages"));
+    }
+    let reader: Box<dyn BufRead> = match &args.input {
+        Some(input) => Box::new(std::io::BufReader::new(
+            std::fs::File::open(input)
+                .wrap_err_with(|| format!("Failed to read input file: {:?}", input.display()))?,
+        )),
+        None => Box::new(std::io::stdin().lock()),
+    };
+    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+
+    let mut matched = 0;
+    for (i, raw) in batch::MessageReader::new(reader).enumerate() {
+        let raw = raw.wrap_err_with(|| "Failed to read input")?;
+        let source = String::from_utf8_lossy(&raw);
+        let source = correct_newlines(strip_ansi(&source), cli);
+        let message = match ParsedMessageOwned::parse(source, false) {
+            Ok(message) => message,
+            Err(err) => {
+                eprintln!("warning: skipping message #{}: {err}", i + 1);
+                continue;
             }
+        };
+        if !args.filters.iter().all(|filter| filter.matches(&message)) {
+            continue;
         }
+
+        matched += 1;
+        stdout
+            .write_all(&raw)
+            .wrap_err_with(|| "Failed to write to stdout")?;
+        // keep the last message of the input separate from the next one printed
+        if !raw.ends_with(b"\r") && !raw.ends_with(b"\n") {
+            let newline: &[u8] = if raw.contains(&b'\n') { b"\n" } else { b"\r" };
+            stdout
+                .write_all(newline)
+                .wrap_err_with(|| "Failed to write to stdout")?;
+        }
+    }
+    stdout
+        .flush()
+        .wrap_err_with(|| "Failed to write to stdout")?;
+
+    if matched == 0 {
+        return Err(eyre!("No messages matched the filters"));
+    }
+    Ok(())
+}
+
+/// Read the messages in each of a list of files and directories (or stdin, if the list is
+/// empty), skipping any files which can't be read with a warning
+fn read_many(paths: &[PathBuf], cli: &Cli) -> Result<Vec<(String, Vec<ParsedMessageOwned>)>> {
+    let inputs: Vec<Option<PathBuf>> = if paths.is_empty() {
+        vec![None]
     } else {
-        print_query_results(message, &cli).wrap_err_with(|| "Failed to print queries")
+        grep::files_to_search(paths)?
+            .into_iter()
+            .map(Some)
+            .collect()
+    };
+
+    let mut files = Vec::new();
+    for input in inputs {
+        let file = input
+            .as_ref()
+            .map(|input| input.display().to_string())
+            .unwrap_or_else(|| "-".to_string());
+        match read_messages(input.as_deref(), cli) {
+            Ok(messages) => files.push((file, messages)),
+            Err(err) => eprintln!("warning: skipping {file}: {err}"),
+        }
+    }
+    Ok(files)
+}
+
+fn run_stats(args: &cli::StatsArgs, cli: &Cli) -> Result<()> {
+    let mut stats = stats::Stats::default();
+    for (_, messages) in read_many(&args.paths, cli)? {
+        messages.iter().for_each(|message| stats.add(message));
+    }
+    if stats.messages == 0 {
+        return Err(eyre!("No messages found"));
+    }
+    print_stats(&stats, args, cli).wrap_err_with(|| "Failed to print statistics")
+}
+
+fn run_grep(args: &cli::GrepArgs, cli: &Cli) -> Result<()> {
+    let pattern = grep::build_pattern(args)?;
+    let mut matches = Vec::new();
+    for (file, messages) in read_many(&args.paths, cli)? {
+        for (i, message) in messages.iter().enumerate() {
+            matches.extend(grep::grep_message(&file, i + 1, message, &pattern, args));
+        }
+    }
+
+    print_grep_matches(&matches, cli).wrap_err_with(|| "Failed to print matches")?;
+    if matches.is_empty() {
+        return Err(eyre!("No values matched {:?}", args.pattern));
+    }
+    Ok(())
+}
+
+fn run_diff(args: &cli::DiffArgs, cli: &Cli) -> Result<()> {
+    let a = read_messages(Some(&args.a), cli)?;
+    let b = read_messages(Some(&args.b), cli)?;
+    if a.len() != b.len() {
+        return Err(eyre!(
+            "Can't compare {} message(s) with {} message(s)",
+            a.len(),
+            b.len()
+        ));
     }
+
+    let differences: Vec<_> = a
+        .iter()
+        .zip(b.iter())
+        .map(|(a, b)| diff::diff_messages(a, b, &args.ignore))
+        .collect();
+    print_differences(&differences, cli).wrap_err_with(|| "Failed to print differences")?;
+    // like `diff`, differences aren't an error, but still exit with a non-zero status
+    if differences.iter().any(|differences| !differences.is_empty()) {
+        std::process::exit(1);
+    }
+    Ok(())
 }
 
-fn apply_maps(mut message: ParsedMessageOwned, cli: &Cli) -> Result<ParsedMessageOwned> {
-    'maps: for map in cli.map.iter() {
-        let query = &*map.from;
+fn apply_operations(mut message: ParsedMessageOwned, cli: &Cli) -> Result<ParsedMessageOwned> {
+    // evaluate every map's value (and condition) against the message before any edits are made,
+    // so that maps which reference other fields (ex: swapping MSH-3 and MSH-5) see the original
+    // values
+    let reify = |map: &ValueMap| {
+        map.to
+            .reify(&map.from, &message)
+            .map(Some)
+            .map_err(|err| eyre!("Failed to evaluate map {map}: {err}"))
+    };
+    let values: Vec<Option<String>> = cli
+        .operations
+        .iter()
+        .map(|operation| match operation {
+            Operation::Map(map) => reify(map),
+            Operation::ConditionalMap { map, when } if when.matches(&message) => reify(map),
+            _ => Ok(None),
+        })
+        .collect::<Result<_>>()?;
+
+    for (operation, value) in cli.operations.iter().zip(values) {
+        message = match (operation, value) {
+            (Operation::Map(map) | Operation::ConditionalMap { map, .. }, Some(value)) => {
+                apply_map(message, map, &value, cli.create_segments)?
+            }
+            (Operation::Delete(deletion), _) => apply_deletion(message, deletion)?,
+            (Operation::Insert(insertion), _) => apply_insertion(message, insertion)?,
+            // the map's condition didn't match
+            (_, None) => message,
+        };
+    }
 
+    if cli.anonymize {
+        let fields = if cli.anonymize_fields.is_empty() {
+            anonymize::default_phi_fields()
+        } else {
+            cli.anonymize_fields.clone()
+        };
+        message = anonymize::anonymize(message, &fields, cli.anonymize_seed.unwrap_or_default())?;
+    }
+    Ok(message)
+}
+
+fn reparse(source: &str, operation: &dyn std::fmt::Display) -> Result<ParsedMessageOwned> {
+    ParsedMessageOwned::parse(source, false)
+        .wrap_err_with(|| format!("Failed to re-parse message after applying {operation}"))
+}
+
+fn is_separator_field(query: &LocationQuery) -> bool {
+    query.segment == "MSH" && matches!(query.field.map(|f| f.get()), Some(1 | 2))
+}
+
+fn apply_map(
+    mut message: ParsedMessageOwned,
+    map: &ValueMap,
+    value: &str,
+    create_segments: bool,
+) -> Result<ParsedMessageOwned> {
+    let query = &*map.from;
+
+    if is_separator_field(query) {
+        eprintln!("warning: ignoring map {map}: MSH-1 and MSH-2 hold the message's separators");
+        return Ok(message);
+    }
+
+    // only the first occurrence of a segment can be created
+    if !message.has_segment(&query.segment) && query.segment_index() == 0 {
+        if !create_segments {
+            eprintln!(
+                "warning: ignoring map {map}: the message has no {} segment (use --create-segments to add it)",
+                query.segment
+            );
+            return Ok(message);
+        }
+        let version = dictionary::message_version(&message).unwrap_or(Version::V2_5_1);
+        let position = structure::insertion_point(&message, version, &query.segment)
+            .map(|segment| segment.segment.range.end)
+            .unwrap_or(message.source.len());
+        let segment = format!("\r{}{}", query.segment, message.separators.field);
+        message.source.insert_str(position, &segment);
+        message = reparse(&message.source, map)?;
+    }
+
+    // add any fields, repeats, or components the map needs to the segment
+    let Some(segment) = message.segment_n(&query.segment, query.segment_index()) else {
+        eprintln!("warning: map {map} had no effect: {query} isn't in the message");
+        return Ok(message);
+    };
+    let segment = segment.range.clone();
+    let expanded =
+        map::expand_segment(&message.source[segment.clone()], &message.separators, query);
+    if expanded != message.source[segment.clone()] {
+        message.source.replace_range(segment, &expanded);
+        message = reparse(&message.source, map)?;
+    }
+
+    match query.range(&message) {
+        Some(range) => {
+            message.source.replace_range(range, value);
+            reparse(&message.source, map)
+        }
+        None => {
+            eprintln!("warning: map {map} had no effect: {query} isn't in the message");
+            Ok(message)
+        }
+    }
+}
+
+fn apply_deletion(
+    mut message: ParsedMessageOwned,
+    deletion: &Deletion,
+) -> Result<ParsedMessageOwned> {
+    let query = &deletion.0;
+
+    if query.field.is_none() && query.segment == "MSH" {
+        eprintln!("warning: ignoring deletion {deletion}: the MSH segment can't be removed");
+        return Ok(message);
+    }
+    if is_separator_field(query) {
+        eprintln!(
+            "warning: ignoring deletion {deletion}: MSH-1 and MSH-2 hold the message's separators"
+        );
+        return Ok(message);
+    }
+
+    let mut ranges = query.ranges(&message);
+    if ranges.is_empty() {
         if !message.has_segment(&query.segment) {
-            continue 'maps;
+            eprintln!(
+                "warning: deletion {deletion} had no effect: the message has no {} segment",
+                query.segment
+            );
+        } else if query.is_single()
+            && message
+                .segment_n(&query.segment, query.segment_index())
+                .is_none()
+        {
+            eprintln!(
+                "warning: deletion {deletion} had no effect: the message doesn't have {} {} segment(s)",
+                query.segment_index() + 1,
+                query.segment
+            );
+        } else {
+            eprintln!("warning: deletion {deletion} had no effect: {query} isn't in the message");
         }
+        return Ok(message);
+    }
 
-        let range = message.query(query).wrap_err_with(|| {
-            format!(
-                "Failed to query message for {:?} (map: {:?})",
-                query, map.from
-            )
-        })?;
+    // remove from the end so that the earlier ranges stay valid
+    ranges.sort_by_key(|range| range.start);
+    for range in ranges.into_iter().rev() {
+        if query.field.is_none() {
+            // take the segment separator before the segment with it (MSH is always first, so
+            // there is one)
+            message.source.replace_range(range.start - 1..range.end, "");
+        } else {
+            message.source.replace_range(range, "");
+        }
+    }
+    reparse(&message.source, deletion)
+}
 
-        if let Some(range) = range {
-            let value = map.to.reify(query);
-            message.source.replace_range(range, &value);
-            message = ParsedMessageOwned::parse(&message.source, false)
-                .wrap_err_with(|| format!("Failed to re-parse message after applying map {map}"))?;
+fn apply_insertion(
+    mut message: ParsedMessageOwned,
+    insertion: &Insertion,
+) -> Result<ParsedMessageOwned> {
+    let after = message
+        .segments
+        .get(&insertion.after)
+        .and_then(|segments| segments.get(insertion.occurrence.get() - 1))
+        .map(|segment| segment.range.end);
+    match after {
+        Some(position) => {
+            message
+                .source
+                .insert_str(position, &format!("\r{}", insertion.segment));
+            reparse(&message.source, insertion)
+        }
+        None => {
+            eprintln!(
+                "warning: insertion {insertion} had no effect: the message doesn't have {} {} segment(s)",
+                insertion.occurrence, insertion.after
+            );
+            Ok(message)
         }
     }
-    Ok(message)
 }
 
 fn open_stdout(cli: &Cli) -> StandardStream {
@@ -99,32 +543,47 @@ fn open_stdout(cli: &Cli) -> StandardStream {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{cli::OutputMode, map::*};
+    use crate::{
+        cli::{InputFormat, OutputMode},
+        map::*,
+    };
     use clap::ColorChoice;
-    use hl7_parser::LocationQuery;
 
     #[test]
     fn can_map() {
         let input = "MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.3|||\rPID|1|2|3|4|5|6|7|8|9|10|11|12|13|14|15|16\r";
+        let maps = vec![
+            ValueMap {
+                from: ValueMapFrom(LocationQuery::new_field_repeat("MSH", 10, 1).unwrap().into()),
+                to: ValueMapTo::Explicit("XXX".to_string()),
+            },
+            ValueMap {
+                from: ValueMapFrom(LocationQuery::new_field_repeat("MSH", 7, 1).unwrap().into()),
+                to: ValueMapTo::Explicit("123".to_string()),
+            },
+        ];
         let cli = Cli {
-            map: vec![
-                ValueMap {
-                    from: ValueMapFrom(LocationQuery::new_field_repeat("MSH", 10, 1).unwrap()),
-                    to: ValueMapTo::Explicit("XXX".to_string()),
-                },
-                ValueMap {
-                    from: ValueMapFrom(LocationQuery::new_field_repeat("MSH", 7, 1).unwrap()),
-                    to: ValueMapTo::Explicit("123".to_string()),
-                },
-            ],
+            operations: maps.iter().cloned().map(Operation::Map).collect(),
+            map: maps,
+            delete: vec![],
+            insert: vec![],
+            script: None,
+            anonymize: false,
+            anonymize_seed: None,
+            anonymize_fields: vec![],
+            command: None,
             no_correct_newlines: false,
             colour: ColorChoice::Never,
             input: None,
+            input_format: InputFormat::HL7,
             output: OutputMode::HL7,
+            annotate: false,
             query: vec![],
+            validate: false,
+            create_segments: false,
         };
         let message = ParsedMessageOwned::parse(input, false).unwrap();
-        let message = apply_maps(message, &cli).unwrap();
+        let message = apply_operations(message, &cli).unwrap();
         assert_eq!(
             message.source,
             "MSH|^~\\&|AccMgr|1|||123||ADT^A01|XXX|P|2.3|||\rPID|1|2|3|4|5|6|7|8|9|10|11|12|13|14|15|16\r"
@@ -1,12 +1,18 @@
+use crate::{
+    expression::Template,
+    filter::Filter,
+    generate::generate_value,
+    query::{Query, Selector},
+};
 use chrono::Local;
-use hl7_parser::LocationQuery;
-use std::str::FromStr;
+use hl7_parser::{LocationQuery, ParsedMessageOwned, Separators};
+use std::{num::NonZeroUsize, str::FromStr};
 
 #[derive(Debug, Clone)]
-pub struct ValueMapFrom(pub LocationQuery);
+pub struct ValueMapFrom(pub Query);
 
 impl std::ops::Deref for ValueMapFrom {
-    type Target = LocationQuery;
+    type Target = Query;
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -18,6 +24,8 @@ pub enum ValueMapTo {
     Auto,
     Now,
     Explicit(String),
+    /// A value built from other fields of the message (ex: `{PID-3.1}-{MSH-7}`)
+    Expression(Template),
 }
 
 #[derive(Debug, Clone)]
@@ -33,11 +41,25 @@ impl FromStr for ValueMap {
         let mut parts = s.splitn(2, '=');
         let from = parts.next().ok_or_else(|| "missing from".to_string())?;
         let to = parts.next().ok_or_else(|| "missing to".to_string())?;
-        let from = LocationQuery::from_str(from)?;
+        let from = Query::from_str(from)?;
+        if !from.is_single() {
+            return Err(format!("maps can only set a single value, not {from}"));
+        }
         let to = match to {
             "<auto>" => ValueMapTo::Auto,
             "<now>" => ValueMapTo::Now,
-            s => ValueMapTo::Explicit(s.to_string()),
+            s if !s.contains(['{', '}']) => ValueMapTo::Explicit(s.to_string()),
+            // values which happen to contain braces (ex: JSON in OBX-5) are kept as they are
+            s => match Template::from_str(s) {
+                Ok(template) if template.has_expressions() => ValueMapTo::Expression(template),
+                Ok(_) => ValueMapTo::Explicit(s.to_string()),
+                Err(_) => {
+                    eprintln!(
+                        "warning: map value {s:?} isn't a valid expression, using it as it is"
+                    );
+                    ValueMapTo::Explicit(s.to_string())
+                }
+            },
         };
         Ok(ValueMap {
             from: ValueMapFrom(from),
@@ -47,29 +69,181 @@ impl FromStr for ValueMap {
 }
 
 impl ValueMapTo {
-    pub fn reify(&self, location: &LocationQuery) -> String {
-        match self {
-            ValueMapTo::Auto => {
-                // TODO: generate based on defintion of field
-                if *location == LocationQuery::new_field_repeat("MSH", 7, 1).unwrap() {
-                    // message time
-                    let now = Local::now();
-                    now.format("%Y%m%d%H%M%S").to_string()
-                } else if *location == LocationQuery::new_field_repeat("MSH", 10, 1).unwrap() {
-                    // control ID
-                    use rand::distributions::{Alphanumeric, DistString};
-                    Alphanumeric.sample_string(&mut rand::thread_rng(), 20)
-                } else {
-                    use rand::distributions::{Alphanumeric, DistString};
-                    Alphanumeric.sample_string(&mut rand::thread_rng(), 8)
-                }
-            }
+    /// Produce the value to write into `location` of `message`
+    pub fn reify(
+        &self,
+        location: &LocationQuery,
+        message: &ParsedMessageOwned,
+    ) -> Result<String, String> {
+        Ok(match self {
+            ValueMapTo::Auto => generate_value(message, location),
             ValueMapTo::Now => {
                 let now = Local::now();
                 now.format("%Y%m%d%H%M%S").to_string()
             }
             ValueMapTo::Explicit(s) => s.clone(),
+            ValueMapTo::Expression(template) => template.evaluate(message)?,
+        })
+    }
+}
+
+/// A location to delete: fields, repeats, components, and sub-components are cleared, while a
+/// segment on its own removes segments. Occurrences of a segment and repeats of a field can be
+/// picked out with the same selectors as queries (ex: `OBX[2].5`, `OBX[3.1=NOTE]`).
+#[derive(Debug, Clone)]
+pub struct Deletion(pub Query);
+
+impl FromStr for Deletion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut query = Query::from_str(s)?;
+        if query.field.is_none() {
+            // deleting `NTE` removes every NTE segment
+            if !query.has_segment_selector() {
+                query.segment_selector = Some(Selector::All);
+            }
+        } else if query.component.is_none()
+            && query.sub_component.is_none()
+            && !query.has_repeat_selector()
+        {
+            // queries default to the first repeat, but deleting `PID-3` should clear every
+            // repeat of the field
+            query.repeat_selector = None;
+            query.location.repeat = None;
+        }
+        Ok(Deletion(query))
+    }
+}
+
+/// A segment to insert after an occurrence of another segment (ex: `OBX[2]=NTE|1||Note`)
+#[derive(Debug, Clone)]
+pub struct Insertion {
+    /// The identifier of the segment to insert after
+    pub after: String,
+    /// The 1-based occurrence of the segment to insert after
+    pub occurrence: NonZeroUsize,
+    /// The source of the new segment
+    pub segment: String,
+}
+
+impl FromStr for Insertion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (after, segment) = s
+            .split_once('=')
+            .ok_or_else(|| "missing segment to insert".to_string())?;
+        let (after, occurrence) = match after.split_once('[') {
+            Some((after, occurrence)) => {
+                let occurrence = occurrence
+                    .strip_suffix(']')
+                    .and_then(|o| o.parse::<NonZeroUsize>().ok())
+                    .ok_or_else(|| format!("invalid segment occurrence: {after}[{occurrence}"))?;
+                (after, occurrence)
+            }
+            None => (after, NonZeroUsize::new(1).expect("1 is non-zero")),
+        };
+        if !is_segment_id(after) {
+            return Err(format!("invalid segment identifier: {after}"));
+        }
+        if !segment.get(..3).is_some_and(is_segment_id) {
+            return Err(format!(
+                "segment must start with a segment identifier: {segment}"
+            ));
+        }
+        Ok(Insertion {
+            after: after.to_uppercase(),
+            occurrence,
+            segment: segment.to_string(),
+        })
+    }
+}
+
+fn is_segment_id(s: &str) -> bool {
+    s.len() == 3 && s.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// An edit to make to each message, in the order given on the command line (or in a script)
+#[derive(Debug, Clone)]
+pub enum Operation {
+    Map(ValueMap),
+    /// A map which is only applied to messages matching the filter
+    ConditionalMap {
+        map: ValueMap,
+        when: Box<Filter>,
+    },
+    Delete(Deletion),
+    Insert(Insertion),
+}
+
+/// Add any fields, repeats, components, and sub-components missing from the source of a segment
+/// so that `location` exists within it (ex: padding a PID which ends at field 16 so that PID-30
+/// can be set)
+///
+/// Anything already in the segment is left untouched. MSH-1 and MSH-2 are the separators
+/// themselves, so are never added.
+pub fn expand_segment(segment: &str, separators: &Separators, location: &LocationQuery) -> String {
+    let Some(field) = location.field.map(|f| f.get()) else {
+        return segment.to_string();
+    };
+    // MSH-1 is the field separator itself, so MSH fields are one position closer to the
+    // segment identifier than other segments' fields
+    let field = if location.segment == "MSH" {
+        field - 1
+    } else {
+        field
+    };
+
+    let repeat = match (location.repeat, location.component, location.sub_component) {
+        (Some(repeat), _, _) => Some(repeat.get()),
+        (None, Some(_), _) | (None, _, Some(_)) => Some(1),
+        (None, None, None) => None,
+    };
+    let component = match (location.component, location.sub_component) {
+        (Some(component), _) => Some(component.get()),
+        (None, Some(_)) => Some(1),
+        (None, None) => None,
+    };
+    let sub_component = location.sub_component.map(|s| s.get());
+
+    let mut fields: Vec<String> = segment
+        .split(separators.field)
+        .map(|f| f.to_string())
+        .collect();
+    pad(&mut fields, field + 1);
+    let Some(repeat) = repeat else {
+        return fields.join(&separators.field.to_string());
+    };
+
+    let mut repeats: Vec<String> = fields[field]
+        .split(separators.repeat)
+        .map(|r| r.to_string())
+        .collect();
+    pad(&mut repeats, repeat);
+    if let Some(component) = component {
+        let mut components: Vec<String> = repeats[repeat - 1]
+            .split(separators.component)
+            .map(|c| c.to_string())
+            .collect();
+        pad(&mut components, component);
+        if let Some(sub_component) = sub_component {
+            let mut sub_components: Vec<String> = components[component - 1]
+                .split(separators.subcomponent)
+                .map(|s| s.to_string())
+                .collect();
+            pad(&mut sub_components, sub_component);
+            components[component - 1] = sub_components.join(&separators.subcomponent.to_string());
         }
+        repeats[repeat - 1] = components.join(&separators.component.to_string());
+    }
+    fields[field] = repeats.join(&separators.repeat.to_string());
+    fields.join(&separators.field.to_string())
+}
+
+fn pad(values: &mut Vec<String>, len: usize) {
+    while values.len() < len {
+        values.push(String::new());
     }
 }
 
@@ -85,12 +259,97 @@ impl std::fmt::Display for ValueMapTo {
             ValueMapTo::Auto => write!(f, "<auto>"),
             ValueMapTo::Now => write!(f, "<now>"),
             ValueMapTo::Explicit(s) => write!(f, "{}", s),
+            ValueMapTo::Expression(template) => write!(f, "{}", template),
         }
     }
 }
 
+impl std::fmt::Display for Deletion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::fmt::Display for Insertion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}[{}]={}", self.after, self.occurrence, self.segment)
+    }
+}
+
 impl std::fmt::Display for ValueMap {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{from}={to}", from = self.from, to = self.to)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(segment: &str, location: &str) -> String {
+        let separators = Separators::default();
+        let location = LocationQuery::from_str(location).expect("valid location");
+        expand_segment(segment, &separators, &location)
+    }
+
+    #[test]
+    fn can_expand_segments() {
+        assert_eq!(expand("PID|1|2", "PID-5"), "PID|1|2|||");
+        assert_eq!(expand("PID|1|2", "PID-2"), "PID|1|2");
+        assert_eq!(expand("PID|1|2", "PID-2[3]"), "PID|1|2~~");
+        assert_eq!(expand("PID|1|2^3", "PID-2.4"), "PID|1|2^3^^");
+        assert_eq!(expand("PID|1|2~3", "PID-2[2].2.3"), "PID|1|2~3^&&");
+        assert_eq!(expand("PV1", "PV1-2.1"), "PV1||");
+        assert_eq!(expand("MSH|^~\\&|A", "MSH-5"), "MSH|^~\\&|A||");
+        assert_eq!(expand("MSH|^~\\&|A", "MSH-2"), "MSH|^~\\&|A");
+    }
+
+    #[test]
+    fn keeps_braces_in_plain_values() {
+        let to = |s: &str| ValueMap::from_str(s).expect("valid map").to;
+        assert!(matches!(to("OBX-5={MSH-3}"), ValueMapTo::Expression(_)));
+        for value in [r#"{"result": 5.4}"#, "{}", "{{braces}}", "a}b"] {
+            match to(&format!("OBX-5={value}")) {
+                ValueMapTo::Explicit(explicit) => assert_eq!(explicit, value),
+                to => panic!("{value:?} was parsed as {to:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn can_parse_deletions_and_insertions() {
+        let deletion = |s: &str| Deletion::from_str(s).expect("valid deletion").0;
+        let index = |i: usize| Some(Selector::Index(NonZeroUsize::new(i).unwrap()));
+        assert_eq!(deletion("PID-3").repeat_selector, None);
+        assert_eq!(deletion("PID-3[2]").repeat_selector, index(2));
+        assert_eq!(deletion("PID-3.1").repeat_selector, index(1));
+        assert_eq!(deletion("NTE").segment_selector, Some(Selector::All));
+        assert_eq!(deletion("OBX[2]").segment_selector, index(2));
+        let obx = deletion("OBX[2].5");
+        assert_eq!(obx.segment_selector, index(2));
+        assert_eq!(obx.repeat_selector, None);
+        assert_eq!(deletion("OBX[3.1=X].5").repeat_selector, None);
+        assert_eq!(deletion("OBX.5[2]").repeat_selector, index(2));
+        assert_eq!(deletion("OBX.5[*]").repeat_selector, Some(Selector::All));
+        assert!(matches!(
+            deletion("PID.3[5=MR]").repeat_selector,
+            Some(Selector::Where(_))
+        ));
+        assert!(Deletion::from_str("OBX[").is_err());
+
+        let insertion = Insertion::from_str("obx[2]=NTE|1||Note").expect("valid insertion");
+        assert_eq!(insertion.after, "OBX");
+        assert_eq!(insertion.occurrence.get(), 2);
+        assert_eq!(insertion.segment, "NTE|1||Note");
+        assert_eq!(
+            Insertion::from_str("PID=NTE")
+                .expect("valid insertion")
+                .occurrence
+                .get(),
+            1
+        );
+        assert!(Insertion::from_str("PID").is_err());
+        assert!(Insertion::from_str("PID[0]=NTE").is_err());
+        assert!(Insertion::from_str("PID=|1").is_err());
+    }
+}
@@ -0,0 +1,60 @@
+use super::table::{write_message_index, write_path, write_value};
+use crate::{
+    cli::Cli,
+    diff::{Difference, DifferenceKind},
+    open_stdout,
+};
+use color_eyre::eyre::{Context, Result};
+use std::io::Write;
+use termcolor::{Color, ColorSpec, WriteColor};
+
+/// Print the differences between each pair of messages, one per line
+///
+/// When there is more than one pair of messages, each line is prefixed with the 1-based index
+/// of the messages the difference was found in
+pub fn print_differences(differences: &[Vec<Difference>], cli: &Cli) -> Result<()> {
+    let mut stdout = open_stdout(cli);
+
+    let mut hl_removed = ColorSpec::new();
+    let mut hl_added = ColorSpec::new();
+    let mut hl_changed = ColorSpec::new();
+    hl_removed.set_fg(Some(Color::Red)).set_bold(true);
+    hl_added.set_fg(Some(Color::Green)).set_bold(true);
+    hl_changed.set_fg(Some(Color::Yellow)).set_bold(true);
+
+    for (i, message_differences) in differences.iter().enumerate() {
+        let message_index = if differences.len() > 1 {
+            Some(i + 1)
+        } else {
+            None
+        };
+        for difference in message_differences.iter() {
+            let (marker, hl_marker) = match difference.kind {
+                DifferenceKind::Removed => ("-", &hl_removed),
+                DifferenceKind::Added => ("+", &hl_added),
+                DifferenceKind::Changed => ("~", &hl_changed),
+            };
+            write_message_index(&mut stdout, message_index)?;
+            stdout
+                .set_color(hl_marker)
+                .wrap_err_with(|| "Failed to set stdout colour")?;
+            write!(stdout, "{marker}").wrap_err_with(|| "Failed to write to stdout")?;
+            stdout
+                .reset()
+                .wrap_err_with(|| "Failed to reset stdout colour")?;
+            write!(stdout, "\t").wrap_err_with(|| "Failed to write to stdout")?;
+            write_path(&mut stdout, &difference.path)?;
+            if let Some(path_b) = &difference.path_b {
+                write!(stdout, " -> ").wrap_err_with(|| "Failed to write to stdout")?;
+                write_path(&mut stdout, path_b)?;
+            }
+            write!(stdout, "\t").wrap_err_with(|| "Failed to write to stdout")?;
+            write_value(&mut stdout, difference.a.as_deref().unwrap_or_default())?;
+            write!(stdout, "\t").wrap_err_with(|| "Failed to write to stdout")?;
+            write_value(&mut stdout, difference.b.as_deref().unwrap_or_default())?;
+            writeln!(stdout).wrap_err_with(|| "Failed to write to stdout")?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use super::table::{write_message_index, write_path};
+use crate::{cli::Cli, grep::GrepMatch, open_stdout};
+use color_eyre::eyre::{Context, Result};
+use std::io::Write;
+use termcolor::{Color, ColorSpec, WriteColor};
+
+/// Print each match on its own line, with the file, message index, path, and value, with the
+/// parts of the value which matched highlighted
+pub fn print_grep_matches(matches: &[GrepMatch], cli: &Cli) -> Result<()> {
+    let mut stdout = open_stdout(cli);
+
+    let mut hl_file = ColorSpec::new();
+    let mut hl_value = ColorSpec::new();
+    let mut hl_match = ColorSpec::new();
+    hl_file.set_fg(Some(Color::Green));
+    hl_value.set_fg(Some(Color::White)).set_intense(true);
+    hl_match.set_fg(Some(Color::Red)).set_bold(true);
+
+    for m in matches.iter() {
+        stdout
+            .set_color(&hl_file)
+            .wrap_err_with(|| "Failed to set stdout colour")?;
+        write!(stdout, "{}", m.file).wrap_err_with(|| "Failed to write to stdout")?;
+        stdout
+            .reset()
+            .wrap_err_with(|| "Failed to reset stdout colour")?;
+        write!(stdout, "\t").wrap_err_with(|| "Failed to write to stdout")?;
+        write_message_index(&mut stdout, Some(m.message))?;
+        write_path(&mut stdout, &m.path)?;
+        write!(stdout, "\t").wrap_err_with(|| "Failed to write to stdout")?;
+
+        let mut end = 0;
+        for span in m.spans.iter() {
+            stdout
+                .set_color(&hl_value)
+                .wrap_err_with(|| "Failed to set stdout colour")?;
+            write!(stdout, "{}", &m.value[end..span.start])
+                .wrap_err_with(|| "Failed to write to stdout")?;
+            stdout
+                .set_color(&hl_match)
+                .wrap_err_with(|| "Failed to set stdout colour")?;
+            write!(stdout, "{}", &m.value[span.clone()])
+                .wrap_err_with(|| "Failed to write to stdout")?;
+            end = span.end;
+        }
+        stdout
+            .set_color(&hl_value)
+            .wrap_err_with(|| "Failed to set stdout colour")?;
+        write!(stdout, "{}", &m.value[end..]).wrap_err_with(|| "Failed to write to stdout")?;
+        stdout
+            .reset()
+            .wrap_err_with(|| "Failed to reset stdout colour")?;
+        writeln!(stdout).wrap_err_with(|| "Failed to write to stdout")?;
+    }
+
+    Ok(())
+}
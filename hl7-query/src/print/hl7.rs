@@ -1,12 +1,20 @@
 use crate::cli::Cli;
 use color_eyre::eyre::{Context, Result};
 use hl7_parser::ParsedMessageOwned;
-use termcolor::{Color, ColorSpec, WriteColor};
+use termcolor::{Color, ColorSpec, StandardStream, WriteColor};
 use crate::open_stdout;
 use std::ops::Range;
 use std::io::Write;
 
-pub fn print_message_hl7(message: ParsedMessageOwned, cli: &Cli) -> Result<()> {
+pub fn print_message_hl7(messages: &[ParsedMessageOwned], cli: &Cli) -> Result<()> {
+    let mut stdout = open_stdout(cli);
+    for message in messages.iter() {
+        write_message_hl7(&mut stdout, message)?;
+    }
+    Ok(())
+}
+
+fn write_message_hl7(stdout: &mut StandardStream, message: &ParsedMessageOwned) -> Result<()> {
     let mut hl_segment = ColorSpec::new();
     let mut hl_special_char = ColorSpec::new();
     let mut hl_number = ColorSpec::new();
@@ -17,8 +25,6 @@ pub fn print_message_hl7(message: ParsedMessageOwned, cli: &Cli) -> Result<()> {
     hl_number.set_fg(Some(Color::White));
     hl_value.set_fg(Some(Color::White)).set_intense(true);
 
-    let mut stdout = open_stdout(cli);
-
     // this is awful but it basically works
     let mut value_ranges: Vec<Range<usize>> = Vec::new();
     let mut number_ranges: Vec<Range<usize>> = Vec::new();
@@ -86,7 +92,7 @@ pub fn print_message_hl7(message: ParsedMessageOwned, cli: &Cli) -> Result<()> {
     stdout
         .reset()
         .wrap_err_with(|| "Failed to reset stdout colour")?;
-    println!();
+    writeln!(stdout).wrap_err_with(|| "Failed to write new line to stdout")?;
 
     Ok(())
 }
@@ -1,4 +1,4 @@
-use crate::cli::Cli;
+use crate::{cli::Cli, segments::segments_in_order};
 use clap::ColorChoice;
 use color_eyre::eyre::{Context, Result};
 use hl7_parser::ParsedMessageOwned;
@@ -14,121 +14,120 @@ use termcolor::{Color, ColorSpec, WriteColor};
 const JSON_SYNTAX: &str = include_str!("../../assets/JSON.sublime-syntax");
 const THEME: &[u8] = include_bytes!("../../assets/ansi.tmTheme");
 
-fn sub_components_to_json(
-    sub_components: &[hl7_parser::SubComponent],
-    source: &str,
-) -> Option<Value> {
-    if sub_components.len() == 1 {
-        let value = sub_components[0].source(source);
-        if value.is_empty() {
-            None
-        } else {
-            Some(Value::String(value.to_string()))
-        }
+fn value_to_json(value: &str, message: &ParsedMessageOwned) -> Option<Value> {
+    if value.is_empty() {
+        None
     } else {
-        Some(Value::Object(
-            sub_components
-                .iter()
-                .enumerate()
-                .filter_map(|(i, sub_component)| {
-                    let value = sub_component.source(source);
-                    if value.is_empty() {
-                        None
-                    } else {
-                        Some((format!("{}", i + 1), Value::String(value.to_string())))
-                    }
-                })
-                .collect(),
-        ))
+        Some(Value::String(message.separators.decode(value)))
     }
 }
 
-fn components_to_json(components: &[hl7_parser::Component], source: &str) -> Option<Value> {
-    if components.len() == 1 {
-        sub_components_to_json(&components[0].sub_components, source)
+/// Collect values into an object keyed by their 1-based index, leaving out empty values
+fn indexed_object(values: impl Iterator<Item = Option<Value>>) -> Option<Value> {
+    let object: Map<String, Value> = values
+        .enumerate()
+        .filter_map(|(i, value)| value.map(|value| (format!("{}", i + 1), value)))
+        .collect();
+    if object.is_empty() {
+        None
     } else {
-        Some(Value::Object(
-            components
-                .iter()
-                .enumerate()
-                .filter_map(|(i, component)| {
-                    sub_components_to_json(&component.sub_components, source)
-                        .map(|value| (format!("{}", i + 1), value))
-                })
-                .collect(),
-        ))
+        Some(Value::Object(object))
     }
 }
 
-fn repeats_to_json(repeats: &[hl7_parser::Repeat], source: &str) -> Option<Value> {
-    if repeats.len() == 1 {
-        components_to_json(&repeats[0].components, source)
+fn sub_components_to_json(
+    sub_components: &[hl7_parser::SubComponent],
+    message: &ParsedMessageOwned,
+) -> Option<Value> {
+    if sub_components.len() == 1 {
+        value_to_json(sub_components[0].source(&message.source), message)
     } else {
-        Some(Value::Object(
-            repeats
+        indexed_object(
+            sub_components
                 .iter()
-                .enumerate()
-                .filter_map(|(i, repeat)| {
-                    components_to_json(&repeat.components, source)
-                        .map(|value| (format!("{}", i + 1), value))
-                })
-                .collect(),
-        ))
+                .map(|sub_component| value_to_json(sub_component.source(&message.source), message)),
+        )
     }
 }
 
-fn fields_to_json(fields: &[hl7_parser::Field], source: &str) -> Option<Value> {
-    if fields.len() == 1 {
-        repeats_to_json(&fields[0].repeats, source)
-    } else {
-        Some(Value::Object(
-            fields
+fn components_to_json(
+    components: &[hl7_parser::Component],
+    message: &ParsedMessageOwned,
+) -> Option<Value> {
+    match components {
+        // a single component is only collapsed when it is a plain value, otherwise its
+        // sub-components would be read back in as components
+        [component] if component.sub_components.len() <= 1 => {
+            sub_components_to_json(&component.sub_components, message)
+        }
+        components => indexed_object(
+            components
                 .iter()
-                .enumerate()
-                .filter_map(|(i, field)| {
-                    repeats_to_json(&field.repeats, source)
-                        .map(|value| (format!("{}", i + 1), value))
-                })
-                .collect(),
-        ))
+                .map(|component| sub_components_to_json(&component.sub_components, message)),
+        ),
     }
 }
 
-fn segments_to_json(segments: &[hl7_parser::Segment], source: &str) -> Value {
-    if segments.len() == 1 {
-        fields_to_json(&segments[0].fields, source).unwrap_or(Value::Null)
-    } else {
-        Value::Object(
-            segments
+/// Convert a field to JSON
+///
+/// Repeats are represented as an array (with `null` for empty repeats) so that they can't be
+/// confused with components when reading the JSON back in
+fn field_to_json(field: &hl7_parser::Field, message: &ParsedMessageOwned) -> Option<Value> {
+    match field.repeats.as_slice() {
+        // MSH-1 and MSH-2 hold the separators themselves and aren't split into repeats
+        [] => Some(Value::String(field.source(&message.source).to_string())),
+        [repeat] => components_to_json(&repeat.components, message),
+        repeats => {
+            let repeats: Vec<Value> = repeats
                 .iter()
-                .enumerate()
-                .filter_map(|(i, segment)| {
-                    fields_to_json(&segment.fields, source)
-                        .map(|value| (format!("{}", i + 1), value))
+                .map(|repeat| {
+                    components_to_json(&repeat.components, message).unwrap_or(Value::Null)
                 })
-                .collect(),
-        )
+                .collect();
+            if repeats.iter().all(Value::is_null) {
+                None
+            } else {
+                Some(Value::Array(repeats))
+            }
+        }
     }
 }
 
+fn fields_to_json(fields: &[hl7_parser::Field], message: &ParsedMessageOwned) -> Value {
+    indexed_object(fields.iter().map(|field| field_to_json(field, message)))
+        .unwrap_or_else(|| Value::Object(Map::new()))
+}
+
+/// Convert a message to a JSON array of segments, in the order they appear in the message
+///
+/// Each segment is an object with a single key (the segment identifier) so that repeated
+/// segments (ex: `IN1` / `IN2` pairs) keep their position relative to each other
 fn message_to_json(message: &ParsedMessageOwned) -> Value {
-    let tree: Map<String, Value> = message
-        .segments
-        .iter()
-        .map(|(segment_name, segments)| {
-            (
-                segment_name.to_string(),
-                segments_to_json(segments, &message.source),
-            )
-        })
-        .collect();
+    Value::Array(
+        segments_in_order(message)
+            .into_iter()
+            .map(|segment| {
+                let fields = fields_to_json(&segment.segment.fields, message);
+                let mut object = Map::new();
+                object.insert(segment.id.to_string(), fields);
+                Value::Object(object)
+            })
+            .collect(),
+    )
+}
 
-    Value::Object(tree)
+/// Print the messages as JSON
+///
+/// The output is always an array of messages in the order they appeared in the input, even
+/// when there is only one message, so that its shape doesn't depend on the input
+pub fn print_message_json(messages: &[ParsedMessageOwned], cli: &Cli) -> Result<()> {
+    let json = Value::Array(messages.iter().map(message_to_json).collect());
+    print_json(&json, cli)
 }
 
-pub fn print_message_json(message: ParsedMessageOwned, cli: &Cli) -> Result<()> {
-    let json = message_to_json(&message);
-    let json = serde_json::to_string_pretty(&json).wrap_err_with(|| "Can't serialize JSON")?;
+/// Pretty-print a JSON value, syntax highlighting it unless colour is disabled
+pub(super) fn print_json(json: &Value, cli: &Cli) -> Result<()> {
+    let json = serde_json::to_string_pretty(json).wrap_err_with(|| "Can't serialize JSON")?;
 
     if cli.colour == ColorChoice::Never {
         print!("{json}");
@@ -1,8 +1,18 @@
+mod diff;
+pub use diff::print_differences;
+mod grep;
+pub use grep::print_grep_matches;
 mod hl7;
 pub use hl7::print_message_hl7;
 mod json;
 pub use json::print_message_json;
+mod stats;
+pub use stats::print_stats;
 mod table;
 pub use table::print_message_table;
 mod query;
 pub use query::print_query_results;
+mod validation;
+pub use validation::print_validation_results;
+mod xml;
+pub use xml::print_message_xml;
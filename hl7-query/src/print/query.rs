@@ -1,46 +1,195 @@
-use crate::cli::Cli;
+use super::json::print_json;
+use crate::cli::{Cli, OutputMode};
 use color_eyre::eyre::{Context, Result};
 use hl7_parser::ParsedMessageOwned;
-use termcolor::{Color, ColorSpec, WriteColor};
+use serde_json::{Map, Value};
+use termcolor::{Color, ColorSpec, StandardStream, WriteColor};
 use crate::open_stdout;
-use std::io::Write;
+use std::{borrow::Cow, io::Write};
+
+pub fn print_query_results(messages: &[ParsedMessageOwned], cli: &Cli) -> Result<()> {
+    match cli.output {
+        OutputMode::Json => return print_query_json(messages, cli),
+        OutputMode::Csv => return print_query_records(messages, cli, ',', csv_value),
+        OutputMode::Tsv => return print_query_records(messages, cli, '\t', tsv_value),
+        OutputMode::HL7 | OutputMode::Table | OutputMode::Xml => {}
+    }
 
-pub fn print_query_results(message: ParsedMessageOwned, cli: &Cli) -> Result<()> {
     let mut stdout = open_stdout(cli);
+    for message in messages.iter() {
+        write_query_results(&mut stdout, message, cli)?;
+    }
+    Ok(())
+}
+
+fn write_query_results(
+    stdout: &mut StandardStream,
+    message: &ParsedMessageOwned,
+    cli: &Cli,
+) -> Result<()> {
     for query in cli.query.iter() {
-        let value = message.query_value(query).wrap_err_with(|| {
-            format!(
-                "Failed to query message for {:?} (query: {:?})",
-                query, query
-            )
-        })?;
-        if let Some(value) = value {
-            let value = message.separators.decode(value);
-
-            let mut hl_special_char = ColorSpec::new();
-            let mut hl_value = ColorSpec::new();
-            hl_special_char.set_fg(Some(Color::Black)).set_intense(true);
-            hl_value.set_fg(Some(Color::White)).set_intense(true);
-
-            for c in value.chars() {
-                if message.separators.is_special_char(c) {
-                    stdout
-                        .set_color(&hl_special_char)
-                        .wrap_err_with(|| "Failed to set stdout colour")?;
-                }
-                else {
-                    stdout
-                        .set_color(&hl_value)
-                        .wrap_err_with(|| "Failed to set stdout colour")?;
+        // queries selecting many values print a line per match, and every query prints an
+        // empty line when nothing matches so that each query's output stays on its own line
+        let values: Vec<Option<&str>> = if query.is_single() {
+            vec![query.value(message)]
+        } else {
+            match query.values(message) {
+                values if values.is_empty() => vec![None],
+                values => values.into_iter().map(Some).collect(),
+            }
+        };
+        for value in values {
+            if let Some(value) = value {
+                let value = message.separators.decode(value);
+
+                let mut hl_special_char = ColorSpec::new();
+                let mut hl_value = ColorSpec::new();
+                hl_special_char.set_fg(Some(Color::Black)).set_intense(true);
+                hl_value.set_fg(Some(Color::White)).set_intense(true);
+
+                for c in value.chars() {
+                    if message.separators.is_special_char(c) {
+                        stdout
+                            .set_color(&hl_special_char)
+                            .wrap_err_with(|| "Failed to set stdout colour")?;
+                    }
+                    else {
+                        stdout
+                            .set_color(&hl_value)
+                            .wrap_err_with(|| "Failed to set stdout colour")?;
+                    }
+                    write!(stdout, "{c}").wrap_err_with(|| "Failed to write to stdout")?;
                 }
-                write!(stdout, "{c}").wrap_err_with(|| "Failed to write to stdout")?;
+                stdout.reset().wrap_err_with(|| "Failed to reset stdout colour")?;
+                writeln!(stdout)
+                    .wrap_err_with(|| "Failed to write to stdout")?;
+            } else {
+                writeln!(stdout).wrap_err_with(|| "Failed to write to stdout")?;
             }
-            stdout.reset().wrap_err_with(|| "Failed to reset stdout colour")?;
-            writeln!(stdout)
-                .wrap_err_with(|| "Failed to write to stdout")?;
-        } else {
-            writeln!(stdout).wrap_err_with(|| "Failed to write to stdout")?;
         }
     }
     Ok(())
 }
+
+/// The decoded results of a query for a message
+enum QueryValues {
+    /// The value selected by a single value query, or `None` if it isn't in the message
+    Single(Option<String>),
+    /// Every value selected by a query using `*` or filters
+    Many(Vec<String>),
+}
+
+fn query_values(message: &ParsedMessageOwned, cli: &Cli) -> Vec<QueryValues> {
+    let decode = |value: &str| message.separators.decode(value);
+    cli.query
+        .iter()
+        .map(|query| {
+            if query.is_single() {
+                QueryValues::Single(query.value(message).map(decode))
+            } else {
+                QueryValues::Many(query.values(message).into_iter().map(decode).collect())
+            }
+        })
+        .collect()
+}
+
+/// Print an object for each message, keyed by the queries. Missing values are `null`, and
+/// queries which select many values are printed as arrays.
+///
+/// The output is always an array of objects in the order the messages appeared in the input,
+/// even when there is only one message
+fn print_query_json(messages: &[ParsedMessageOwned], cli: &Cli) -> Result<()> {
+    let record = |message: &ParsedMessageOwned| -> Value {
+        let object: Map<String, Value> = cli
+            .query
+            .iter()
+            .zip(query_values(message, cli))
+            .map(|(query, values)| {
+                let value = match values {
+                    QueryValues::Single(value) => value.map(Value::String).unwrap_or(Value::Null),
+                    QueryValues::Many(values) => {
+                        Value::Array(values.into_iter().map(Value::String).collect())
+                    }
+                };
+                (query.as_str().to_string(), value)
+            })
+            .collect();
+        Value::Object(object)
+    };
+    let json = Value::Array(messages.iter().map(record).collect());
+    print_json(&json, cli)
+}
+
+/// Print a header row of the queries, followed by a row of values for each message
+fn print_query_records(
+    messages: &[ParsedMessageOwned],
+    cli: &Cli,
+    delimiter: char,
+    format: fn(Option<&str>) -> Cow<'_, str>,
+) -> Result<()> {
+    let mut stdout = open_stdout(cli);
+    let mut write_row = |values: Vec<Option<&str>>| -> Result<()> {
+        let row: Vec<Cow<str>> = values.into_iter().map(format).collect();
+        writeln!(stdout, "{}", row.join(&delimiter.to_string()))
+            .wrap_err_with(|| "Failed to write to stdout")
+    };
+
+    write_row(cli.query.iter().map(|query| Some(query.as_str())).collect())?;
+    for message in messages.iter() {
+        // queries which select many values put each value on its own line within the cell
+        let values: Vec<Option<String>> = query_values(message, cli)
+            .into_iter()
+            .map(|values| match values {
+                QueryValues::Single(value) => value,
+                QueryValues::Many(values) if values.is_empty() => None,
+                QueryValues::Many(values) => Some(values.join("\n")),
+            })
+            .collect();
+        write_row(values.iter().map(|value| value.as_deref()).collect())?;
+    }
+    Ok(())
+}
+
+/// Quote a value for CSV (RFC 4180). Empty values are quoted so that they can be told apart
+/// from missing values, which are left blank.
+fn csv_value(value: Option<&str>) -> Cow<'_, str> {
+    match value {
+        None => Cow::Borrowed(""),
+        Some(value) if value.is_empty() || value.contains([',', '"', '\r', '\n']) => {
+            Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+        }
+        Some(value) => Cow::Borrowed(value),
+    }
+}
+
+/// Escape a value for TSV, using `\N` for missing values (as PostgreSQL and MySQL do)
+fn tsv_value(value: Option<&str>) -> Cow<'_, str> {
+    match value {
+        None => Cow::Borrowed("\\N"),
+        Some(value) if value.contains(['\\', '\t', '\r', '\n']) => Cow::Owned(
+            value
+                .replace('\\', "\\\\")
+                .replace('\t', "\\t")
+                .replace('\r', "\\r")
+                .replace('\n', "\\n"),
+        ),
+        Some(value) => Cow::Borrowed(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_escape_values() {
+        assert_eq!(csv_value(None), "");
+        assert_eq!(csv_value(Some("")), "\"\"");
+        assert_eq!(csv_value(Some("DUCK")), "DUCK");
+        assert_eq!(csv_value(Some("DUCK, DONALD \"D\"")), "\"DUCK, DONALD \"\"D\"\"\"");
+
+        assert_eq!(tsv_value(None), "\\N");
+        assert_eq!(tsv_value(Some("")), "");
+        assert_eq!(tsv_value(Some("a\tb\\c\nd")), "a\\tb\\\\c\\nd");
+    }
+}
@@ -0,0 +1,186 @@
+use super::{
+    json::print_json,
+    table::{write_path, write_value},
+};
+use crate::{
+    cli::{Cli, OutputMode, StatsArgs},
+    open_stdout,
+    stats::{is_z_segment, Stats},
+};
+use color_eyre::eyre::{eyre, Context, Result};
+use serde_json::{json, Map, Value};
+use std::io::Write;
+use termcolor::{ColorSpec, StandardStream, WriteColor};
+
+fn write_header(stdout: &mut StandardStream, columns: &[&str]) -> Result<()> {
+    let mut hl_header = ColorSpec::new();
+    hl_header.set_bold(true);
+
+    stdout
+        .set_color(&hl_header)
+        .wrap_err_with(|| "Failed to set stdout colour")?;
+    write!(stdout, "{}", columns.join("\t")).wrap_err_with(|| "Failed to write to stdout")?;
+    stdout
+        .reset()
+        .wrap_err_with(|| "Failed to reset stdout colour")?;
+    writeln!(stdout).wrap_err_with(|| "Failed to write to stdout")?;
+    Ok(())
+}
+
+fn field_path(segment: &str, field: usize) -> Vec<String> {
+    vec![segment.to_string(), field.to_string()]
+}
+
+fn percent(rate: f64) -> String {
+    format!("{:.1}%", rate * 100.0)
+}
+
+/// Print the statistics as a series of tables: the message types, the segments, how each field
+/// is populated, and any fields with values longer than the dictionary allows
+fn print_stats_table(stats: &Stats, args: &StatsArgs, cli: &Cli) -> Result<()> {
+    let mut stdout = open_stdout(cli);
+
+    writeln!(stdout, "Messages\t{}", stats.messages)
+        .wrap_err_with(|| "Failed to write to stdout")?;
+
+    writeln!(stdout).wrap_err_with(|| "Failed to write to stdout")?;
+    write_header(&mut stdout, &["Type", "Event", "Messages"])?;
+    for ((message_type, event), count) in stats.message_types.iter() {
+        write_value(&mut stdout, message_type)?;
+        write!(stdout, "\t").wrap_err_with(|| "Failed to write to stdout")?;
+        write_value(&mut stdout, event)?;
+        writeln!(stdout, "\t{count}").wrap_err_with(|| "Failed to write to stdout")?;
+    }
+
+    writeln!(stdout).wrap_err_with(|| "Failed to write to stdout")?;
+    write_header(&mut stdout, &["Segment", "Messages", "Count", "Name"])?;
+    for (id, segment) in stats.segments.iter() {
+        let name = match (&segment.name, is_z_segment(id)) {
+            (Some(name), _) => name.as_str(),
+            (None, true) => "(Z-segment)",
+            (None, false) => "",
+        };
+        write_path(&mut stdout, std::slice::from_ref(id))?;
+        writeln!(stdout, "\t{}\t{}\t{name}", segment.messages, segment.count)
+            .wrap_err_with(|| "Failed to write to stdout")?;
+    }
+
+    writeln!(stdout).wrap_err_with(|| "Failed to write to stdout")?;
+    write_header(
+        &mut stdout,
+        &["Field", "Filled", "Distinct", "Top values", "Name"],
+    )?;
+    for (id, segment) in stats.segments.iter() {
+        for (field_n, field) in segment.fields.iter() {
+            write_path(&mut stdout, &field_path(id, *field_n))?;
+            write!(
+                stdout,
+                "\t{}\t{}\t",
+                percent(field.fill_rate(segment.count)),
+                field.values.len()
+            )
+            .wrap_err_with(|| "Failed to write to stdout")?;
+            let top_values = field
+                .top_values(args.top)
+                .into_iter()
+                .map(|(value, count)| format!("{value} ({count})"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            write_value(&mut stdout, &top_values)?;
+            writeln!(stdout, "\t{}", field.name.as_deref().unwrap_or_default())
+                .wrap_err_with(|| "Failed to write to stdout")?;
+        }
+    }
+
+    let too_long = stats.too_long();
+    if !too_long.is_empty() {
+        writeln!(stdout).wrap_err_with(|| "Failed to write to stdout")?;
+        write_header(
+            &mut stdout,
+            &["Too long", "Max length", "Longest", "Count", "Name"],
+        )?;
+        for (id, field_n, field) in too_long {
+            write_path(&mut stdout, &field_path(id, field_n))?;
+            writeln!(
+                stdout,
+                "\t{}\t{}\t{}\t{}",
+                field.max_length.unwrap_or_default(),
+                field.longest,
+                field.too_long,
+                field.name.as_deref().unwrap_or_default()
+            )
+            .wrap_err_with(|| "Failed to write to stdout")?;
+        }
+    }
+
+    Ok(())
+}
+
+fn stats_to_json(stats: &Stats, args: &StatsArgs) -> Value {
+    let message_types: Vec<Value> = stats
+        .message_types
+        .iter()
+        .map(|((message_type, event), count)| {
+            json!({
+                "type": message_type,
+                "event": event,
+                "messages": count,
+            })
+        })
+        .collect();
+
+    let segments: Map<String, Value> = stats
+        .segments
+        .iter()
+        .map(|(id, segment)| {
+            let fields: Map<String, Value> = segment
+                .fields
+                .iter()
+                .map(|(field_n, field)| {
+                    let top_values: Vec<Value> = field
+                        .top_values(args.top)
+                        .into_iter()
+                        .map(|(value, count)| json!({ "value": value, "count": count }))
+                        .collect();
+                    let json = json!({
+                        "name": field.name,
+                        "populated": field.populated,
+                        "fill_rate": field.fill_rate(segment.count),
+                        "distinct": field.values.len(),
+                        "top_values": top_values,
+                        "max_length": field.max_length,
+                        "longest": field.longest,
+                        "too_long": field.too_long,
+                    });
+                    (field_n.to_string(), json)
+                })
+                .collect();
+            let json = json!({
+                "name": segment.name,
+                "z_segment": is_z_segment(id),
+                "messages": segment.messages,
+                "count": segment.count,
+                "fields": fields,
+            });
+            (id.clone(), json)
+        })
+        .collect();
+
+    json!({
+        "messages": stats.messages,
+        "message_types": message_types,
+        "segments": segments,
+    })
+}
+
+/// Print the statistics gathered from a set of messages, as a table (for `--output hl7` or
+/// `table`) or JSON
+pub fn print_stats(stats: &Stats, args: &StatsArgs, cli: &Cli) -> Result<()> {
+    match cli.output {
+        OutputMode::HL7 | OutputMode::Table => print_stats_table(stats, args, cli),
+        OutputMode::Json => print_json(&stats_to_json(stats, args), cli),
+        output => Err(eyre!(
+            "hq stats can only print a table or JSON, not --output {output}"
+        )),
+    }
+}
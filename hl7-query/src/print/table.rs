@@ -1,10 +1,14 @@
-use crate::cli::Cli;
+use crate::{
+    cli::Cli,
+    dictionary::{message_version, Annotation, Version},
+    segments::{segment_leaves, segments_in_order},
+};
 use color_eyre::eyre::{Context, Result};
 use hl7_parser::ParsedMessageOwned;
 use std::io::Write;
 use termcolor::{Color, ColorSpec, StandardStream, WriteColor};
 
-fn write_path(stdout: &mut StandardStream, path: &[String]) -> Result<()> {
+pub(super) fn write_path(stdout: &mut StandardStream, path: &[String]) -> Result<()> {
     let mut hl_segment = ColorSpec::new();
     let mut hl_special_char = ColorSpec::new();
     let mut hl_number = ColorSpec::new();
@@ -39,7 +43,7 @@ fn write_path(stdout: &mut StandardStream, path: &[String]) -> Result<()> {
     Ok(())
 }
 
-fn write_value(stdout: &mut StandardStream, value: &str) -> Result<()> {
+pub(super) fn write_value(stdout: &mut StandardStream, value: &str) -> Result<()> {
     let mut hl_value = ColorSpec::new();
     hl_value.set_fg(Some(Color::White)).set_intense(true);
 
@@ -53,77 +57,128 @@ fn write_value(stdout: &mut StandardStream, value: &str) -> Result<()> {
     Ok(())
 }
 
-fn write_path_value(stdout: &mut StandardStream, path: &[String], value: &str) -> Result<()> {
+pub(super) fn write_message_index(
+    stdout: &mut StandardStream,
+    message_index: Option<usize>,
+) -> Result<()> {
+    let Some(message_index) = message_index else {
+        return Ok(());
+    };
+
+    let mut hl_index = ColorSpec::new();
+    hl_index.set_fg(Some(Color::Magenta));
+
+    stdout
+        .set_color(&hl_index)
+        .wrap_err_with(|| "Failed to set stdout colour")?;
+    write!(stdout, "{message_index}").wrap_err_with(|| "Failed to write to stdout")?;
+    stdout
+        .reset()
+        .wrap_err_with(|| "Failed to reset stdout colour")?;
+    write!(stdout, "\t")?;
+    Ok(())
+}
+
+fn write_path_value(
+    stdout: &mut StandardStream,
+    message_index: Option<usize>,
+    path: &[String],
+    value: &str,
+    annotation: Option<&Annotation>,
+) -> Result<()> {
+    write_message_index(stdout, message_index)?;
     write_path(stdout, path)?;
     write!(stdout, "\t")?;
     write_value(stdout, value)?;
+    if let Some(annotation) = annotation {
+        write_annotation(stdout, value, annotation)?;
+    }
     writeln!(stdout)?;
     Ok(())
 }
 
-pub fn print_message_table(message: ParsedMessageOwned, cli: &Cli) -> Result<()> {
+fn write_annotation(
+    stdout: &mut StandardStream,
+    value: &str,
+    annotation: &Annotation,
+) -> Result<()> {
+    let mut hl_description = ColorSpec::new();
+    let mut hl_name = ColorSpec::new();
+    let mut hl_data_type = ColorSpec::new();
+
+    hl_description.set_fg(Some(Color::Black)).set_intense(true);
+    hl_name.set_fg(Some(Color::Green));
+    hl_data_type.set_fg(Some(Color::Blue));
+
+    if let Some(description) = annotation.describe(value) {
+        stdout
+            .set_color(&hl_description)
+            .wrap_err_with(|| "Failed to set stdout colour")?;
+        write!(stdout, " ({description})").wrap_err_with(|| "Failed to write to stdout")?;
+    }
+    stdout
+        .reset()
+        .wrap_err_with(|| "Failed to reset stdout colour")?;
+    write!(stdout, "\t")?;
+    stdout
+        .set_color(&hl_name)
+        .wrap_err_with(|| "Failed to set stdout colour")?;
+    write!(stdout, "{}", annotation.names.join(" / "))
+        .wrap_err_with(|| "Failed to write to stdout")?;
+    stdout
+        .reset()
+        .wrap_err_with(|| "Failed to reset stdout colour")?;
+    write!(stdout, "\t")?;
+    stdout
+        .set_color(&hl_data_type)
+        .wrap_err_with(|| "Failed to set stdout colour")?;
+    write!(stdout, "{}", annotation.data_type).wrap_err_with(|| "Failed to write to stdout")?;
+    stdout
+        .reset()
+        .wrap_err_with(|| "Failed to reset stdout colour")?;
+    Ok(())
+}
+
+/// Print the messages as a table of paths and unescaped values
+///
+/// When there is more than one message, each row is prefixed with the 1-based index of the
+/// message it came from
+pub fn print_message_table(messages: &[ParsedMessageOwned], cli: &Cli) -> Result<()> {
     let mut stdout = crate::open_stdout(cli);
 
-    let mut current_path: Vec<String> = Vec::new();
-    for (segment_name, segments) in message.segments.iter() {
-        for (segment_i, segment) in segments.iter().enumerate() {
-            let segment_name = if segments.len() > 1 {
-                format!("{}[{}]", segment_name, segment_i)
+    for (i, message) in messages.iter().enumerate() {
+        let message_index = if messages.len() > 1 {
+            Some(i + 1)
+        } else {
+            None
+        };
+        write_message_table(&mut stdout, message_index, message, cli.annotate)?;
+    }
+
+    Ok(())
+}
+
+fn write_message_table(
+    stdout: &mut StandardStream,
+    message_index: Option<usize>,
+    message: &ParsedMessageOwned,
+    annotate: bool,
+) -> Result<()> {
+    let version = message_version(message).unwrap_or(Version::V2_5_1);
+    for segment in segments_in_order(message) {
+        let segment_path = segment.path();
+        for leaf in segment_leaves(&message.source, &segment) {
+            let annotation = if annotate {
+                leaf.annotation(version, &segment)
             } else {
-                segment_name.to_string()
+                None
             };
-            current_path.push(segment_name);
-            for (field_i, field) in segment.fields.iter().enumerate() {
-                let field_name = format!("{}", field_i + 1);
-                current_path.push(field_name);
-                if field.repeats.is_empty() {
-                    let value = field.source(&message.source);
-                    if !value.is_empty() {
-                        write_path_value(&mut stdout, &current_path, value)?;
-                    }
-                } else {
-                    for (repeat_i, repeat) in field.repeats.iter().enumerate() {
-                        if field.repeats.len() > 1 {
-                            current_path.push(format!("[{}]", repeat_i + 1));
-                        }
-                        if repeat.components.is_empty() || repeat.components.len() == 1 {
-                            let value = repeat.source(&message.source);
-                            if !value.is_empty() {
-                                write_path_value(&mut stdout, &current_path, value)?;
-                            }
-                        } else {
-                            for (component_i, component) in repeat.components.iter().enumerate() {
-                                current_path.push(format!("{}", component_i + 1));
-                                if component.sub_components.is_empty()
-                                    || component.sub_components.len() == 1
-                                {
-                                    let value = component.source(&message.source);
-                                    if !value.is_empty() {
-                                        write_path_value(&mut stdout, &current_path, value)?;
-                                    }
-                                } else {
-                                    for (sub_component_i, sub_component) in
-                                        component.sub_components.iter().enumerate()
-                                    {
-                                        current_path.push(format!("{}", sub_component_i + 1));
-                                        let value = sub_component.source(&message.source);
-                                        if !value.is_empty() {
-                                            write_path_value(&mut stdout, &current_path, value)?;
-                                        }
-                                        current_path.pop();
-                                    }
-                                }
-                                current_path.pop();
-                            }
-                        }
-                        if field.repeats.len() > 1 {
-                            current_path.pop();
-                        }
-                    }
-                }
-                current_path.pop();
-            }
-            current_path.pop();
+            let path: Vec<String> = std::iter::once(segment_path.clone())
+                .chain(leaf.path)
+                .collect();
+            // values are unescaped, as they are when printed by `--query`
+            let value = message.separators.decode(leaf.value);
+            write_path_value(stdout, message_index, &path, &value, annotation.as_ref())?;
         }
     }
 
@@ -0,0 +1,60 @@
+use crate::{
+    cli::Cli,
+    open_stdout,
+    validate::{Issue, Severity},
+};
+use color_eyre::eyre::{Context, Result};
+use std::io::Write;
+use termcolor::{Color, ColorSpec, WriteColor};
+
+/// Print the issues found while validating each message, one per line
+///
+/// When there is more than one message, each line is prefixed with the 1-based index of the
+/// message the issue was found in
+pub fn print_validation_results(issues: &[Vec<Issue>], cli: &Cli) -> Result<()> {
+    let mut stdout = open_stdout(cli);
+
+    let mut hl_index = ColorSpec::new();
+    let mut hl_error = ColorSpec::new();
+    let mut hl_warning = ColorSpec::new();
+    let mut hl_path = ColorSpec::new();
+    hl_index.set_fg(Some(Color::Magenta));
+    hl_error.set_fg(Some(Color::Red)).set_bold(true);
+    hl_warning.set_fg(Some(Color::Yellow)).set_bold(true);
+    hl_path.set_fg(Some(Color::Cyan));
+
+    for (i, message_issues) in issues.iter().enumerate() {
+        for issue in message_issues.iter() {
+            if issues.len() > 1 {
+                stdout
+                    .set_color(&hl_index)
+                    .wrap_err_with(|| "Failed to set stdout colour")?;
+                write!(stdout, "{}", i + 1).wrap_err_with(|| "Failed to write to stdout")?;
+                stdout
+                    .reset()
+                    .wrap_err_with(|| "Failed to reset stdout colour")?;
+                write!(stdout, "\t").wrap_err_with(|| "Failed to write to stdout")?;
+            }
+
+            let hl_severity = match issue.severity {
+                Severity::Error => &hl_error,
+                Severity::Warning => &hl_warning,
+            };
+            stdout
+                .set_color(hl_severity)
+                .wrap_err_with(|| "Failed to set stdout colour")?;
+            write!(stdout, "{}", issue.severity).wrap_err_with(|| "Failed to write to stdout")?;
+            stdout
+                .set_color(&hl_path)
+                .wrap_err_with(|| "Failed to set stdout colour")?;
+            write!(stdout, "\t{}", issue.path).wrap_err_with(|| "Failed to write to stdout")?;
+            stdout
+                .reset()
+                .wrap_err_with(|| "Failed to reset stdout colour")?;
+            writeln!(stdout, "\t{}", issue.message)
+                .wrap_err_with(|| "Failed to write to stdout")?;
+        }
+    }
+
+    Ok(())
+}
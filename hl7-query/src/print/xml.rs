@@ -0,0 +1,428 @@
+use crate::{
+    cli::Cli,
+    dictionary::{dictionary, message_version, Version},
+    open_stdout,
+    segments::{segments_in_order, SegmentRef},
+    structure::{match_structure, StructureNode},
+};
+use color_eyre::eyre::{Context, Result};
+use hl7_parser::{Component, ParsedMessageOwned, Repeat, SubComponent};
+use std::io::Write;
+use termcolor::{Color, ColorSpec, WriteColor};
+
+const NAMESPACE: &str = "urn:hl7-org:v2xml";
+
+/// An XML element which holds either text or other elements
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Element {
+    name: String,
+    attributes: Vec<(&'static str, String)>,
+    content: Content,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Content {
+    Text(String),
+    Elements(Vec<Element>),
+}
+
+impl Element {
+    fn text<S: ToString>(name: S, text: String) -> Element {
+        Element {
+            name: name.to_string(),
+            attributes: Vec::new(),
+            content: Content::Text(text),
+        }
+    }
+
+    fn elements<S: ToString>(name: S, elements: Vec<Element>) -> Element {
+        Element {
+            name: name.to_string(),
+            attributes: Vec::new(),
+            content: Content::Elements(elements),
+        }
+    }
+}
+
+/// Print the messages using the HL7 v2.xml encoding
+///
+/// When there is more than one message, the messages are wrapped in a `<MESSAGES>` element so
+/// that the output is still a single XML document
+pub fn print_message_xml(messages: &[ParsedMessageOwned], cli: &Cli) -> Result<()> {
+    let mut stdout = open_stdout(cli);
+
+    let root = match messages {
+        [message] => message_to_xml(message),
+        messages => Element::elements("MESSAGES", messages.iter().map(message_to_xml).collect()),
+    };
+
+    writeln!(stdout, r#"<?xml version="1.0" encoding="UTF-8"?>"#)
+        .wrap_err_with(|| "Failed to write to stdout")?;
+    write_element(&mut stdout, &root, 0)
+}
+
+fn message_to_xml(message: &ParsedMessageOwned) -> Element {
+    let version = message_version(message).unwrap_or(Version::V2_5_1);
+
+    let (name, elements) = match match_structure(message, version) {
+        Some(structure) => (
+            structure.id.to_string(),
+            nodes_to_xml(message, version, &structure.nodes),
+        ),
+        None => {
+            // without a known structure, name the message after its type and leave the
+            // segments ungrouped
+            let message_type = message
+                .query_value("MSH.9.3")
+                .expect("valid query")
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .or_else(|| {
+                    let code = message.query_value("MSH.9.1").expect("valid query")?;
+                    let trigger = message.query_value("MSH.9.2").expect("valid query")?;
+                    Some(format!("{code}_{trigger}"))
+                })
+                .unwrap_or_else(|| "MESSAGE".to_string());
+            let elements = segments_in_order(message)
+                .iter()
+                .map(|segment| segment_to_xml(message, version, segment))
+                .collect();
+            (message_type, elements)
+        }
+    };
+
+    let mut root = Element::elements(name, elements);
+    root.attributes.push(("xmlns", NAMESPACE.to_string()));
+    root
+}
+
+fn nodes_to_xml(
+    message: &ParsedMessageOwned,
+    version: Version,
+    nodes: &[StructureNode],
+) -> Vec<Element> {
+    nodes
+        .iter()
+        .map(|node| match node {
+            StructureNode::Segment(segment) => segment_to_xml(message, version, segment),
+            StructureNode::Group { name, nodes } => {
+                Element::elements(name, nodes_to_xml(message, version, nodes))
+            }
+        })
+        .collect()
+}
+
+fn segment_to_xml(message: &ParsedMessageOwned, version: Version, segment: &SegmentRef) -> Element {
+    let definition = dictionary().segment(segment.id);
+    let mut elements = Vec::new();
+
+    for (i, field) in segment.segment.fields.iter().enumerate() {
+        let name = format!("{}.{}", segment.id, i + 1);
+
+        // MSH-1 and MSH-2 hold the separators themselves
+        if field.repeats.is_empty() {
+            elements.push(Element::text(
+                name,
+                field.source(&message.source).to_string(),
+            ));
+            continue;
+        }
+
+        let data_type = definition
+            .and_then(|d| d.fields(version).nth(i))
+            .map(|f| f.data_type(version));
+        for repeat in field.repeats.iter() {
+            if let Some(content) = repeat_to_xml(message, version, data_type, &name, repeat) {
+                elements.push(Element {
+                    name: name.clone(),
+                    attributes: Vec::new(),
+                    content,
+                });
+            }
+        }
+    }
+
+    Element::elements(segment.id, elements)
+}
+
+fn decode(message: &ParsedMessageOwned, source: &str) -> String {
+    message.separators.decode(source)
+}
+
+/// Convert a field repeat to XML, naming components after the field's data type. Values which
+/// don't fit the data type are named after their position instead (ex: `ZPI.1.2`).
+fn repeat_to_xml(
+    message: &ParsedMessageOwned,
+    version: Version,
+    data_type: Option<&str>,
+    name: &str,
+    repeat: &Repeat,
+) -> Option<Content> {
+    if repeat.source(&message.source).is_empty() {
+        return None;
+    }
+
+    let definition = data_type.and_then(|t| dictionary().data_type(version, t));
+    let composite = definition.filter(|d| !d.is_primitive());
+
+    let Some(composite) = composite else {
+        if let [component] = repeat.components.as_slice() {
+            if component.sub_components.len() == 1 {
+                return Some(Content::Text(decode(
+                    message,
+                    component.source(&message.source),
+                )));
+            }
+        }
+        return Some(generic_components(message, name, &repeat.components));
+    };
+
+    let data_type = version.data_type(data_type.expect("composite has a data type"));
+    let mut elements = Vec::new();
+    for (i, component) in repeat.components.iter().enumerate() {
+        if component.source(&message.source).is_empty() {
+            continue;
+        }
+        let name = format!("{data_type}.{}", i + 1);
+        let component_type = composite
+            .components
+            .get(i)
+            .map(|c| version.data_type(&c.data_type));
+        elements.push(Element {
+            content: component_to_xml(message, version, component_type, &name, component),
+            name,
+            attributes: Vec::new(),
+        });
+    }
+    Some(Content::Elements(elements))
+}
+
+fn component_to_xml(
+    message: &ParsedMessageOwned,
+    version: Version,
+    data_type: Option<&str>,
+    name: &str,
+    component: &Component,
+) -> Content {
+    let composite = data_type
+        .and_then(|t| dictionary().data_type(version, t))
+        .filter(|d| !d.is_primitive());
+
+    match (composite, component.sub_components.as_slice()) {
+        (None, [sub_component]) => {
+            Content::Text(decode(message, sub_component.source(&message.source)))
+        }
+        (None, sub_components) => generic_sub_components(message, name, sub_components),
+        (Some(_), sub_components) => {
+            let data_type = data_type.expect("composite has a data type");
+            generic_sub_components(message, data_type, sub_components)
+        }
+    }
+}
+
+fn generic_components(
+    message: &ParsedMessageOwned,
+    name: &str,
+    components: &[Component],
+) -> Content {
+    Content::Elements(
+        components
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !c.source(&message.source).is_empty())
+            .map(|(i, component)| {
+                let name = format!("{name}.{}", i + 1);
+                Element {
+                    content: component_to_xml(message, Version::V2_5_1, None, &name, component),
+                    name,
+                    attributes: Vec::new(),
+                }
+            })
+            .collect(),
+    )
+}
+
+fn generic_sub_components(
+    message: &ParsedMessageOwned,
+    name: &str,
+    sub_components: &[SubComponent],
+) -> Content {
+    Content::Elements(
+        sub_components
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !s.source(&message.source).is_empty())
+            .map(|(i, sub_component)| {
+                Element::text(
+                    format!("{name}.{}", i + 1),
+                    decode(message, sub_component.source(&message.source)),
+                )
+            })
+            .collect(),
+    )
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\r' => escaped.push_str("&#13;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn write_element<W: WriteColor>(out: &mut W, element: &Element, depth: usize) -> Result<()> {
+    let mut hl_tag = ColorSpec::new();
+    let mut hl_special_char = ColorSpec::new();
+    let mut hl_attribute = ColorSpec::new();
+    let mut hl_value = ColorSpec::new();
+    hl_tag.set_fg(Some(Color::Cyan));
+    hl_special_char.set_fg(Some(Color::Black)).set_intense(true);
+    hl_attribute.set_fg(Some(Color::Yellow));
+    hl_value.set_fg(Some(Color::White)).set_intense(true);
+
+    let write_tag = |out: &mut W, closing: bool, attributes: &[(&str, String)]| -> Result<()> {
+        out.set_color(&hl_special_char)
+            .wrap_err_with(|| "Failed to set stdout colour")?;
+        write!(out, "{}", if closing { "</" } else { "<" })
+            .wrap_err_with(|| "Failed to write to stdout")?;
+        out.set_color(&hl_tag)
+            .wrap_err_with(|| "Failed to set stdout colour")?;
+        write!(out, "{}", element.name).wrap_err_with(|| "Failed to write to stdout")?;
+        for (name, value) in attributes {
+            out.set_color(&hl_attribute)
+                .wrap_err_with(|| "Failed to set stdout colour")?;
+            write!(out, " {name}").wrap_err_with(|| "Failed to write to stdout")?;
+            out.set_color(&hl_special_char)
+                .wrap_err_with(|| "Failed to set stdout colour")?;
+            write!(out, "=\"").wrap_err_with(|| "Failed to write to stdout")?;
+            out.set_color(&hl_value)
+                .wrap_err_with(|| "Failed to set stdout colour")?;
+            write!(out, "{}", escape(value)).wrap_err_with(|| "Failed to write to stdout")?;
+            out.set_color(&hl_special_char)
+                .wrap_err_with(|| "Failed to set stdout colour")?;
+            write!(out, "\"").wrap_err_with(|| "Failed to write to stdout")?;
+        }
+        out.set_color(&hl_special_char)
+            .wrap_err_with(|| "Failed to set stdout colour")?;
+        write!(out, ">").wrap_err_with(|| "Failed to write to stdout")?;
+        out.reset()
+            .wrap_err_with(|| "Failed to reset stdout colour")?;
+        Ok(())
+    };
+
+    let indent = "  ".repeat(depth);
+    write!(out, "{indent}").wrap_err_with(|| "Failed to write to stdout")?;
+    write_tag(out, false, &element.attributes)?;
+    match &element.content {
+        Content::Text(text) => {
+            out.set_color(&hl_value)
+                .wrap_err_with(|| "Failed to set stdout colour")?;
+            write!(out, "{}", escape(text)).wrap_err_with(|| "Failed to write to stdout")?;
+            out.reset()
+                .wrap_err_with(|| "Failed to reset stdout colour")?;
+        }
+        Content::Elements(elements) => {
+            writeln!(out).wrap_err_with(|| "Failed to write to stdout")?;
+            for child in elements.iter() {
+                write_element(out, child, depth + 1)?;
+            }
+            write!(out, "{indent}").wrap_err_with(|| "Failed to write to stdout")?;
+        }
+    }
+    write_tag(out, true, &[])?;
+    writeln!(out).wrap_err_with(|| "Failed to write to stdout")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use termcolor::NoColor;
+
+    fn to_xml(source: &str) -> String {
+        let message = ParsedMessageOwned::parse(source, false).expect("can parse message");
+        let mut out = NoColor::new(Vec::new());
+        write_element(&mut out, &message_to_xml(&message), 0).expect("can write XML");
+        String::from_utf8(out.into_inner()).expect("XML is UTF-8")
+    }
+
+    #[test]
+    fn can_convert_message_to_xml() {
+        let xml = to_xml(
+            "MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.5.1\rPID|1||10006579^^^1&2.16.840&ISO~123||DUCK\\T\\SONS^DONALD||||||||||||||||||||||||||||||||||||||X\rZPI|A^B|C\rIN1|1|MEDICARE",
+        );
+        assert_eq!(
+            xml,
+            r#"<ADT_A01 xmlns="urn:hl7-org:v2xml">
+  <MSH>
+    <MSH.1>|</MSH.1>
+    <MSH.2>^~\&amp;</MSH.2>
+    <MSH.3>
+      <HD.1>AccMgr</HD.1>
+    </MSH.3>
+    <MSH.4>
+      <HD.1>1</HD.1>
+    </MSH.4>
+    <MSH.7>
+      <TS.1>20050110045504</TS.1>
+    </MSH.7>
+    <MSH.9>
+      <MSG.1>ADT</MSG.1>
+      <MSG.2>A01</MSG.2>
+    </MSH.9>
+    <MSH.10>599102</MSH.10>
+    <MSH.11>
+      <PT.1>P</PT.1>
+    </MSH.11>
+    <MSH.12>
+      <VID.1>2.5.1</VID.1>
+    </MSH.12>
+  </MSH>
+  <PID>
+    <PID.1>1</PID.1>
+    <PID.3>
+      <CX.1>10006579</CX.1>
+      <CX.4>
+        <HD.1>1</HD.1>
+        <HD.2>2.16.840</HD.2>
+        <HD.3>ISO</HD.3>
+      </CX.4>
+    </PID.3>
+    <PID.3>
+      <CX.1>123</CX.1>
+    </PID.3>
+    <PID.5>
+      <XPN.1>
+        <FN.1>DUCK&amp;SONS</FN.1>
+      </XPN.1>
+      <XPN.2>DONALD</XPN.2>
+    </PID.5>
+    <PID.43>X</PID.43>
+  </PID>
+  <ZPI>
+    <ZPI.1>
+      <ZPI.1.1>A</ZPI.1.1>
+      <ZPI.1.2>B</ZPI.1.2>
+    </ZPI.1>
+    <ZPI.2>C</ZPI.2>
+  </ZPI>
+  <ADT_A01.INSURANCE>
+    <IN1>
+      <IN1.1>1</IN1.1>
+      <IN1.2>
+        <CE.1>MEDICARE</CE.1>
+      </IN1.2>
+    </IN1>
+  </ADT_A01.INSURANCE>
+</ADT_A01>
+"#
+        );
+    }
+}
@@ -0,0 +1,548 @@
+use crate::{
+    filter::{parse_test, FilterTest},
+    segments::SegmentRef,
+};
+use hl7_parser::{LocationQuery, ParsedMessageOwned, Repeat, Segment};
+use std::{num::NonZeroUsize, ops::Range, str::FromStr};
+
+/// Which occurrences of a segment (or repeats of a field) a query selects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selector {
+    /// A single 1-based occurrence (ex: `OBX[2]`)
+    Index(NonZeroUsize),
+    /// Every occurrence (ex: `OBX[*]`)
+    All,
+    /// Every occurrence whose value at a path relative to it matches (ex: `OBX[3.1=2345-7]`)
+    Where(Predicate),
+}
+
+/// A test of a value relative to a segment (`3.1` for the first component of field 3) or to a
+/// repeat of a field (`5` for the fifth component), using the same operators as
+/// [filters](crate::filter::Filter)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Predicate {
+    pub path: Vec<NonZeroUsize>,
+    pub test: FilterTest,
+}
+
+impl Predicate {
+    fn matches(&self, value: Option<&str>) -> bool {
+        // values which aren't in the message are treated as empty, as in filters
+        self.test.matches(&[value.unwrap_or_default()])
+    }
+
+    fn matches_segment(&self, source: &str, segment: &Segment) -> bool {
+        let mut path = self.path.iter().copied();
+        let value = path
+            .next()
+            .and_then(|field| segment.field(field))
+            .and_then(|field| {
+                if field.repeats.is_empty() {
+                    return Some(field.range.clone());
+                }
+                repeat_range(field.repeats.first()?, path.next(), path.next())
+            })
+            .map(|range| &source[range]);
+        self.matches(value)
+    }
+
+    fn matches_repeat(&self, source: &str, repeat: &Repeat) -> bool {
+        let mut path = self.path.iter().copied();
+        let value = repeat_range(repeat, path.next(), path.next()).map(|range| &source[range]);
+        self.matches(value)
+    }
+}
+
+/// Whether a selector picks out the item at a 0-based index, where no selector picks out the
+/// first item
+fn selects(
+    selector: &Option<Selector>,
+    index: usize,
+    matches: impl Fn(&Predicate) -> bool,
+) -> bool {
+    match selector {
+        None => index == 0,
+        Some(Selector::Index(i)) => i.get() == index + 1,
+        Some(Selector::All) => true,
+        Some(Selector::Where(predicate)) => matches(predicate),
+    }
+}
+
+/// A location in a message: a [LocationQuery] which can also pick out occurrences of a
+/// repeated segment, using the same syntax as the paths printed by `--output table`
+///
+/// Queries are in the form `<SEGMENT ID>[\[<SELECTOR>\]][<SEP><FIELD>][\[<SELECTOR>\]][<SEP><COMPONENT>][<SEP><SUB-COMPONENT>]`,
+/// where a selector picks out occurrences of the segment or repeats of the field:
+/// * `<N>`: a 1-based index, ex: `OBX[2].5` is the value of the second OBX segment
+/// * `*`: every occurrence, ex: `IN1[*].4` is the name of every insurance company
+/// * `<PATH>=<VALUE>`, `<PATH>!=<VALUE>`, `<PATH>~<PATTERN>`, or `<PATH>!~<PATTERN>`: every
+///   occurrence where the value at a path relative to it passes the test, as in a
+///   [filter](crate::filter::Filter), ex: `OBX[3.1=2345-7].5` is the value of every glucose
+///   OBX, and `PID.3[5=MR].1` is every ID number with an identifier type code of `MR`
+///
+/// Without a selector, the first segment and first repeat are used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    pub location: LocationQuery,
+    /// Which occurrences of the segment to select, or `None` for the first
+    pub segment_selector: Option<Selector>,
+    /// Which repeats of the field to select, or `None` for the whole field
+    pub repeat_selector: Option<Selector>,
+    /// Whether the segment selector was written in the query, rather than being a default
+    explicit_segment_selector: bool,
+    /// Whether the repeat selector was written in the query, rather than being a default
+    explicit_repeat_selector: bool,
+    /// The query as it was written (ex: `PID-5`, where displaying the query gives `PID.5[1]`)
+    text: String,
+}
+
+impl std::ops::Deref for Query {
+    type Target = LocationQuery;
+
+    fn deref(&self) -> &Self::Target {
+        &self.location
+    }
+}
+
+impl From<LocationQuery> for Query {
+    fn from(location: LocationQuery) -> Self {
+        Query {
+            text: location.to_string(),
+            repeat_selector: location.repeat.map(Selector::Index),
+            segment_selector: None,
+            explicit_segment_selector: false,
+            explicit_repeat_selector: location.repeat.is_some(),
+            location,
+        }
+    }
+}
+
+fn parse_index(s: &str) -> Result<NonZeroUsize, String> {
+    s.parse::<NonZeroUsize>()
+        .map_err(|_| format!("invalid index: {s:?}"))
+}
+
+fn parse_selector(s: &str, max_depth: usize) -> Result<Selector, String> {
+    if s == "*" {
+        return Ok(Selector::All);
+    }
+    let (path, test) = parse_test(s)?;
+    if test == FilterTest::Present {
+        return parse_index(s).map(Selector::Index);
+    }
+    let path = path
+        .split(['.', '-'])
+        .map(parse_index)
+        .collect::<Result<Vec<_>, _>>()?;
+    if path.len() > max_depth {
+        return Err(format!(
+            "filter paths can have at most {max_depth} parts: {s:?}"
+        ));
+    }
+    Ok(Selector::Where(Predicate { path, test }))
+}
+
+/// Split a `[selector]` off the start of the string, if there is one. Brackets within the
+/// selector (ex: in a pattern) must be balanced.
+fn take_selector(s: &str) -> Result<(Option<&str>, &str), String> {
+    let Some(rest) = s.strip_prefix('[') else {
+        return Ok((None, s));
+    };
+    let mut depth = 0usize;
+    for (i, c) in rest.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' if depth == 0 => return Ok((Some(&rest[..i]), &rest[i + 1..])),
+            ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    Err(format!("missing `]` after {s:?}"))
+}
+
+/// Split a separator (`.`, `-`, or ` `) and a number off the start of the string, if there is
+/// one
+fn take_number(s: &str) -> Result<(Option<NonZeroUsize>, &str), String> {
+    let Some(rest) = s.strip_prefix(['.', '-', ' ']) else {
+        return Ok((None, s));
+    };
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let number = parse_index(&rest[..end])?;
+    Ok((Some(number), &rest[end..]))
+}
+
+impl FromStr for Query {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Query::parse(s, false)
+    }
+}
+
+impl Query {
+    /// Whether the query picks out occurrences of its segment (ex: `OBX[2]`), rather than
+    /// using the default
+    pub fn has_segment_selector(&self) -> bool {
+        self.explicit_segment_selector
+    }
+
+    /// Whether the query picks out repeats of its field (ex: `PID.3[2]`), rather than using the
+    /// default
+    pub fn has_repeat_selector(&self) -> bool {
+        self.explicit_repeat_selector
+    }
+
+    /// Parse a query for a location to look for values in rather than a value to read, where a
+    /// segment or field without a selector covers every occurrence of the segment and every
+    /// repeat of the field (ex: `OBX-5` covers the value of every OBX segment)
+    pub fn parse_location(s: &str) -> Result<Query, String> {
+        Query::parse(s, true)
+    }
+
+    fn parse(s: &str, every_occurrence: bool) -> Result<Query, String> {
+        let segment = s
+            .get(..3)
+            .filter(|segment| segment.chars().all(|c| c.is_ascii_alphanumeric()))
+            .ok_or_else(|| format!("invalid segment identifier in {s:?}"))?;
+        let (segment_selector, rest) = take_selector(&s[3..])?;
+        let segment_selector = segment_selector
+            .map(|selector| parse_selector(selector, 3))
+            .transpose()?;
+        let (field, rest) = take_number(rest)?;
+        let (repeat_selector, rest) = match field {
+            Some(_) => take_selector(rest)?,
+            None => (None, rest),
+        };
+        let repeat_selector = repeat_selector
+            .map(|selector| parse_selector(selector, 2))
+            .transpose()?;
+        let (component, rest) = take_number(rest)?;
+        let (sub_component, rest) = match component {
+            Some(_) => take_number(rest)?,
+            None => (None, rest),
+        };
+        if !rest.is_empty() {
+            return Err(format!("unexpected {rest:?} at the end of query {s:?}"));
+        }
+        let explicit_segment_selector = segment_selector.is_some();
+        let explicit_repeat_selector = repeat_selector.is_some();
+
+        // like location queries, default to the first repeat of a field, while locations
+        // cover every occurrence and repeat
+        let (segment_selector, repeat_selector) = match every_occurrence {
+            true => (segment_selector.or(Some(Selector::All)), repeat_selector),
+            false => match (field, repeat_selector) {
+                (Some(_), None) => (segment_selector, Some(Selector::Index(NonZeroUsize::MIN))),
+                (_, selector) => (segment_selector, selector),
+            },
+        };
+        let repeat = match &repeat_selector {
+            Some(Selector::Index(index)) => Some(*index),
+            _ => None,
+        };
+        Ok(Query {
+            location: LocationQuery {
+                segment: segment.to_uppercase(),
+                field,
+                repeat,
+                component,
+                sub_component,
+            },
+            segment_selector,
+            repeat_selector,
+            explicit_segment_selector,
+            explicit_repeat_selector,
+            text: s.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Selector::Index(index) => write!(f, "{index}"),
+            Selector::All => write!(f, "*"),
+            Selector::Where(predicate) => {
+                let path: Vec<String> = predicate.path.iter().map(|p| p.to_string()).collect();
+                write!(f, "{}{}", path.join("."), predicate.test)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.location.segment)?;
+        if let Some(selector) = &self.segment_selector {
+            write!(f, "[{selector}]")?;
+        }
+        if let Some(field) = self.location.field {
+            write!(f, ".{field}")?;
+            if let Some(selector) = &self.repeat_selector {
+                write!(f, "[{selector}]")?;
+            }
+        }
+        if let Some(component) = self.location.component {
+            write!(f, ".{component}")?;
+        }
+        if let Some(sub_component) = self.location.sub_component {
+            write!(f, ".{sub_component}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Find a component (or sub-component) of a repeat
+fn repeat_range(
+    repeat: &Repeat,
+    component: Option<NonZeroUsize>,
+    sub_component: Option<NonZeroUsize>,
+) -> Option<Range<usize>> {
+    let Some(component) = component else {
+        return Some(repeat.range.clone());
+    };
+    let component = repeat.component(component)?;
+    match sub_component {
+        Some(sub_component) => component
+            .sub_component(sub_component)
+            .map(|s| s.range.clone()),
+        None => Some(component.range.clone()),
+    }
+}
+
+fn select<'s, T>(
+    items: &'s [T],
+    selector: &Option<Selector>,
+    matches: impl Fn(&Predicate, &T) -> bool,
+) -> Vec<&'s T> {
+    match selector {
+        None => items.first().into_iter().collect(),
+        Some(Selector::Index(index)) => items.get(index.get() - 1).into_iter().collect(),
+        Some(Selector::All) => items.iter().collect(),
+        Some(Selector::Where(predicate)) => items
+            .iter()
+            .filter(|item| matches(predicate, item))
+            .collect(),
+    }
+}
+
+impl Query {
+    /// The query as it was written
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Whether the query selects at most one value (it doesn't use `*` or filters)
+    pub fn is_single(&self) -> bool {
+        [&self.segment_selector, &self.repeat_selector]
+            .into_iter()
+            .all(|selector| matches!(selector, None | Some(Selector::Index(_))))
+    }
+
+    /// The 0-based index of the segment amongst the segments sharing its identifier (for
+    /// queries which select a single value)
+    pub fn segment_index(&self) -> usize {
+        match self.segment_selector {
+            Some(Selector::Index(index)) => index.get() - 1,
+            _ => 0,
+        }
+    }
+
+    /// Find the ranges of every value the query selects in the message's source, in the order
+    /// they appear
+    pub fn ranges(&self, message: &ParsedMessageOwned) -> Vec<Range<usize>> {
+        let LocationQuery {
+            segment,
+            field,
+            component,
+            sub_component,
+            ..
+        } = &self.location;
+        let source = message.source.as_str();
+        let Some(segments) = message.segments.get(segment) else {
+            return Vec::new();
+        };
+
+        let segment_matches =
+            |predicate: &Predicate, segment: &Segment| predicate.matches_segment(source, segment);
+        let repeat_matches =
+            |predicate: &Predicate, repeat: &Repeat| predicate.matches_repeat(source, repeat);
+
+        let mut ranges = Vec::new();
+        for segment in select(segments, &self.segment_selector, segment_matches) {
+            let Some(field) = field else {
+                ranges.push(segment.range.clone());
+                continue;
+            };
+            let Some(field) = segment.field(*field) else {
+                continue;
+            };
+            // MSH-1 and MSH-2 don't have any repeats, so they can only be queried as a whole
+            let whole_field = field.repeats.is_empty() || self.repeat_selector.is_none();
+            if whole_field && component.is_none() && sub_component.is_none() {
+                ranges.push(field.range.clone());
+                continue;
+            }
+            for repeat in select(&field.repeats, &self.repeat_selector, repeat_matches) {
+                ranges.extend(repeat_range(repeat, *component, *sub_component));
+            }
+        }
+        ranges
+    }
+
+    /// Whether the query selects an occurrence (at a 0-based index) of its segment
+    pub fn selects_segment(&self, source: &str, segment: &Segment, index: usize) -> bool {
+        selects(&self.segment_selector, index, |predicate| {
+            predicate.matches_segment(source, segment)
+        })
+    }
+
+    /// Whether the query selects a repeat (at a 0-based index) of its field. Queries without a
+    /// repeat selector select the whole field, so every repeat.
+    pub fn selects_repeat(&self, source: &str, repeat: &Repeat, index: usize) -> bool {
+        self.repeat_selector.is_none()
+            || selects(&self.repeat_selector, index, |predicate| {
+                predicate.matches_repeat(source, repeat)
+            })
+    }
+
+    /// Whether a value (or a whole segment, if `position` is `None`) is within the location the
+    /// query selects, where a query without a field covers the whole segment, a query without
+    /// a component covers the whole field, etc. The position is the value's
+    /// field, repeat, component, and sub-component, as in table leaves.
+    pub fn contains(
+        &self,
+        source: &str,
+        segment: &SegmentRef,
+        position: Option<[usize; 4]>,
+    ) -> bool {
+        if self.location.segment != segment.id
+            || !self.selects_segment(source, segment.segment, segment.index)
+        {
+            return false;
+        }
+        let Some([field, repeat, component, sub_component]) = position else {
+            return self.field.is_none();
+        };
+        let Some(field_n) = self.field else {
+            return true;
+        };
+        if field_n.get() != field {
+            return false;
+        }
+        // MSH-1 and MSH-2 don't have any repeats
+        let repeat_selected = segment
+            .segment
+            .field(field_n)
+            .and_then(|field| field.repeats.get(repeat - 1))
+            .is_none_or(|r| self.selects_repeat(source, r, repeat - 1));
+        repeat_selected
+            && self.component.is_none_or(|c| c.get() == component)
+            && self.sub_component.is_none_or(|s| s.get() == sub_component)
+    }
+
+    /// Find the range of the first value the query selects in the message's source
+    pub fn range(&self, message: &ParsedMessageOwned) -> Option<Range<usize>> {
+        self.ranges(message).into_iter().next()
+    }
+
+    /// Find every value the query selects in the message
+    pub fn values<'m>(&self, message: &'m ParsedMessageOwned) -> Vec<&'m str> {
+        self.ranges(message)
+            .into_iter()
+            .map(|range| &message.source[range])
+            .collect()
+    }
+
+    /// Find the first value the query selects in the message
+    pub fn value<'m>(&self, message: &'m ParsedMessageOwned) -> Option<&'m str> {
+        self.range(message).map(|range| &message.source[range])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_and_print_queries() {
+        let query: Query = "OBX[2].5".parse().expect("valid query");
+        assert_eq!(query.location.segment, "OBX");
+        assert_eq!(
+            query.segment_selector,
+            NonZeroUsize::new(2).map(Selector::Index)
+        );
+        assert_eq!(query.field, NonZeroUsize::new(5));
+        assert_eq!(query.to_string(), "OBX[2].5[1]");
+        assert!(query.is_single());
+
+        let query: Query = "PID-3[2].1".parse().expect("valid query");
+        assert_eq!(query.segment_selector, None);
+        assert_eq!(query.repeat, NonZeroUsize::new(2));
+        assert_eq!(query.to_string(), "PID.3[2].1");
+        assert_eq!(query.as_str(), "PID-3[2].1");
+
+        let query: Query = "obx[3.1=2345-7].5[*]".parse().expect("valid query");
+        assert_eq!(query.to_string(), "OBX[3.1=2345-7].5[*]");
+        assert!(!query.is_single());
+        let query: Query = "PID.3[5!=MR].1".parse().expect("valid query");
+        assert_eq!(query.to_string(), "PID.3[5!=MR].1");
+
+        assert!("OBX[0].5".parse::<Query>().is_err());
+        assert!("OBX[2.5".parse::<Query>().is_err());
+        assert!("OBX.5x".parse::<Query>().is_err());
+        assert!("PID.3[1.2.3=x]".parse::<Query>().is_err());
+        let query: Query = "OBX[5!~^[0-9]+$].3".parse().expect("valid query");
+        assert_eq!(query.to_string(), "OBX[5!~^[0-9]+$].3[1]");
+        assert!("OBX[5~(].3".parse::<Query>().is_err());
+
+        let location = Query::parse_location("PID-3").expect("valid location");
+        assert_eq!(location.segment_selector, Some(Selector::All));
+        assert_eq!(location.repeat_selector, None);
+        assert_eq!(location.to_string(), "PID[*].3");
+        let location = Query::parse_location("OBX[2].5[1]").expect("valid location");
+        assert_eq!(location.to_string(), "OBX[2].5[1]");
+    }
+
+    #[test]
+    fn can_query_segment_occurrences() {
+        let message =
+            ParsedMessageOwned::parse("MSH|^~\\&|A\rOBX|1|NM|A||1~2^x&y\rOBX|2|NM|B||3", false)
+                .expect("can parse message");
+        let value = |query: &str| query.parse::<Query>().expect("valid query").value(&message);
+        assert_eq!(value("MSH.1"), Some("|"));
+        assert_eq!(value("MSH.2"), Some("^~\\&"));
+        assert_eq!(value("OBX.3"), Some("A"));
+        assert_eq!(value("OBX[1].5[2].2.2"), Some("y"));
+        assert_eq!(value("OBX[2].3"), Some("B"));
+        assert_eq!(value("OBX[2]"), Some("OBX|2|NM|B||3"));
+        assert_eq!(value("OBX[3].3"), None);
+    }
+
+    #[test]
+    fn can_query_with_wildcards_and_filters() {
+        let message = ParsedMessageOwned::parse(
+            "MSH|^~\\&|A\rPID|1||123^^^A^MR~456^^^B^SS~789^^^C^MR\rOBX|1|NM|2345-7^GLUCOSE||5.5\rOBX|2|NM|718-7^HGB||14\rOBX|3|NM|2345-7^GLUCOSE||6.1",
+            false,
+        )
+        .expect("can parse message");
+        let values = |query: &str| {
+            query
+                .parse::<Query>()
+                .expect("valid query")
+                .values(&message)
+        };
+        assert_eq!(values("OBX[*].3.2"), vec!["GLUCOSE", "HGB", "GLUCOSE"]);
+        assert_eq!(values("OBX[3.1=2345-7].5"), vec!["5.5", "6.1"]);
+        assert_eq!(values("OBX[3.1!=2345-7].5"), vec!["14"]);
+        assert_eq!(values("OBX[3.1=nope].5"), Vec::<&str>::new());
+        assert_eq!(values("PID.3[*].1"), vec!["123", "456", "789"]);
+        assert_eq!(values("PID.3[5=MR].1"), vec!["123", "789"]);
+        assert_eq!(values("OBX[*].3[1=718-7].2"), vec!["HGB"]);
+        assert_eq!(values("OBX[5~^[0-9]+$].3.2"), vec!["HGB"]);
+        assert_eq!(values("PID.3[5!~^M].1"), vec!["456"]);
+        assert_eq!(values("PID.3.1"), vec!["123"]);
+    }
+}
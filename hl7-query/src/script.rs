@@ -0,0 +1,173 @@
+use crate::{
+    filter::Filter,
+    map::{Deletion, Insertion, Operation, ValueMap},
+};
+use color_eyre::eyre::{eyre, Context, Result};
+use serde::Deserialize;
+use std::{path::Path, str::FromStr};
+
+/// A list of edits to make to each message, read from a TOML or YAML file
+///
+/// ```toml
+/// [[operations]]
+/// map = "MSH-10=<auto>"
+///
+/// [[operations]]
+/// delete = "NTE"
+///
+/// [[operations]]
+/// insert = "OBX[2]=NTE|1||Reviewed"
+///
+/// [[operations]]
+/// map = "PV1-3.1=ICU"
+/// when = "PV1-2=I"
+///
+/// [[operations]]
+/// map = "PV1-3.1=CLINIC"
+/// when = "PV1-2~^[OP]$"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Script {
+    operations: Vec<ScriptOperation>,
+}
+
+/// A single operation in a script. Exactly one of `map`, `delete`, or `insert` must be given,
+/// using the same syntax as the command line arguments.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScriptOperation {
+    map: Option<String>,
+    delete: Option<String>,
+    insert: Option<String>,
+    /// Only apply the map to messages matching this condition, using the same syntax as
+    /// `hq filter --where`
+    when: Option<String>,
+}
+
+impl ScriptOperation {
+    fn into_operation(self) -> Result<Operation> {
+        let ScriptOperation {
+            map,
+            delete,
+            insert,
+            when,
+        } = self;
+        let when = when
+            .map(|when| Filter::from_str(&when).map_err(|e| eyre!("Invalid condition: {e}")))
+            .transpose()?;
+
+        match (map, delete, insert) {
+            (Some(map), None, None) => {
+                let map = ValueMap::from_str(&map).map_err(|e| eyre!("Invalid map: {e}"))?;
+                Ok(match when {
+                    Some(when) => Operation::ConditionalMap {
+                        map,
+                        when: Box::new(when),
+                    },
+                    None => Operation::Map(map),
+                })
+            }
+            (None, Some(_), None) | (None, None, Some(_)) if when.is_some() => {
+                Err(eyre!("Only maps can have a `when` condition"))
+            }
+            (None, Some(delete), None) => Deletion::from_str(&delete)
+                .map(Operation::Delete)
+                .map_err(|e| eyre!("Invalid deletion: {e}")),
+            (None, None, Some(insert)) => Insertion::from_str(&insert)
+                .map(Operation::Insert)
+                .map_err(|e| eyre!("Invalid insertion: {e}")),
+            _ => Err(eyre!(
+                "Operations must have exactly one of `map`, `delete`, or `insert`"
+            )),
+        }
+    }
+}
+
+/// Read the operations from a script file. The format is chosen from the file's extension
+/// (`.toml`, `.yaml`, or `.yml`).
+pub fn load_script(path: &Path) -> Result<Vec<Operation>> {
+    parse_operations(hl7_common::config::load(path, "script")?)
+}
+
+fn parse_operations(script: Script) -> Result<Vec<Operation>> {
+    script
+        .operations
+        .into_iter()
+        .enumerate()
+        .map(|(i, operation)| {
+            operation
+                .into_operation()
+                .wrap_err_with(|| format!("Invalid operation #{} in script", i + 1))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_read_toml_and_yaml_scripts() {
+        let toml = r#"
+[[operations]]
+map = "MSH-10=<auto>"
+
+[[operations]]
+delete = "NTE"
+
+[[operations]]
+insert = "OBX[2]=NTE|1||Reviewed"
+
+[[operations]]
+map = "PV1-3.1=ICU"
+when = "PV1-2=I"
+
+[[operations]]
+map = "PV1-3.1=CLINIC"
+when = "PV1-2~^[OP]$"
+"#;
+        let yaml = r#"
+operations:
+  - map: MSH-10=<auto>
+  - delete: NTE
+  - insert: OBX[2]=NTE|1||Reviewed
+  - map: PV1-3.1=ICU
+    when: PV1-2=I
+  - map: PV1-3.1=CLINIC
+    when: PV1-2~^[OP]$
+"#;
+        for script in [
+            toml::from_str::<Script>(toml).expect("valid TOML"),
+            serde_yaml::from_str::<Script>(yaml).expect("valid YAML"),
+        ] {
+            let operations = parse_operations(script).expect("valid operations");
+            assert_eq!(operations.len(), 5);
+            assert!(matches!(operations[0], Operation::Map(_)));
+            assert!(matches!(operations[1], Operation::Delete(_)));
+            assert!(matches!(operations[2], Operation::Insert(_)));
+            assert!(matches!(operations[3], Operation::ConditionalMap { .. }));
+            let Operation::ConditionalMap { when, .. } = &operations[4] else {
+                panic!("conditional map");
+            };
+            assert_eq!(when.to_string(), "PV1.2[1]~^[OP]$");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_operations() {
+        let script: Script =
+            serde_yaml::from_str("operations:\n  - map: MSH-10=1\n    delete: NTE\n")
+                .expect("valid YAML");
+        assert!(parse_operations(script).is_err());
+        let script: Script =
+            serde_yaml::from_str("operations:\n  - delete: NTE\n    when: PID-1\n")
+                .expect("valid YAML");
+        assert!(parse_operations(script).is_err());
+        let script: Script =
+            serde_yaml::from_str("operations:\n  - map: MSH-10=1\n    when: PID-1~(\n")
+                .expect("valid YAML");
+        assert!(parse_operations(script).is_err());
+        assert!(serde_yaml::from_str::<Script>("operations:\n  - nope: NTE\n").is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use crate::dictionary::{dictionary, Annotation, Version};
+use hl7_parser::{ParsedMessageOwned, Segment};
+
+/// A segment along with where it sits in the message
+#[derive(Debug, Copy, Clone)]
+pub struct SegmentRef<'m> {
+    /// The segment identifier (`MSH`, `PID`, etc)
+    pub id: &'m str,
+    /// The 0-based index of this segment amongst the segments sharing its identifier
+    pub index: usize,
+    /// How many segments in the message share this segment's identifier
+    pub count: usize,
+    pub segment: &'m Segment,
+}
+
+impl SegmentRef<'_> {
+    /// The path to the segment, including the 1-based segment index if the segment repeats
+    /// within the message (ex: `PID`, `IN1[2]`)
+    pub fn path(&self) -> String {
+        if self.count > 1 {
+            format!("{}[{}]", self.id, self.index + 1)
+        } else {
+            self.id.to_string()
+        }
+    }
+}
+
+/// List all the segments of the message in the order they appear in the source
+pub fn segments_in_order(message: &ParsedMessageOwned) -> Vec<SegmentRef<'_>> {
+    let mut segments: Vec<SegmentRef> = message
+        .segments
+        .iter()
+        .flat_map(|(id, segments)| {
+            segments
+                .iter()
+                .enumerate()
+                .map(|(index, segment)| SegmentRef {
+                    id: id.as_str(),
+                    index,
+                    count: segments.len(),
+                    segment,
+                })
+        })
+        .collect();
+    segments.sort_by_key(|s| s.segment.range.start);
+    segments
+}
+
+/// A single value in a segment (a field, repeat, component, or sub-component which isn't
+/// broken down any further), along with its path within the segment as printed in tables
+#[derive(Debug, Clone)]
+pub struct Leaf<'m> {
+    /// The field (with the repeat, if the field repeats), component, and sub-component of the
+    /// value (ex: `["3[2]", "1"]`)
+    pub path: Vec<String>,
+    /// The 1-based field, repeat, component, and sub-component of the value, where anything not
+    /// broken down is treated as the first of its kind (so `PID.5` and `PID.5.1` are at the same
+    /// position)
+    pub position: [usize; 4],
+    pub value: &'m str,
+}
+
+impl Leaf<'_> {
+    /// Look up the value in the dictionary, using the components and sub-components the value
+    /// was broken down into
+    pub fn annotation(
+        &self,
+        version: Version,
+        segment: &SegmentRef,
+    ) -> Option<Annotation<'static>> {
+        // the path has an entry for each of the field, component, and sub-component that the
+        // value was broken down into
+        let depth = self.path.len();
+        let [field, _, component, sub_component] = self.position;
+        dictionary().annotate(
+            version,
+            segment.id,
+            field,
+            (depth >= 2).then_some(component),
+            (depth >= 3).then_some(sub_component),
+        )
+    }
+}
+
+/// List the non-empty values of a segment in the order they appear
+pub fn segment_leaves<'m>(source: &'m str, segment: &SegmentRef<'m>) -> Vec<Leaf<'m>> {
+    let mut leaves = Vec::new();
+    let mut path: Vec<String> = Vec::new();
+    let mut push = |path: &[String], position: [usize; 4], value: &'m str| {
+        if !value.is_empty() {
+            leaves.push(Leaf {
+                path: path.to_vec(),
+                position,
+                value,
+            });
+        }
+    };
+
+    for (field_i, field) in segment.segment.fields.iter().enumerate() {
+        let field_n = field_i + 1;
+        if field.repeats.is_empty() {
+            push(
+                &[field_n.to_string()],
+                [field_n, 1, 1, 1],
+                field.source(source),
+            );
+        }
+        for (repeat_i, repeat) in field.repeats.iter().enumerate() {
+            let repeat_n = repeat_i + 1;
+            if field.repeats.len() > 1 {
+                path.push(format!("{field_n}[{repeat_n}]"));
+            } else {
+                path.push(field_n.to_string());
+            }
+            if repeat.components.len() <= 1 {
+                push(&path, [field_n, repeat_n, 1, 1], repeat.source(source));
+            } else {
+                for (component_i, component) in repeat.components.iter().enumerate() {
+                    let component_n = component_i + 1;
+                    path.push(component_n.to_string());
+                    if component.sub_components.len() <= 1 {
+                        push(
+                            &path,
+                            [field_n, repeat_n, component_n, 1],
+                            component.source(source),
+                        );
+                    } else {
+                        for (sub_component_i, sub_component) in
+                            component.sub_components.iter().enumerate()
+                        {
+                            let sub_component_n = sub_component_i + 1;
+                            path.push(sub_component_n.to_string());
+                            push(
+                                &path,
+                                [field_n, repeat_n, component_n, sub_component_n],
+                                sub_component.source(source),
+                            );
+                            path.pop();
+                        }
+                    }
+                    path.pop();
+                }
+            }
+            path.pop();
+        }
+    }
+    leaves
+}
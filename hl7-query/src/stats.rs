@@ -0,0 +1,224 @@
+use crate::dictionary::{dictionary, message_version, Version};
+use hl7_parser::ParsedMessageOwned;
+use std::collections::{BTreeMap, HashMap};
+
+/// How often a field is populated across a set of messages, and with what
+#[derive(Debug, Clone, Default)]
+pub struct FieldStats {
+    /// The name of the field in the HL7 dictionary
+    pub name: Option<String>,
+    /// The number of segments the field has a value in
+    pub populated: usize,
+    /// How many times each value of the field was seen
+    pub values: HashMap<String, usize>,
+    /// The maximum length of the field in the HL7 dictionary
+    pub max_length: Option<usize>,
+    /// The length of the longest repeat of the field
+    pub longest: usize,
+    /// The number of repeats of the field longer than the maximum length
+    pub too_long: usize,
+}
+
+impl FieldStats {
+    /// The fraction of the segments the field has a value in
+    pub fn fill_rate(&self, segments: usize) -> f64 {
+        if segments == 0 {
+            0.0
+        } else {
+            self.populated as f64 / segments as f64
+        }
+    }
+
+    /// The `n` most common values of the field, most common first
+    pub fn top_values(&self, n: usize) -> Vec<(&str, usize)> {
+        let mut values: Vec<(&str, usize)> = self
+            .values
+            .iter()
+            .map(|(value, count)| (value.as_str(), *count))
+            .collect();
+        values.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        values.truncate(n);
+        values
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SegmentStats {
+    /// The name of the segment in the HL7 dictionary
+    pub name: Option<String>,
+    /// The number of messages containing the segment
+    pub messages: usize,
+    /// The number of times the segment appears across all of the messages
+    pub count: usize,
+    /// The fields seen in the segment, by 1-based field number
+    pub fields: BTreeMap<usize, FieldStats>,
+}
+
+/// Statistics about the contents of a set of messages, used to learn what a sending system
+/// actually populates
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pub messages: usize,
+    /// The number of messages of each message type (MSH-9.1) and trigger event (MSH-9.2)
+    pub message_types: BTreeMap<(String, String), usize>,
+    pub segments: BTreeMap<String, SegmentStats>,
+}
+
+/// Whether the segment is a locally defined `Z` segment rather than one from the HL7 standard
+pub fn is_z_segment(id: &str) -> bool {
+    id.starts_with('Z')
+}
+
+impl Stats {
+    /// Add the contents of a message to the statistics
+    pub fn add(&mut self, message: &ParsedMessageOwned) {
+        let version = message_version(message).unwrap_or(Version::V2_5_1);
+        let value = |query: &str| {
+            message
+                .query_value(query)
+                .expect("valid query")
+                .unwrap_or_default()
+                .to_string()
+        };
+
+        self.messages += 1;
+        *self
+            .message_types
+            .entry((value("MSH.9.1"), value("MSH.9.2")))
+            .or_default() += 1;
+
+        for (id, segments) in message.segments.iter() {
+            let definition = dictionary().segment(id);
+            let stats = self.segments.entry(id.clone()).or_default();
+            stats.messages += 1;
+            stats.count += segments.len();
+            if stats.name.is_none() {
+                stats.name = definition.map(|definition| definition.name.clone());
+            }
+
+            for segment in segments.iter() {
+                for (i, field) in segment.fields.iter().enumerate() {
+                    let field_value = field.source(&message.source);
+                    if field_value.is_empty() {
+                        continue;
+                    }
+
+                    let field_definition =
+                        definition.and_then(|definition| definition.fields(version).nth(i));
+                    let field_stats = stats.fields.entry(i + 1).or_default();
+                    if field_stats.name.is_none() {
+                        if let Some(field_definition) = field_definition {
+                            field_stats.name = Some(field_definition.name.clone());
+                            field_stats.max_length = field_definition.max_length(version);
+                        }
+                    }
+
+                    field_stats.populated += 1;
+                    *field_stats
+                        .values
+                        .entry(message.separators.decode(field_value))
+                        .or_default() += 1;
+
+                    // lengths are checked per repeat, as in validation
+                    let lengths: Vec<usize> = if field.repeats.is_empty() {
+                        vec![field_value.chars().count()]
+                    } else {
+                        field
+                            .repeats
+                            .iter()
+                            .map(|repeat| repeat.source(&message.source).chars().count())
+                            .collect()
+                    };
+                    for length in lengths {
+                        field_stats.longest = field_stats.longest.max(length);
+                        if field_stats
+                            .max_length
+                            .is_some_and(|max_length| length > max_length)
+                        {
+                            field_stats.too_long += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The fields with values longer than the dictionary's maximum length, as `(segment id,
+    /// field number, field statistics)`
+    pub fn too_long(&self) -> Vec<(&str, usize, &FieldStats)> {
+        self.segments
+            .iter()
+            .flat_map(|(id, segment)| {
+                segment
+                    .fields
+                    .iter()
+                    .filter(|(_, field)| field.too_long > 0)
+                    .map(|(field_n, field)| (id.as_str(), *field_n, field))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_collect_stats() {
+        let mut stats = Stats::default();
+        for source in [
+            "MSH|^~\\&|A|1|||20050110045504||ADT^A01|1|P|2.5.1\rPID|1||123||DUCK^DONALD\rZPD|1|x",
+            "MSH|^~\\&|A|1|||20050110045504||ADT^A08|2|P|2.5.1\rPID|1||456||DUCK^DAISY||19340609",
+            "MSH|^~\\&|B|1|||20050110045504||ADT^A08|3|P|2.5.1\rPID|1||789||DUCK^DONALD\rNK1|1|A\rNK1|2|B",
+        ] {
+            let message = ParsedMessageOwned::parse(source, false).expect("can parse message");
+            stats.add(&message);
+        }
+
+        assert_eq!(stats.messages, 3);
+        assert_eq!(
+            stats
+                .message_types
+                .get(&("ADT".to_string(), "A08".to_string())),
+            Some(&2)
+        );
+
+        let pid = &stats.segments["PID"];
+        assert_eq!(pid.name.as_deref(), Some("Patient Identification"));
+        assert_eq!((pid.messages, pid.count), (3, 3));
+        let name = &pid.fields[&5];
+        assert_eq!(name.name.as_deref(), Some("Patient Name"));
+        assert_eq!(name.populated, 3);
+        assert_eq!(name.values.len(), 2);
+        assert_eq!(name.top_values(1), vec![("DUCK^DONALD", 2)]);
+        let birth_date = &pid.fields[&7];
+        assert_eq!(birth_date.fill_rate(pid.count), 1.0 / 3.0);
+        assert!(!pid.fields.contains_key(&2));
+
+        let nk1 = &stats.segments["NK1"];
+        assert_eq!((nk1.messages, nk1.count), (1, 2));
+        assert_eq!(nk1.fields[&2].fill_rate(nk1.count), 1.0);
+
+        assert!(is_z_segment("ZPD"));
+        assert_eq!(stats.segments["ZPD"].name, None);
+        assert!(stats.too_long().is_empty());
+    }
+
+    #[test]
+    fn can_find_values_longer_than_expected() {
+        let mut stats = Stats::default();
+        let source = format!(
+            "MSH|^~\\&|A|1|||20050110045504||ADT^A01|1|P|2.5.1\rPID|1||123||DUCK^DONALD||19340609|{}",
+            "M".repeat(2)
+        );
+        let message = ParsedMessageOwned::parse(source, false).expect("can parse message");
+        stats.add(&message);
+
+        let too_long = stats.too_long();
+        assert_eq!(too_long.len(), 1);
+        let (segment, field, field_stats) = too_long[0];
+        assert_eq!((segment, field), ("PID", 8));
+        assert_eq!(field_stats.max_length, Some(1));
+        assert_eq!((field_stats.longest, field_stats.too_long), (2, 1));
+    }
+}
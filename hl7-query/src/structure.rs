@@ -0,0 +1,257 @@
+use crate::{
+    dictionary::{dictionary, StructureItem, Version},
+    segments::{segments_in_order, SegmentRef},
+};
+use hl7_parser::ParsedMessageOwned;
+
+/// A segment or group of segments in a message, arranged according to its message structure
+#[derive(Debug, Clone)]
+pub enum StructureNode<'m> {
+    Segment(SegmentRef<'m>),
+    Group {
+        /// The name of the group, qualified by the message structure (ex: `ADT_A01.INSURANCE`)
+        name: String,
+        nodes: Vec<StructureNode<'m>>,
+    },
+}
+
+/// A problem found while arranging a message according to its message structure
+#[derive(Debug, Clone)]
+pub enum StructureProblem<'m> {
+    /// A segment which doesn't fit where it appears in the message. `known` is true if the
+    /// segment appears somewhere else in the message structure.
+    Unexpected {
+        segment: SegmentRef<'m>,
+        known: bool,
+    },
+    /// A required segment or group which isn't in the message
+    Missing(&'static StructureItem),
+}
+
+/// A message arranged according to its message structure
+#[derive(Debug, Clone)]
+pub struct MessageStructure<'m> {
+    /// The message structure identifier (ex: `ADT_A01`)
+    pub id: &'static str,
+    pub nodes: Vec<StructureNode<'m>>,
+    pub problems: Vec<StructureProblem<'m>>,
+}
+
+/// Find the message structure of a message, using MSH-9.3 if it is present and known, or
+/// otherwise the message code and trigger event from MSH-9
+pub fn structure_id(message: &ParsedMessageOwned) -> Option<&'static str> {
+    let query = |q: &str| -> &str {
+        message
+            .query_value(q)
+            .expect("valid query")
+            .unwrap_or_default()
+    };
+    let message_code = query("MSH.9.1");
+    let trigger_event = query("MSH.9.2");
+    let message_structure = query("MSH.9.3");
+
+    let dictionary = dictionary();
+    dictionary
+        .structure_id(message_structure)
+        .or_else(|| dictionary.message_structure(message_code, trigger_event))
+}
+
+/// Arrange the segments of a message into the groups of its message structure
+///
+/// Segments are matched greedily in the order they appear. Segments which can't be matched are
+/// kept where they appear and reported as problems, and Z-segments are kept alongside the
+/// segments they follow. Returns `None` if the message structure isn't in the dictionary.
+pub fn match_structure(
+    message: &ParsedMessageOwned,
+    version: Version,
+) -> Option<MessageStructure<'_>> {
+    let id = structure_id(message)?;
+    let structure = dictionary().structure(id)?;
+
+    let segments = segments_in_order(message);
+    let mut matcher = StructureMatcher {
+        id,
+        structure,
+        segments: &segments,
+        position: 0,
+        version,
+        problems: Vec::new(),
+    };
+    let mut nodes = Vec::new();
+    matcher.match_items(structure, true, &mut nodes);
+    while let Some(segment) = matcher.current() {
+        if !is_z_segment(&segment) {
+            matcher.unexpected(segment);
+        }
+        nodes.push(StructureNode::Segment(segment));
+        matcher.position += 1;
+    }
+
+    Some(MessageStructure {
+        id,
+        nodes,
+        problems: matcher.problems,
+    })
+}
+
+/// Find the segment that a new segment with the identifier `id` should be inserted after, so
+/// that it sits in the right place in the message's structure
+///
+/// The new segment follows the last segment which comes before it in the message structure
+/// (along with any Z-segments directly after that segment). If the message structure isn't in
+/// the dictionary or doesn't include the segment, the new segment goes at the end of the
+/// message.
+pub fn insertion_point<'m>(
+    message: &'m ParsedMessageOwned,
+    version: Version,
+    id: &str,
+) -> Option<SegmentRef<'m>> {
+    let segments = segments_in_order(message);
+
+    let mut order = Vec::new();
+    if let Some(structure) = structure_id(message).and_then(|id| dictionary().structure(id)) {
+        segment_order(structure, version, &mut order);
+    }
+    let Some(target) = order.iter().position(|s| *s == id) else {
+        return segments.last().copied();
+    };
+
+    let mut after = 0;
+    for (i, segment) in segments.iter().enumerate() {
+        match order.iter().position(|s| *s == segment.id) {
+            Some(position) if position <= target => after = i,
+            Some(_) => {}
+            // keep unknown and Z-segments with the segment they follow
+            None if i == after + 1 => after = i,
+            None => {}
+        }
+    }
+    segments.get(after).copied()
+}
+
+/// Flatten a message structure into the order its segments appear in
+fn segment_order(items: &'static [StructureItem], version: Version, order: &mut Vec<&'static str>) {
+    for item in items.iter().filter(|i| i.defined_in(version)) {
+        match item {
+            StructureItem::Segment { segment, .. } => order.push(segment),
+            StructureItem::Group { items, .. } => segment_order(items, version, order),
+        }
+    }
+}
+
+/// Z-segments are site-specific and may appear anywhere
+fn is_z_segment(segment: &SegmentRef) -> bool {
+    segment.id.starts_with('Z')
+}
+
+/// Greedily matches the segments of a message against a message structure
+struct StructureMatcher<'a, 'm> {
+    id: &'static str,
+    structure: &'static [StructureItem],
+    segments: &'a [SegmentRef<'m>],
+    position: usize,
+    version: Version,
+    problems: Vec<StructureProblem<'m>>,
+}
+
+impl<'m> StructureMatcher<'_, 'm> {
+    fn current(&self) -> Option<SegmentRef<'m>> {
+        self.segments.get(self.position).copied()
+    }
+
+    fn unexpected(&mut self, segment: SegmentRef<'m>) {
+        let known = self
+            .structure
+            .iter()
+            .any(|i| i.contains_segment(segment.id));
+        self.problems
+            .push(StructureProblem::Unexpected { segment, known });
+    }
+
+    fn take_z_segments(&mut self, nodes: &mut Vec<StructureNode<'m>>) {
+        while let Some(segment) = self.current().filter(is_z_segment) {
+            nodes.push(StructureNode::Segment(segment));
+            self.position += 1;
+        }
+    }
+
+    /// Whether the item can start at the current segment
+    fn starts(&self, item: &StructureItem) -> bool {
+        let Some(current) = self.current() else {
+            return false;
+        };
+        match item {
+            StructureItem::Segment { segment, .. } => segment == current.id,
+            StructureItem::Group { items, .. } => {
+                for item in items.iter().filter(|i| i.defined_in(self.version)) {
+                    if self.starts(item) {
+                        return true;
+                    }
+                    if item.required() {
+                        return false;
+                    }
+                }
+                false
+            }
+        }
+    }
+
+    fn match_items(
+        &mut self,
+        items: &'static [StructureItem],
+        top_level: bool,
+        nodes: &mut Vec<StructureNode<'m>>,
+    ) {
+        for (i, item) in items.iter().enumerate() {
+            if !item.defined_in(self.version) {
+                continue;
+            }
+
+            if top_level {
+                // skip over anything that can't be matched by the rest of the structure so
+                // that one stray segment doesn't cascade into errors for the rest of the message
+                while let Some(segment) = self.current() {
+                    if !is_z_segment(&segment) {
+                        if items[i..].iter().any(|i| i.contains_segment(segment.id)) {
+                            break;
+                        }
+                        self.unexpected(segment);
+                    }
+                    nodes.push(StructureNode::Segment(segment));
+                    self.position += 1;
+                }
+            }
+
+            let mut count = 0;
+            loop {
+                self.take_z_segments(nodes);
+                if !self.starts(item) {
+                    break;
+                }
+                match item {
+                    StructureItem::Segment { .. } => {
+                        let segment = self.current().expect("item starts at a segment");
+                        nodes.push(StructureNode::Segment(segment));
+                        self.position += 1;
+                    }
+                    StructureItem::Group { group, items, .. } => {
+                        let mut group_nodes = Vec::new();
+                        self.match_items(items, false, &mut group_nodes);
+                        nodes.push(StructureNode::Group {
+                            name: format!("{}.{}", self.id, group),
+                            nodes: group_nodes,
+                        });
+                    }
+                }
+                count += 1;
+                if !item.repeatable() {
+                    break;
+                }
+            }
+
+            if count == 0 && item.required() {
+                self.problems.push(StructureProblem::Missing(item));
+            }
+        }
+    }
+}
@@ -0,0 +1,538 @@
+use crate::{
+    dictionary::{dictionary, message_version, Optionality, StructureItem, Version},
+    segments::{segments_in_order, SegmentRef},
+    structure::{match_structure, StructureProblem},
+};
+use hl7_parser::{Component, ParsedMessageOwned, SubComponent};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found while validating a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub severity: Severity,
+    /// The location of the problem, in location query syntax
+    pub path: String,
+    pub message: String,
+}
+
+impl Issue {
+    fn error<P: ToString, M: ToString>(path: P, message: M) -> Issue {
+        Issue {
+            severity: Severity::Error,
+            path: path.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn warning<P: ToString, M: ToString>(path: P, message: M) -> Issue {
+        Issue {
+            severity: Severity::Warning,
+            path: path.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Validate a message against the bundled HL7 dictionary
+///
+/// Checks the segment order against the message structure for the message type in MSH-9, then
+/// checks each field for optionality, repetition, length, and data type
+pub fn validate_message(message: &ParsedMessageOwned) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    let raw_version = message
+        .query_value("MSH.12.1")
+        .expect("valid query")
+        .unwrap_or_default();
+    let version = match message_version(message) {
+        Some(version) => {
+            if version.to_string() != raw_version {
+                issues.push(Issue::warning(
+                    "MSH.12",
+                    format!(
+                        "Version {raw_version} is not in the dictionary, validating as {version}"
+                    ),
+                ));
+            }
+            version
+        }
+        None => {
+            let version = Version::V2_5_1;
+            issues.push(Issue::error(
+                "MSH.12",
+                format!("Version ID {raw_version:?} is not valid, validating as {version}"),
+            ));
+            version
+        }
+    };
+
+    validate_structure(message, version, &mut issues);
+    for segment in segments_in_order(message).iter() {
+        validate_segment(message, version, segment, &mut issues);
+    }
+
+    issues
+}
+
+fn validate_structure(message: &ParsedMessageOwned, version: Version, issues: &mut Vec<Issue>) {
+    let Some(structure) = match_structure(message, version) else {
+        let message_type = message
+            .query_value("MSH.9")
+            .expect("valid query")
+            .unwrap_or_default();
+        issues.push(Issue::warning(
+            "MSH.9",
+            format!(
+                "No message structure is known for {message_type}, segment order was not checked"
+            ),
+        ));
+        return;
+    };
+
+    for problem in structure.problems {
+        let issue = match problem {
+            StructureProblem::Unexpected {
+                segment,
+                known: true,
+            } => Issue::error(
+                segment.path(),
+                format!(
+                    "Segment {} is out of order or repeated for {}",
+                    segment.id, structure.id
+                ),
+            ),
+            StructureProblem::Unexpected {
+                segment,
+                known: false,
+            } => Issue::error(
+                segment.path(),
+                format!(
+                    "Segment {} is not part of the {} message structure",
+                    segment.id, structure.id
+                ),
+            ),
+            StructureProblem::Missing(StructureItem::Segment { segment, .. }) => Issue::error(
+                segment,
+                match dictionary().segment(segment) {
+                    Some(definition) => {
+                        format!(
+                            "Required segment {segment} ({}) is missing",
+                            definition.name
+                        )
+                    }
+                    None => format!("Required segment {segment} is missing"),
+                },
+            ),
+            StructureProblem::Missing(StructureItem::Group { group, items, .. }) => Issue::error(
+                first_segment(items).unwrap_or_default(),
+                format!("Required group {group} is missing"),
+            ),
+        };
+        issues.push(issue);
+    }
+}
+
+fn first_segment(items: &[StructureItem]) -> Option<&str> {
+    items.first().and_then(|item| match item {
+        StructureItem::Segment { segment, .. } => Some(segment.as_str()),
+        StructureItem::Group { items, .. } => first_segment(items),
+    })
+}
+
+fn validate_segment(
+    message: &ParsedMessageOwned,
+    version: Version,
+    segment: &SegmentRef,
+    issues: &mut Vec<Issue>,
+) {
+    let Some(definition) = dictionary().segment(segment.id) else {
+        issues.push(Issue::warning(
+            segment.path(),
+            format!(
+                "Segment {} is not in the dictionary, its fields were not checked",
+                segment.id
+            ),
+        ));
+        return;
+    };
+    let source = message.source.as_str();
+
+    let mut defined_fields = 0;
+    for (i, field_definition) in definition.fields(version).enumerate() {
+        defined_fields += 1;
+        let field_number = i + 1;
+        let path = format!("{}.{}", segment.path(), field_number);
+
+        // MSH-1 and MSH-2 are the separators themselves
+        if segment.id == "MSH" && field_number <= 2 {
+            continue;
+        }
+
+        let field = segment.segment.fields.get(i);
+        let value = field.map(|f| f.source(source)).unwrap_or_default();
+        if value.is_empty() {
+            if field_definition.optionality(version) == Optionality::Required {
+                issues.push(Issue::error(
+                    path,
+                    format!("Required field {} is missing", field_definition.name),
+                ));
+            }
+            continue;
+        }
+        let Some(field) = field else {
+            continue;
+        };
+
+        if field_definition.optionality(version) == Optionality::Withdrawn {
+            issues.push(Issue::warning(
+                &path,
+                format!("Field {} is withdrawn in v{version}", field_definition.name),
+            ));
+        }
+
+        if let Some(max_repeats) = field_definition.max_repeats() {
+            if field.repeats.len() > max_repeats {
+                issues.push(Issue::error(
+                    &path,
+                    format!(
+                        "Field {} repeats {} times but may only appear {max_repeats} time(s)",
+                        field_definition.name,
+                        field.repeats.len()
+                    ),
+                ));
+            }
+        }
+
+        let data_type = field_definition.data_type(version);
+        for (repeat_i, repeat) in field.repeats.iter().enumerate() {
+            let repeat_path = if field.repeats.len() > 1 {
+                format!("{path}[{}]", repeat_i + 1)
+            } else {
+                path.clone()
+            };
+
+            let value = repeat.source(source);
+            if let Some(max_length) = field_definition.max_length(version) {
+                let length = value.chars().count();
+                if length > max_length {
+                    let message = format!(
+                        "Value is {length} characters long, the maximum for {} is {max_length}",
+                        field_definition.name
+                    );
+                    issues.push(if version.enforces_max_length() {
+                        Issue::error(&repeat_path, message)
+                    } else {
+                        Issue::warning(&repeat_path, message)
+                    });
+                }
+            }
+
+            validate_components(
+                message,
+                version,
+                data_type,
+                field_definition.table.as_deref(),
+                &repeat.components,
+                &repeat_path,
+                issues,
+            );
+        }
+    }
+
+    for (i, field) in segment
+        .segment
+        .fields
+        .iter()
+        .enumerate()
+        .skip(defined_fields)
+    {
+        if !field.source(source).is_empty() {
+            issues.push(Issue::warning(
+                format!("{}.{}", segment.path(), i + 1),
+                format!("Field is not defined for {} in v{version}", segment.id),
+            ));
+        }
+    }
+}
+
+fn validate_components(
+    message: &ParsedMessageOwned,
+    version: Version,
+    data_type: &str,
+    table: Option<&str>,
+    components: &[Component],
+    path: &str,
+    issues: &mut Vec<Issue>,
+) {
+    let Some(definition) = dictionary().data_type(version, data_type) else {
+        return;
+    };
+    let source = message.source.as_str();
+
+    if definition.is_primitive() {
+        if components.len() > 1 {
+            issues.push(Issue::warning(
+                path,
+                format!("{data_type} is a primitive data type but the value has components"),
+            ));
+        } else if let Some(component) = components.first() {
+            validate_sub_components(
+                message,
+                version,
+                data_type,
+                table,
+                &component.sub_components,
+                path,
+                issues,
+            );
+        }
+        return;
+    }
+
+    for (i, component) in components.iter().enumerate() {
+        let component_path = format!("{path}.{}", i + 1);
+        match definition.components.get(i) {
+            Some(component_definition) => validate_sub_components(
+                message,
+                version,
+                &component_definition.data_type,
+                component_definition.table.as_deref(),
+                &component.sub_components,
+                &component_path,
+                issues,
+            ),
+            None if !component.source(source).is_empty() => issues.push(Issue::warning(
+                component_path,
+                format!(
+                    "{data_type} only has {} components",
+                    definition.components.len()
+                ),
+            )),
+            None => {}
+        }
+    }
+}
+
+fn validate_sub_components(
+    message: &ParsedMessageOwned,
+    version: Version,
+    data_type: &str,
+    table: Option<&str>,
+    sub_components: &[SubComponent],
+    path: &str,
+    issues: &mut Vec<Issue>,
+) {
+    let Some(definition) = dictionary().data_type(version, data_type) else {
+        return;
+    };
+    let source = message.source.as_str();
+
+    if definition.is_primitive() {
+        if sub_components.len() > 1 {
+            issues.push(Issue::warning(
+                path,
+                format!("{data_type} is a primitive data type but the value has sub-components"),
+            ));
+        } else if let Some(sub_component) = sub_components.first() {
+            let value = message.separators.decode(sub_component.source(source));
+            if let Some(issue) = validate_primitive(data_type, table, &value, path) {
+                issues.push(issue);
+            }
+        }
+        return;
+    }
+
+    for (i, sub_component) in sub_components.iter().enumerate() {
+        let sub_component_path = format!("{path}.{}", i + 1);
+        let value = message.separators.decode(sub_component.source(source));
+        match definition.components.get(i) {
+            Some(component_definition) => {
+                let data_type = version.data_type(&component_definition.data_type);
+                if let Some(issue) = validate_primitive(
+                    data_type,
+                    component_definition.table.as_deref(),
+                    &value,
+                    &sub_component_path,
+                ) {
+                    issues.push(issue);
+                }
+            }
+            None if !value.is_empty() => issues.push(Issue::warning(
+                sub_component_path,
+                format!(
+                    "{data_type} only has {} components",
+                    definition.components.len()
+                ),
+            )),
+            None => {}
+        }
+    }
+}
+
+fn is_number(value: &str) -> bool {
+    let digits = value.strip_prefix(['+', '-']).unwrap_or(value);
+    let mut parts = digits.splitn(2, '.');
+    let whole = parts.next().unwrap_or_default();
+    let fraction = parts.next().unwrap_or_default();
+    (!whole.is_empty() || !fraction.is_empty())
+        && whole.chars().all(|c| c.is_ascii_digit())
+        && fraction.chars().all(|c| c.is_ascii_digit())
+}
+
+fn is_timestamp(value: &str, date_lengths: &[usize]) -> bool {
+    let date_length = value.chars().take_while(|c| c.is_ascii_digit()).count();
+    date_lengths.contains(&date_length)
+        && value
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == '.' || c == '+' || c == '-')
+        && hl7_parser::parse_time(value).is_ok()
+}
+
+/// Check a single (decoded) value against a primitive data type
+fn validate_primitive(
+    data_type: &str,
+    table: Option<&str>,
+    value: &str,
+    path: &str,
+) -> Option<Issue> {
+    // `""` is the explicit HL7 null value
+    if value.is_empty() || value == "\"\"" {
+        return None;
+    }
+
+    let valid = match data_type {
+        "NM" => is_number(value),
+        "SI" => value.chars().all(|c| c.is_ascii_digit()),
+        "DT" => value.chars().all(|c| c.is_ascii_digit()) && is_timestamp(value, &[4, 6, 8]),
+        "DTM" => is_timestamp(value, &[4, 6, 8, 10, 12, 14]),
+        "TM" => is_timestamp(&format!("20000101{value}"), &[10, 12, 14]),
+        "ID" | "IS" => {
+            let table_id = table?;
+            let table = dictionary().table(table_id)?;
+            if table.contains(value) {
+                return None;
+            }
+            return Some(Issue::warning(
+                path,
+                format!(
+                    "Value {value:?} is not in table {table_id} ({})",
+                    table.name
+                ),
+            ));
+        }
+        _ => true,
+    };
+
+    if valid {
+        None
+    } else {
+        Some(Issue::error(
+            path,
+            format!("Value {value:?} is not a valid {data_type}"),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issues(message: &str) -> Vec<Issue> {
+        let message = ParsedMessageOwned::parse(message, false).expect("can parse message");
+        validate_message(&message)
+    }
+
+    #[test]
+    fn valid_message_has_no_issues() {
+        let issues = issues(
+            "MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.5.1\rEVN|A01|20050110045502\rPID|1||10006579^^^1^MR^1||DUCK^DONALD^D||19241010|M\rPV1|1|I",
+        );
+        assert_eq!(issues, vec![]);
+    }
+
+    #[test]
+    fn can_find_structure_issues() {
+        let issues = issues(
+            "MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.5.1\rPID|1||10006579||DUCK^DONALD\rPV1|1|I\rIN1|1|MEDICARE|3\rDG1|1|||||A\rIN1|2|OTHER|4",
+        );
+        assert_eq!(
+            issues,
+            vec![
+                Issue::error("EVN", "Required segment EVN (Event Type) is missing"),
+                Issue::error("DG1", "Segment DG1 is out of order or repeated for ADT_A01"),
+                Issue::error(
+                    "IN1[2]",
+                    "Segment IN1 is out of order or repeated for ADT_A01"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn can_find_field_issues() {
+        let issues = issues(
+            "MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.5.1\rEVN|A01|20050110045502\rPID|1||10006579||||19241310|X|||||||||||1234567890123456789\rPV1|1~2|I",
+        );
+        assert_eq!(
+            issues,
+            vec![
+                Issue::error("PID.5", "Required field Patient Name is missing"),
+                Issue::error("PID.7.1", "Value \"19241310\" is not a valid DTM"),
+                Issue::warning(
+                    "PID.8",
+                    "Value \"X\" is not in table 0001 (Administrative Sex)"
+                ),
+                Issue::error(
+                    "PID.19",
+                    "Value is 19 characters long, the maximum for SSN Number - Patient is 16"
+                ),
+                Issue::error(
+                    "PV1.1",
+                    "Field Set ID - PV1 repeats 2 times but may only appear 1 time(s)"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn can_validate_version_specific_fields() {
+        let issues_v2_3 = issues(
+            "MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.3\rEVN|A01|20050110045502\rPID|1||10006579||DUCKWORTH-FEATHERSTONEHAUGH^DONALD^DUNCAN^THE THIRD\rPV1|1|I",
+        );
+        assert_eq!(
+            issues_v2_3,
+            vec![Issue::error(
+                "PID.5",
+                "Value is 51 characters long, the maximum for Patient Name is 48"
+            )]
+        );
+
+        let issues_v2_7 = issues(
+            "MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.7\rEVN|A01|20050110045502\rPID|1||10006579||DUCK^DONALD||||||||||||||123456789|||||||||||||||||||||^PRN^PH^^^^^5551234\rPV1|1|I\rZPI|1",
+        );
+        assert_eq!(
+            issues_v2_7,
+            vec![
+                Issue::warning("PID.19", "Field SSN Number - Patient is withdrawn in v2.7"),
+                Issue::warning(
+                    "ZPI",
+                    "Segment ZPI is not in the dictionary, its fields were not checked"
+                ),
+            ]
+        );
+    }
+}
@@ -63,6 +63,71 @@ fn should_output_tabular_data() {
     ));
 }
 
+#[test]
+fn should_annotate_tabular_data() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("-o")
+        .arg("table")
+        .arg("--annotate")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../assets/sample_adt_a01.hl7"
+        ));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "PID.5.1\tDUCK\tPatient Name / Family Name\tFN\n",
+        ))
+        .stdout(predicate::str::contains(
+            "PID.8\tM (Male)\tAdministrative Sex\tIS\n",
+        ));
+}
+
+#[test]
+fn table_paths_are_valid_queries() {
+    let sample = concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/sample_adt_a01.hl7");
+    let repeats = "MSH|^~\\&|A|1|||20050110045504||ORU^R01|1|P|2.5.1\nPID|1||123^^^A&B&C~12\\F\\3^^^D||DUCK\\T\\CO^DONALD\nOBX|1|NM|A||1~2\nOBX|2|NM|B^x&y||3\n";
+
+    for (input, stdin) in [(Some(sample), ""), (None, repeats)] {
+        let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+        cmd.arg("--colour").arg("never").arg("-o").arg("table");
+        if let Some(input) = input {
+            cmd.arg(input);
+        }
+        let output = cmd.write_stdin(stdin).output().expect("can run hq");
+        assert!(output.status.success());
+        let table = String::from_utf8(output.stdout).expect("valid UTF-8");
+        let rows: Vec<(&str, &str)> = table
+            .lines()
+            .map(|line| line.split_once('\t').expect("rows have a path and value"))
+            .collect();
+        assert!(rows.iter().any(|(path, _)| path.contains('[')));
+        if input.is_none() {
+            assert!(rows.contains(&("PID.3[2].1", "12|3")));
+        }
+
+        let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+        cmd.arg("--colour").arg("never");
+        for (path, _) in rows.iter() {
+            cmd.arg("-q").arg(path);
+        }
+        if let Some(input) = input {
+            cmd.arg(input);
+        }
+        let output = cmd.write_stdin(stdin).output().expect("can run hq");
+        assert!(output.status.success());
+        let values = String::from_utf8(output.stdout).expect("valid UTF-8");
+        let values: Vec<&str> = values.lines().collect();
+        assert_eq!(values.len(), rows.len());
+        for ((path, expected), value) in rows.iter().zip(values) {
+            assert_eq!(*expected, value, "query {path} returned {value:?}");
+        }
+    }
+}
+
 #[test]
 fn should_print_help() {
     let mut cmd = Command::cargo_bin("hq").expect("binary exists");
@@ -72,3 +137,839 @@ fn should_print_help() {
         .success()
         .stdout(predicate::str::contains(r"Usage: hq [OPTIONS] [INPUT]"));
 }
+
+#[test]
+fn should_query_each_message_in_a_batch() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("-q")
+        .arg("MSH.10")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../assets/sample_batch.hl7"
+        ));
+    cmd.assert().success().stdout("599102\n599103\n");
+}
+
+#[test]
+fn should_print_query_results_as_records() {
+    let input = "MSH|^~\\&|A|1|||20050110045504||ADT^A01|1|P|2.5.1\nPID|1||123||DUCK, DONALD\nMSH|^~\\&|A|1|||20050110045504||ADT^A01|2|P|2.5.1\nPID|1||456||\n";
+    let run = |output: &str| {
+        let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+        cmd.arg("--colour")
+            .arg("never")
+            .arg("-o")
+            .arg(output)
+            .arg("-q")
+            .arg("MSH-10")
+            .arg("-q")
+            .arg("PID.5")
+            .arg("-q")
+            .arg("PID.19")
+            .write_stdin(input);
+        cmd.assert().success()
+    };
+
+    run("csv").stdout("MSH-10,PID.5,PID.19\n1,\"DUCK, DONALD\",\n2,\"\",\n");
+    run("tsv").stdout("MSH-10\tPID.5\tPID.19\n1\tDUCK, DONALD\t\\N\n2\t\t\\N\n");
+    run("json").stdout(predicate::str::contains(
+        "{\n    \"MSH-10\": \"2\",\n    \"PID.5\": \"\",\n    \"PID.19\": null\n  }",
+    ));
+
+    // a single message is still printed as an array
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("-o")
+        .arg("json")
+        .arg("-q")
+        .arg("MSH-10")
+        .write_stdin(input.lines().take(2).collect::<Vec<_>>().join("\n"));
+    cmd.assert()
+        .success()
+        .stdout("[\n  {\n    \"MSH-10\": \"1\"\n  }\n]\n");
+}
+
+#[test]
+fn should_query_with_wildcards_and_filters() {
+    let input = "MSH|^~\\&|A|1|||20050110045504||ORU^R01|1|P|2.5.1\nPID|1||123^^^A^MR~456^^^B^SS~789^^^C^MR\nOBX|1|NM|2345-7^GLUCOSE||5.5\nOBX|2|NM|718-7^HGB||14\nOBX|3|NM|2345-7^GLUCOSE||6.1\n";
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("-q")
+        .arg("PID.3[5=MR].1")
+        .arg("-q")
+        .arg("OBX[3.1=2345-7].5")
+        .arg("-q")
+        .arg("OBX[*].3.2")
+        .arg("-q")
+        .arg("OBX[3.1=nope].5")
+        .arg("-q")
+        .arg("MSH.10")
+        .write_stdin(input);
+    cmd.assert()
+        .success()
+        .stdout("123\n789\n5.5\n6.1\nGLUCOSE\nHGB\nGLUCOSE\n\n1\n");
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("-o")
+        .arg("csv")
+        .arg("-q")
+        .arg("MSH.10")
+        .arg("-q")
+        .arg("OBX[3.1=2345-7].5")
+        .arg("-q")
+        .arg("OBX[3.1=nope].5")
+        .write_stdin(input);
+    cmd.assert()
+        .success()
+        .stdout("MSH.10,OBX[3.1=2345-7].5,OBX[3.1=nope].5\n1,\"5.5\n6.1\",\n");
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("-m")
+        .arg("OBX[*].5=1")
+        .write_stdin(input);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("maps can only set a single value"));
+}
+
+#[test]
+fn should_output_json_array_for_batches() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour").arg("never").arg("-o").arg("json").arg(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../assets/sample_batch.hl7"
+    ));
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).expect("output is valid JSON");
+    let messages = json.as_array().expect("output is an array");
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[1][0]["MSH"]["10"], "599103");
+}
+
+#[test]
+fn should_keep_segment_order_in_json() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour").arg("never").arg("-o").arg("json").arg(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../assets/sample_adt_a01.hl7"
+    ));
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).expect("output is valid JSON");
+    let messages = json.as_array().expect("output is an array");
+    assert_eq!(messages.len(), 1);
+    let segments: Vec<&str> = messages[0]
+        .as_array()
+        .expect("message is an array")
+        .iter()
+        .map(|segment| {
+            let segment = segment.as_object().expect("segment is an object");
+            segment.keys().next().expect("segment has an id").as_str()
+        })
+        .collect();
+    assert_eq!(
+        segments,
+        vec!["MSH", "EVN", "PID", "PV1", "GT1", "DG1", "IN1", "IN2", "IN1", "IN2", "IN1"]
+    );
+}
+
+#[test]
+fn should_keep_segment_order_in_table() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour").arg("never").arg("-o").arg("table").arg(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../assets/sample_adt_a01.hl7"
+    ));
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let output = String::from_utf8(output).expect("output is UTF-8");
+    let in1 = output.find("IN1[2].1\t").expect("second IN1 is printed");
+    let in2 = output.find("IN2[1].").expect("first IN2 is printed");
+    assert!(in2 < in1, "IN2[1] is printed before IN1[2]");
+}
+
+#[test]
+fn should_prefix_table_rows_with_message_index() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour").arg("never").arg("-o").arg("table").arg(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../assets/sample_batch.hl7"
+    ));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("1\tMSH.10\t599102"))
+        .stdout(predicate::str::contains("2\tMSH.10\t599103"))
+        .stdout(predicate::str::contains("BHS").not());
+}
+
+#[test]
+fn should_validate_valid_message() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour").arg("never").arg("--validate");
+    cmd.write_stdin("MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.5.1\nEVN|A01|20050110045502\nPID|1||10006579^^^1^MR^1||DUCK^DONALD^D||19241010|M\nPV1|1|I");
+    cmd.assert().success().stdout(predicate::str::is_empty());
+}
+
+#[test]
+fn should_report_validation_errors() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour").arg("never").arg("--validate");
+    cmd.write_stdin("MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.5.1\nPID|1||10006579^^^1^MR^1||||19241010|M\nPV1|1|I");
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "error\tEVN\tRequired segment EVN (Event Type) is missing",
+        ))
+        .stdout(predicate::str::contains(
+            "error\tPID.5\tRequired field Patient Name is missing",
+        ))
+        .stderr(predicate::str::contains("Validation failed with 2 error(s)"));
+}
+
+#[test]
+fn should_round_trip_json() {
+    let sample = concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/sample_adt_a01.hl7");
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("--colour").arg("never").arg("-o").arg("json").arg(sample);
+    let json = cmd.assert().success().get_output().stdout.clone();
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("--colour").arg("never").arg("-o").arg("table").arg(sample);
+    let expected = cmd.assert().success().get_output().stdout.clone();
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("-i")
+        .arg("json")
+        .arg("-o")
+        .arg("table");
+    cmd.write_stdin(json);
+    cmd.assert()
+        .success()
+        .stdout(String::from_utf8(expected).expect("output is UTF-8"));
+}
+
+#[test]
+fn should_round_trip_json_sub_components() {
+    let message = "MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.5.1\nPID|1||A&B~C&D^E||F&G";
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("--colour").arg("never").arg("-o").arg("json");
+    cmd.write_stdin(message);
+    let json = cmd.assert().success().get_output().stdout.clone();
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("--colour").arg("never").arg("--input-format").arg("json");
+    cmd.write_stdin(json);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("PID|1||A&B~C&D^E||F&G"));
+}
+
+#[test]
+fn should_escape_json_values() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour").arg("never").arg("--input-format").arg("json");
+    cmd.write_stdin(r#"[{"MSH": {"1": "|", "2": "^~\\&", "9": {"1": "ADT", "2": "A01"}}}, {"PID": {"5": {"1": "O|BRIEN", "2": "A^B"}}}]"#);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(r"MSH|^~\&|||||||ADT^A01"))
+        .stdout(predicate::str::contains(r"PID|||||O\F\BRIEN^A\S\B"));
+}
+
+#[test]
+fn should_output_xml() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour").arg("never").arg("-o").arg("xml").arg(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../assets/sample_adt_a01.hl7"
+    ));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ADT_A01 xmlns=\"urn:hl7-org:v2xml\">",
+        ))
+        .stdout(predicate::str::contains(
+            "<PID.5>\n      <XPN.1>\n        <FN.1>DUCK</FN.1>\n      </XPN.1>",
+        ))
+        .stdout(predicate::str::contains("<ADT_A01.INSURANCE>\n    <IN1>"))
+        .stdout(predicate::str::ends_with("</ADT_A01>\n"));
+}
+
+#[test]
+fn should_wrap_xml_batches() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour").arg("never").arg("-o").arg("xml").arg(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../assets/sample_batch.hl7"
+    ));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<MESSAGES>\n  <",
+        ))
+        .stdout(predicate::str::ends_with("</MESSAGES>\n"));
+}
+
+#[test]
+fn should_round_trip_xml() {
+    for sample in [
+        concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/sample_adt_a01.hl7"),
+        concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/sample_batch.hl7"),
+    ] {
+        let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+        cmd.arg("--colour").arg("never").arg("-o").arg("xml").arg(sample);
+        let xml = cmd.assert().success().get_output().stdout.clone();
+
+        let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+        cmd.arg("--colour").arg("never").arg("-o").arg("table").arg(sample);
+        let expected = cmd.assert().success().get_output().stdout.clone();
+
+        let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+        cmd.arg("--colour")
+            .arg("never")
+            .arg("--input-format")
+            .arg("xml")
+            .arg("-o")
+            .arg("table");
+        cmd.write_stdin(xml);
+        cmd.assert()
+            .success()
+            .stdout(String::from_utf8(expected).expect("output is UTF-8"));
+    }
+}
+
+#[test]
+fn should_generate_auto_values_from_data_type() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("-m")
+        .arg("PID.8=<auto>")
+        .arg("-m")
+        .arg("PID.7=<auto>")
+        .arg("-q")
+        .arg("PID.8")
+        .arg("-q")
+        .arg("PID.7")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../assets/sample_adt_a01.hl7"
+        ));
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let output = String::from_utf8(output).expect("output is UTF-8");
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(["F", "M", "O", "U", "A", "N"].contains(&lines[0]), "{output}");
+    assert_eq!(lines[1].len(), 8, "{output}");
+    assert!(lines[1].chars().all(|c| c.is_ascii_digit()), "{output}");
+}
+
+#[test]
+fn should_evaluate_map_expressions_before_applying_maps() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("-m")
+        .arg("MSH.3={MSH.5}")
+        .arg("-m")
+        .arg("MSH.5={MSH.3}")
+        .arg("-m")
+        .arg("MSH.4={lower(default(MSH.5, \"none\"))}-{substr(MSH.7, 0, 8)}")
+        .arg("-q")
+        .arg("MSH.3")
+        .arg("-q")
+        .arg("MSH.4")
+        .arg("-q")
+        .arg("MSH.5");
+    cmd.write_stdin("MSH|^~\\&|AccMgr|1|Lab|2|20050110045504||ADT^A01|599102|P|2.3\nPID|1\n");
+    cmd.assert()
+        .success()
+        .stdout("Lab\nlab-20050110\nAccMgr\n");
+}
+
+#[test]
+fn should_keep_invalid_map_expressions_literal() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour").arg("never").arg("-m").arg("MSH.3={nope(MSH.5)}");
+    cmd.write_stdin("MSH|^~\\&|AccMgr|1|Lab|2|20050110045504||ADT^A01|599102|P|2.3\nPID|1");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("MSH|^~\\&|{nope(MSH.5)}|1|Lab"))
+        .stderr(predicate::str::contains("isn't a valid expression"));
+}
+
+#[test]
+fn should_reject_non_numeric_substr_arguments() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("-m").arg("MSH.3={substr(MSH.5, MSH.4)}");
+    cmd.write_stdin("MSH|^~\\&|AccMgr|1x|Lab|2|20050110045504||ADT^A01|599102|P|2.3\nPID|1");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "substr start must be a number, not \"1x\"",
+        ));
+}
+
+#[test]
+fn should_map_fields_which_dont_exist_yet() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("-m")
+        .arg("PID.8=M")
+        .arg("-m")
+        .arg("PID.5[2].1=DUCKY")
+        .arg("-m")
+        .arg("PD1.4.2=SMITH");
+    cmd.write_stdin("MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.5.1\nEVN|A01\nPID|1||123||DUCK^DONALD\nPV1|1|I\n");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("PID|1||123||DUCK^DONALD~DUCKY|||M\n"))
+        .stdout(predicate::str::contains("PD1").not())
+        .stderr(predicate::str::contains(
+            "the message has no PD1 segment (use --create-segments to add it)",
+        ));
+}
+
+#[test]
+fn should_map_repeated_segments() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("-m")
+        .arg("OBX[2].5={OBX[1].5}0")
+        .arg("-m")
+        .arg("OBX[3].5=1");
+    cmd.write_stdin("MSH|^~\\&|A|1|||20050110045504||ORU^R01|1|P|2.5.1\nOBX|1|NM|A||1\nOBX|2|NM|B||2\n");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("OBX|1|NM|A||1\nOBX|2|NM|B||10\n"))
+        .stderr(predicate::str::contains(
+            "map OBX[3].5[1]=1 had no effect: OBX[3].5[1] isn't in the message",
+        ));
+}
+
+#[test]
+fn should_create_segments_in_structure_order() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("--create-segments")
+        .arg("-m")
+        .arg("PD1.4.2=SMITH")
+        .arg("-m")
+        .arg("NK1.2=DUCK^HUEY");
+    cmd.write_stdin("MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.5.1\nEVN|A01\nPID|1||123||DUCK^DONALD\nZPI|1\nPV1|1|I\n");
+    cmd.assert().success().stdout(predicate::str::contains(
+        "PID|1||123||DUCK^DONALD\nZPI|1\nPD1||||^SMITH\nNK1||DUCK^HUEY\nPV1|1|I",
+    ));
+}
+
+#[test]
+fn should_delete_fields_and_segments() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("--delete")
+        .arg("NTE")
+        .arg("--delete")
+        .arg("PID-19")
+        .arg("--delete")
+        .arg("PID-5.2");
+    cmd.write_stdin("MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.5.1\nNTE|1\nPID|1||123||DUCK^DONALD^D||||||||||||||123-45-6789~987|1\nNTE|2\nPV1|1|I\n");
+    cmd.assert().success().stdout(
+        "MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.5.1\nPID|1||123||DUCK^^D|||||||||||||||1\nPV1|1|I\n",
+    );
+}
+
+#[test]
+fn should_delete_selected_segments() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("--delete")
+        .arg("OBX[2].5")
+        .arg("--delete")
+        .arg("OBX[3.1=Z]")
+        .arg("--delete")
+        .arg("OBX[4]");
+    cmd.write_stdin("MSH|^~\\&|Lab|1|||20050110045504||ORU^R01|599102|P|2.5.1\nOBR|1\nOBX|1|NM|X||5\nOBX|2|NM|Y||6\nOBX|3|NM|Z||7\n");
+    cmd.assert()
+        .success()
+        .stdout(
+            "MSH|^~\\&|Lab|1|||20050110045504||ORU^R01|599102|P|2.5.1\nOBR|1\nOBX|1|NM|X||5\nOBX|2|NM|Y||\n",
+        )
+        .stderr(predicate::str::contains(
+            "deletion OBX[4] had no effect: the message doesn't have 4 OBX segment(s)",
+        ));
+}
+
+#[test]
+fn should_apply_edits_in_command_line_order() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("--insert")
+        .arg("OBX[2]=NTE|1||Checked")
+        .arg("-m")
+        .arg("NTE.3=Rechecked")
+        .arg("--insert")
+        .arg("OBX=NTE|1||First")
+        .arg("--delete")
+        .arg("OBX.5");
+    cmd.write_stdin("MSH|^~\\&|Lab|1|||20050110045504||ORU^R01|599102|P|2.5.1\nPID|1||123\nOBR|1\nOBX|1|NM|X||5\nOBX|2|NM|Y||6\n");
+    cmd.assert().success().stdout(
+        "MSH|^~\\&|Lab|1|||20050110045504||ORU^R01|599102|P|2.5.1\nPID|1||123\nOBR|1\nOBX|1|NM|X||\nNTE|1||First\nOBX|2|NM|Y||6\nNTE|1||Rechecked\n",
+    );
+}
+
+#[test]
+fn should_apply_script_operations() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("--script")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../assets/sample_script.yaml"
+        ))
+        .arg("-m")
+        .arg("MSH-3={MSH-5}")
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../assets/sample_adt_a01.hl7"
+        ));
+    cmd.assert()
+        .success()
+        // maps see the message as it was before the script ran, so MSH-5 was empty
+        .stdout(predicate::str::contains("MSH|^~\\&||1|INPATIENT|"))
+        .stdout(predicate::str::contains("\nNTE|1||Insurance removed\nGT1|"))
+        .stdout(predicate::str::contains("599102").not())
+        .stdout(predicate::str::contains("IN1|").not())
+        .stdout(predicate::str::contains("IN2|").not());
+}
+
+#[test]
+fn should_reject_invalid_scripts() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--script").arg(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../assets/sample_adt_a01.hl7"
+    ));
+    cmd.write_stdin("MSH|^~\\&|AccMgr|1|Lab|2|20050110045504||ADT^A01|599102|P|2.3\n");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown script format"));
+}
+
+#[test]
+fn should_anonymize_consistently_with_a_seed() {
+    let run = |seed: &str| -> String {
+        let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+        cmd.arg("--colour")
+            .arg("never")
+            .arg("--anonymize")
+            .arg("--anonymize-seed")
+            .arg(seed)
+            .arg(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../assets/sample_batch.hl7"
+            ));
+        let output = cmd.assert().success().get_output().stdout.clone();
+        String::from_utf8(output).expect("output is UTF-8")
+    };
+
+    let output = run("42");
+    assert_eq!(output, run("42"));
+    assert_ne!(output, run("43"));
+    assert!(!output.contains("DUCK^DONALD"), "{output}");
+}
+
+#[test]
+fn should_anonymize_configured_fields() {
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+
+    cmd.arg("--colour")
+        .arg("never")
+        .arg("--anonymize")
+        .arg("--anonymize-fields")
+        .arg("PID-3.1,PID-18")
+        .arg("-q")
+        .arg("PID-3.1")
+        .arg("-q")
+        .arg("PID-5")
+        .arg("-q")
+        .arg("PID-18.1");
+    cmd.write_stdin("MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|599102|P|2.5.1\nPID|1||123456^^^HOSP^MR||DUCK^DONALD|||||||||||||123456\nPID|2||123456\n");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let output = String::from_utf8(output).expect("output is UTF-8");
+    let lines: Vec<&str> = output.lines().collect();
+    assert_ne!(lines[0], "123456", "{output}");
+    assert_eq!(lines[0].len(), 6, "{output}");
+    assert_eq!(lines[1], "DUCK^DONALD", "{output}");
+    assert_eq!(lines[0], lines[2], "{output}");
+}
+
+#[test]
+fn should_diff_messages() {
+    let dir = std::env::temp_dir().join(format!("hq-diff-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("can create temp dir");
+    let a = dir.join("a.hl7");
+    let b = dir.join("b.hl7");
+    std::fs::write(
+        &a,
+        "MSH|^~\\&|AccMgr|1|||20050110045504||ORU^R01|1|P|2.5.1\nPID|1||123||DUCK^DONALD\nOBX|1|NM|A||1\nOBX|2|NM|B||2\n",
+    )
+    .expect("can write file");
+    std::fs::write(
+        &b,
+        "MSH|^~\\&|AccMgr|1|||20050110045505||ORU^R01|2|P|2.5.1\nPID|1||123||DUCK^DAISY\nOBX|1|NM|A||1\nOBX|2|NM|C||3\nOBX|2|NM|B||2\n",
+    )
+    .expect("can write file");
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("diff")
+        .arg("--colour")
+        .arg("never")
+        .arg("--ignore")
+        .arg("MSH-7,MSH-10")
+        .arg(&a)
+        .arg(&b);
+    cmd.assert()
+        .code(1)
+        .stdout(
+            "~\tPID.5.2\tDONALD\tDAISY\n\
+             +\tOBX[2].1\t\t2\n\
+             +\tOBX[2].2\t\tNM\n\
+             +\tOBX[2].3\t\tC\n\
+             +\tOBX[2].5\t\t3\n",
+        )
+        .stderr("");
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("diff").arg(&a).arg(&a);
+    cmd.assert().success().stdout("");
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn should_grep_directories() {
+    let dir = std::env::temp_dir().join(format!("hq-grep-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("b")).expect("can create temp dir");
+    std::fs::write(
+        dir.join("a.hl7"),
+        "MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|1|P|2.5.1\nPID|1||123^^^DUCK&1.2&ISO^MR||DUCK^DONALD\n",
+    )
+    .expect("can write file");
+    std::fs::write(
+        dir.join("b").join("c.hl7"),
+        "MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|1|P|2.5.1\nPID|1||456||MOUSE^MICKEY\n\
+         MSH|^~\\&|AccMgr|1|||20050110045504||ADT^A01|2|P|2.5.1\nPID|1||789||DUCK^DAISY\n",
+    )
+    .expect("can write file");
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("grep")
+        .arg("--colour")
+        .arg("never")
+        .arg("--data-type")
+        .arg("XPN")
+        .arg("--ignore-case")
+        .arg("duck")
+        .arg(&dir);
+    cmd.assert().success().stdout(format!(
+        "{a}\t1\tPID.5.1\tDUCK\n\
+         {c}\t2\tPID.5.1\tDUCK\n",
+        a = dir.join("a.hl7").display(),
+        c = dir.join("b").join("c.hl7").display(),
+    ));
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("grep").arg("GOOFY").arg(&dir);
+    cmd.assert().failure().stdout("");
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn should_filter_carriage_return_separated_messages() {
+    let input = "MSH|^~\\&|A|1|||20050110045504||ADT^A01|1|P|2.5.1\rPID|1||1234^^^A^MR\r\
+                 MSH|^~\\&|A|1|||20050110045504||ADT^A08|2|P|2.5.1\rPID|1||1234^^^A^MR\r";
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("filter")
+        .arg("--where")
+        .arg("MSH-9.2=A08")
+        .write_stdin(input);
+    cmd.assert()
+        .success()
+        .stdout("MSH|^~\\&|A|1|||20050110045504||ADT^A08|2|P|2.5.1\rPID|1||1234^^^A^MR\r")
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn should_filter_messages() {
+    let input = "FHS|^~\\&|A\r\n\
+                 MSH|^~\\&|A|1|||20050110045504||ADT^A01|1|P|2.5.1\r\nPID|1||1234^^^A^MR||DUCK^DONALD\r\n\
+                 MSH|^~\\&|A|1|||20050110045504||ADT^A08|2|P|2.5.1\r\nPID|1||1234^^^A^MR||DUCK^DONALD\r\n\
+                 MSH|^~\\&|A|1|||20050110045504||ADT^A08|3|P|2.5.1\r\nPID|1||5678^^^A^MR||MOUSE^MICKEY\r\n\
+                 MSH|^~\\&|A|1|||20050110045504||ADT^A08|4|P|2.5.1\r\nPID|1||1299^^^A^MR||DUCK^DAISY\r\n\
+                 FTS|1";
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("filter")
+        .arg("--where")
+        .arg("MSH-9.2=A08")
+        .arg("--where")
+        .arg("PID-3.1~^12")
+        .write_stdin(input);
+    cmd.assert().success().stdout(
+        "MSH|^~\\&|A|1|||20050110045504||ADT^A08|2|P|2.5.1\r\nPID|1||1234^^^A^MR||DUCK^DONALD\r\n\
+         MSH|^~\\&|A|1|||20050110045504||ADT^A08|4|P|2.5.1\r\nPID|1||1299^^^A^MR||DUCK^DAISY\r\n",
+    );
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("filter").arg("-w").arg("MSH-9.2=A04").write_stdin(input);
+    cmd.assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains("No messages matched"));
+}
+
+#[test]
+fn should_print_field_statistics() {
+    let input = "MSH|^~\\&|A|1|||20050110045504||ADT^A01|1|P|2.5.1\n\
+                 PID|1||123||DUCK^DONALD||19340609|MM\n\
+                 ZPD|1|x\n\
+                 MSH|^~\\&|A|1|||20050110045504||ADT^A08|2|P|2.5.1\n\
+                 PID|1||456||DUCK^DONALD\n";
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("stats").arg("--colour").arg("never").write_stdin(input);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "Messages\t2\n\nType\tEvent\tMessages\nADT\tA01\t1\nADT\tA08\t1\n",
+        ))
+        .stdout(predicate::str::contains("ZPD\t1\t1\t(Z-segment)\n"))
+        .stdout(predicate::str::contains(
+            "PID.5\t100.0%\t1\tDUCK^DONALD (2)\tPatient Name\n",
+        ))
+        .stdout(predicate::str::contains(
+            "PID.7\t50.0%\t1\t19340609 (1)\tDate/Time of Birth\n",
+        ))
+        .stdout(predicate::str::ends_with(
+            "PID.8\t1\t2\t1\tAdministrative Sex\n",
+        ));
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("--output")
+        .arg("json")
+        .arg("stats")
+        .arg("--colour")
+        .arg("never")
+        .write_stdin(input);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\"z_segment\": true"))
+        .stdout(predicate::str::contains("\"fill_rate\": 0.5"));
+}
+
+#[test]
+fn should_decode_field_statistics() {
+    let input = "MSH|^~\\&|A|1|||20050110045504||ADT^A01|1|P|2.5.1\n\
+                 PID|1||123||O\\T\\BRIEN^DONALD\n";
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("stats").arg("--colour").arg("never").write_stdin(input);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\tO&BRIEN^DONALD (1)\t"));
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("--output").arg("csv").arg("stats").write_stdin(input);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("hq stats can only print a table or JSON"));
+}
+
+#[test]
+fn should_generate_messages() {
+    let generate = || {
+        let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+        cmd.arg("-q")
+            .arg("MSH.9")
+            .arg("-q")
+            .arg("MSH.12")
+            .arg("-q")
+            .arg("MSH.3")
+            .arg("-m")
+            .arg("MSH.3=HQ")
+            .arg("generate")
+            .arg("ORU^R01")
+            .arg("--version")
+            .arg("2.4")
+            .arg("--count")
+            .arg("2")
+            .arg("--seed")
+            .arg("1234")
+            .arg("--colour")
+            .arg("never");
+        cmd
+    };
+    generate().assert().success().stdout(
+        "ORU^R01^ORU_R01\n2.4\nHQ\nORU^R01^ORU_R01\n2.4\nHQ\n",
+    );
+
+    let output = || {
+        let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+        cmd.arg("generate").arg("ADT^A01").arg("--seed").arg("1234");
+        cmd.output().expect("can run hq").stdout
+    };
+    assert_eq!(output(), output());
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("--validate")
+        .arg("generate")
+        .arg("ADT^A01")
+        .arg("--count")
+        .arg("20");
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("hq").expect("binary exists");
+    cmd.arg("generate").arg("XYZ^A01");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("No message structure for XYZ^A01"));
+}
@@ -1,81 +1,330 @@
-use crate::cli::AckMode;
+use crate::cli::{AckArgs, AckMode, Severity};
 use chrono::Utc;
-use color_eyre::{eyre::Context, Result};
-use hl7_parser::ParsedMessage;
-
-pub fn generate_ack(message: &str, ack_mode: AckMode) -> Result<(String, ParsedMessage)> {
-    match ack_mode {
-        AckMode::Success => {
-            let message =
-                ParsedMessage::parse(message, false).wrap_err_with(|| "Failed to parse message")?;
-            Ok((
-                compose_ack(&message, true).wrap_err_with(|| "Failed to compose ACK")?,
-                message,
-            ))
+use color_eyre::{
+    eyre::{eyre, Context},
+    Result,
+};
+use hl7_parser::{LocationQuery, ParsedMessage, Separators};
+use std::borrow::Cow;
+
+/// Generate the ACKs to send in response to a message, in the order they should be sent.
+///
+/// Messages in original mode (with neither MSH-15 nor MSH-16 valued) always get a single
+/// application ACK. Messages in enhanced mode get a commit ACK as requested by MSH-15, followed
+/// by an application ACK as requested by MSH-16, so there may be zero, one, or two ACKs.
+///
+/// The ACKs are written with the message's own field separator and encoding characters. Error
+/// and reject ACKs carry ERR segments, listing the failures found if `--validate` is set.
+pub fn generate_acks(message: &str, args: &AckArgs) -> Result<Vec<String>> {
+    let outcome = match args.ack_mode {
+        AckMode::Success => Outcome::Accept,
+        AckMode::Error => Outcome::Error,
+        AckMode::Reject => Outcome::Reject,
+        AckMode::Ignore => return Err(eyre!("ACK mode is set to Ignore")),
+    };
+
+    let header =
+        Header::parse(message).ok_or_else(|| eyre!("Message doesn't start with an MSH segment"))?;
+
+    let parsed = ParsedMessage::parse(&without_truncation_character(message), false).map(|_| ());
+    let (failures, accept_ack, application_ack) = if args.validate {
+        let mut failures = Vec::new();
+        if let Err(err) = parsed {
+            failures.push(Failure::new(
+                None,
+                "207",
+                format!("Failed to parse message: {err}"),
+            ));
         }
-        AckMode::Error => {
-            let message =
-                ParsedMessage::parse(message, false).wrap_err_with(|| "Failed to parse message")?;
-            Ok((
-                compose_ack(&message, false).wrap_err_with(|| "Failed to compose ACK")?,
-                message,
-            ))
+        failures.extend(validate_header(&header));
+        let accept_ack = AckRequest::from_str(header.field(15)).unwrap_or_default();
+        let application_ack = AckRequest::from_str(header.field(16)).unwrap_or_default();
+        (failures, accept_ack, application_ack)
+    } else {
+        parsed.wrap_err_with(|| "Failed to parse message")?;
+        let accept_ack = AckRequest::from_str(header.field(15))
+            .wrap_err_with(|| "Failed to parse accept ACK")?;
+        let application_ack = AckRequest::from_str(header.field(16))
+            .wrap_err_with(|| "Failed to parse application ACK")?;
+        (Vec::new(), accept_ack, application_ack)
+    };
+
+    let outcome = failures
+        .iter()
+        .fold(outcome, |outcome, failure| outcome.max(failure.outcome()));
+
+    let ack = |level| compose_ack(&header, level, outcome, &failures, args);
+    let mut acks = Vec::new();
+    if accept_ack.is_none() && application_ack.is_none() {
+        acks.push(ack(AckLevel::Application));
+    } else {
+        if accept_ack.is_some_and(|request| request.wants(outcome)) {
+            acks.push(ack(AckLevel::Commit));
+        }
+        if application_ack.is_some_and(|request| request.wants(outcome)) {
+            acks.push(ack(AckLevel::Application));
         }
-        AckMode::Ignore => Err(color_eyre::eyre::eyre!("ACK mode is set to Ignore")),
     }
+    Ok(acks)
 }
 
-fn compose_ack(message: &ParsedMessage, success: bool) -> Result<String> {
-    let accept_ack = message
-        .query_value("MSH.15")
-        .expect("valid query")
-        .unwrap_or_default();
-    let application_ack = message
-        .query_value("MSH.16")
-        .expect("valid query")
-        .unwrap_or_default();
+/// The header of a message, read straight from its source so that a message can be ACKed even
+/// if it doesn't parse
+struct Header<'m> {
+    separators: Separators,
+    /// The encoding characters (MSH-2), exactly as the sender wrote them
+    encoding_characters: &'m str,
+    /// The fields of the MSH segment, starting with the segment ID
+    fields: Vec<&'m str>,
+}
 
-    let accept_ack: Option<AckRequest> =
-        AckRequest::from_str(accept_ack).wrap_err_with(|| "Failed to parse accept ACK")?;
-    let application_ack: Option<AckRequest> = AckRequest::from_str(application_ack)
-        .wrap_err_with(|| "Failed to parse application ACK")?;
+impl<'m> Header<'m> {
+    fn parse(message: &'m str) -> Option<Header<'m>> {
+        let msh = message.split(['\r', '\n']).next()?;
+        let field = msh.strip_prefix("MSH")?.chars().next()?;
+        let fields: Vec<&str> = msh.split(field).collect();
+        let encoding_characters = *fields.get(1)?;
+        let mut encoding = encoding_characters.chars();
+        let separators = Separators {
+            field,
+            component: encoding.next()?,
+            repeat: encoding.next()?,
+            escape: encoding.next()?,
+            subcomponent: encoding.next()?,
+        };
+        Some(Header {
+            separators,
+            encoding_characters,
+            fields,
+        })
+    }
 
-    let is_enhanced_mode = accept_ack.is_some() || application_ack.is_some();
-    let ack_level = if is_enhanced_mode { 'C' } else { 'A' };
+    /// The source of a field of the MSH segment (MSH-3 onwards), or an empty string if it's
+    /// missing
+    fn field(&self, field: usize) -> &'m str {
+        self.fields.get(field - 1).copied().unwrap_or_default()
+    }
 
-    let control_id = message
-        .query_value("MSH.10")
-        .expect("valid query")
-        .unwrap_or_default();
+    /// The source of a component of the first repeat of a field of the MSH segment
+    fn component(&self, field: usize, component: usize) -> &'m str {
+        self.field(field)
+            .split(self.separators.repeat)
+            .next()
+            .and_then(|repeat| repeat.split(self.separators.component).nth(component - 1))
+            .unwrap_or_default()
+    }
 
-    let sapp = message
-        .query_value("MSH.3")
-        .expect("valid query")
-        .unwrap_or_default();
-    let sfac = message
-        .query_value("MSH.4")
-        .expect("valid query")
-        .unwrap_or_default();
-    let rapp = message
-        .query_value("MSH.5")
-        .expect("valid query")
-        .unwrap_or_default();
-    let rfac = message
-        .query_value("MSH.6")
-        .expect("valid query")
-        .unwrap_or_default();
-    let processing_id = message
-        .query_value("MSH.11")
-        .expect("valid query")
-        .unwrap_or_default();
-    let version = message
-        .query_value("MSH.12")
-        .expect("valid query")
-        .unwrap_or_default();
-    let trigger = message
-        .query_value("MSH.9.2")
-        .expect("valid query")
-        .unwrap_or_default();
+    /// Escape the separators (and newlines) in a value
+    fn escape(&self, value: &str) -> String {
+        let separators = &self.separators;
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            let sequence = if c == separators.field {
+                "F"
+            } else if c == separators.component {
+                "S"
+            } else if c == separators.repeat {
+                "R"
+            } else if c == separators.subcomponent {
+                "T"
+            } else if c == separators.escape {
+                "E"
+            } else if c == '\r' {
+                "X0D"
+            } else if c == '\n' {
+                "X0A"
+            } else {
+                escaped.push(c);
+                continue;
+            };
+            escaped.push(separators.escape);
+            escaped.push_str(sequence);
+            escaped.push(separators.escape);
+        }
+        escaped
+    }
+}
+
+/// hl7-parser only understands the four classic encoding characters, so drop the truncation
+/// character (the fifth encoding character, added in HL7 v2.7) before parsing the message
+pub fn without_truncation_character(message: &str) -> Cow<'_, str> {
+    let Some(header) = Header::parse(message) else {
+        return Cow::Borrowed(message);
+    };
+    let encoding_characters = header.encoding_characters;
+    match encoding_characters.char_indices().nth(4) {
+        Some((truncation, _)) => {
+            // the separators are all ASCII, so MSH-2 starts after `MSH|`
+            let start = "MSH|".len();
+            let truncation = start + truncation;
+            let end = start + encoding_characters.len();
+            Cow::Owned(format!("{}{}", &message[..truncation], &message[end..]))
+        }
+        None => Cow::Borrowed(message),
+    }
+}
+
+/// A problem found with a message, reported in an ERR segment
+#[derive(Debug, Clone)]
+struct Failure {
+    location: Option<LocationQuery>,
+    /// The HL7 error code (table 0357)
+    code: &'static str,
+    message: String,
+}
+
+impl Failure {
+    fn new(location: Option<LocationQuery>, code: &'static str, message: String) -> Failure {
+        Failure {
+            location,
+            code,
+            message,
+        }
+    }
+
+    /// The outcome the failure calls for. A message whose type (MSH-9), processing ID (MSH-11),
+    /// or version ID (MSH-12) can't be handled is rejected outright; anything else is an error.
+    fn outcome(&self) -> Outcome {
+        match &self.location {
+            Some(location)
+                if location.segment == "MSH"
+                    && matches!(location.field.map(|field| field.get()), Some(9 | 11 | 12)) =>
+            {
+                Outcome::Reject
+            }
+            _ => Outcome::Error,
+        }
+    }
+}
+
+const VERSIONS: &[&str] = &[
+    "2.1", "2.2", "2.3", "2.3.1", "2.4", "2.5", "2.5.1", "2.6", "2.7", "2.7.1", "2.8", "2.8.1",
+    "2.8.2",
+];
+
+/// Check that the header has the fields needed to process and acknowledge the message
+fn validate_header(header: &Header) -> Vec<Failure> {
+    let location = |field, component: Option<usize>| {
+        let mut location = LocationQuery::new_field("MSH", field).expect("valid location");
+        location.component = component.and_then(std::num::NonZeroUsize::new);
+        Some(location)
+    };
+
+    let mut failures = Vec::new();
+    for (field, component, name) in [
+        (9, Some(1), "message code"),
+        (9, Some(2), "trigger event"),
+        (10, None, "message control ID"),
+        (11, Some(1), "processing ID"),
+        (12, Some(1), "version ID"),
+    ] {
+        let value = match component {
+            Some(component) => header.component(field, component),
+            None => header.field(field),
+        };
+        if value.is_empty() {
+            let path = match component {
+                Some(component) => format!("MSH-{field}.{component}"),
+                None => format!("MSH-{field}"),
+            };
+            failures.push(Failure::new(
+                location(field, component),
+                "101",
+                format!("Required field {path} ({name}) is missing"),
+            ));
+        }
+    }
+
+    let processing_id = header.component(11, 1);
+    if !processing_id.is_empty() && !["D", "P", "T"].contains(&processing_id) {
+        failures.push(Failure::new(
+            location(11, Some(1)),
+            "202",
+            format!("Unsupported processing ID {processing_id:?}"),
+        ));
+    }
+    let version = header.component(12, 1);
+    if !version.is_empty() && !VERSIONS.contains(&version) {
+        failures.push(Failure::new(
+            location(12, Some(1)),
+            "203",
+            format!("Unsupported version ID {version:?}"),
+        ));
+    }
+    for (field, name) in [(15, "accept"), (16, "application")] {
+        let value = header.field(field);
+        if AckRequest::from_str(value).is_err() {
+            failures.push(Failure::new(
+                location(field, None),
+                "103",
+                format!("Invalid {name} acknowledgment type {value:?} (table 0155)"),
+            ));
+        }
+    }
+    failures
+}
+
+/// The description of an HL7 error code (table 0357)
+fn error_code_text(code: &str) -> &'static str {
+    match code {
+        "0" => "Message accepted",
+        "100" => "Segment sequence error",
+        "101" => "Required field missing",
+        "102" => "Data type error",
+        "103" => "Table value not found",
+        "104" => "Value too long",
+        "200" => "Unsupported message type",
+        "201" => "Unsupported event code",
+        "202" => "Unsupported processing id",
+        "203" => "Unsupported version id",
+        "204" => "Unknown key identifier",
+        "205" => "Duplicate key identifier",
+        "206" => "Application record locked",
+        "207" => "Application internal error",
+        _ => "",
+    }
+}
+
+/// Whether an ACK acknowledges the receipt of a message (a commit ACK) or its processing (an
+/// application ACK)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum AckLevel {
+    Commit,
+    Application,
+}
+
+/// The outcome an ACK reports, from best to worst
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Outcome {
+    Accept,
+    Error,
+    Reject,
+}
+
+/// Compose an ACK for a message. Values copied from the message are copied as they were sent,
+/// so they stay correctly escaped as long as the ACK uses the message's separators.
+fn compose_ack(
+    header: &Header,
+    level: AckLevel,
+    outcome: Outcome,
+    failures: &[Failure],
+    args: &AckArgs,
+) -> String {
+    let (field, component) = (header.separators.field, header.separators.component);
+    let encoding_characters = header.encoding_characters;
+    let ack_level = match level {
+        AckLevel::Commit => 'C',
+        AckLevel::Application => 'A',
+    };
+
+    let control_id = header.field(10);
+    let sapp = header.field(3);
+    let sfac = header.field(4);
+    let rapp = header.field(5);
+    let rfac = header.field(6);
+    let processing_id = header.field(11);
+    let version = header.field(12);
+    let trigger = header.component(9, 2);
 
     use rand::distributions::{Alphanumeric, DistString};
     let new_control_id = Alphanumeric.sample_string(&mut rand::thread_rng(), 20);
@@ -84,22 +333,156 @@ fn compose_ack(message: &ParsedMessage, success: bool) -> Result<String> {
     let now = now.format("%Y%m%d%H%M%S").to_string();
 
     let msh = format!(
-        "MSH|^~\\&|{rapp}|{rfac}|{sapp}|{sfac}|{now}||ACK^{trigger}^ACK|{new_control_id}|{processing_id}|{version}",
+        "MSH{field}{encoding_characters}{field}{rapp}{field}{rfac}{field}{sapp}{field}{sfac}{field}{now}{field}{field}ACK{component}{trigger}{component}ACK{field}{new_control_id}{field}{processing_id}{field}{version}",
     );
 
+    let text = match (&args.ack_text, outcome) {
+        (Some(text), _) => text.as_str(),
+        (None, Outcome::Accept) => "Message accepted",
+        (None, Outcome::Error | Outcome::Reject) => "Message rejected",
+    };
     let msa = format!(
-        "MSA|{ack_level}{success}|{control_id}|{error_message}",
-        success = if success { 'A' } else { 'E' },
-        error_message = if success {
-            "Message accepted"
-        } else {
-            "Message rejected"
+        "MSA{field}{ack_level}{outcome}{field}{control_id}{field}{text}",
+        outcome = match outcome {
+            Outcome::Accept => 'A',
+            Outcome::Error => 'E',
+            Outcome::Reject => 'R',
         },
+        text = header.escape(text),
     );
 
-    Ok(format!("{}\r{}", msh, msa))
+    let mut segments = vec![msh, msa];
+    if outcome != Outcome::Accept {
+        let errors: Vec<ErrorReport> = if failures.is_empty() {
+            vec![ErrorReport {
+                location: args.error_location.as_ref(),
+                code: &args.error_code,
+                message: None,
+            }]
+        } else {
+            failures
+                .iter()
+                .map(|failure| ErrorReport {
+                    location: failure.location.as_ref(),
+                    code: failure.code,
+                    message: Some(&failure.message),
+                })
+                .collect()
+        };
+        if is_before(header.component(12, 1), (2, 5)) {
+            segments.push(compose_eld_err(header, &errors));
+        } else {
+            for error in errors.iter() {
+                segments.push(compose_err(header, error, args));
+            }
+        }
+    }
+    segments.join("\r")
+}
+
+/// An error to report in an ERR segment
+struct ErrorReport<'a> {
+    location: Option<&'a LocationQuery>,
+    /// The HL7 error code (table 0357)
+    code: &'a str,
+    message: Option<&'a str>,
 }
 
+/// Whether a version ID (MSH-12) is before a major and minor version. v2.5 replaced ERR-1
+/// (error code and location) with separate fields for the location, code, severity, etc., and
+/// v2.7 added the fatal severity. Versions which can't be read are treated as recent.
+fn is_before(version: &str, before: (u32, u32)) -> bool {
+    let mut parts = version.split('.').map(|part| part.parse::<u32>());
+    match (parts.next(), parts.next()) {
+        (Some(Ok(major)), Some(Ok(minor))) => (major, minor) < before,
+        _ => false,
+    }
+}
+
+/// Compose an ERR segment for versions before v2.5, where the ACK has a single ERR segment and
+/// each error is a repeat of ERR-1 (ELD) with the segment, sequence, and field of the error
+/// and its code. There is nowhere to send the severity or diagnostic information.
+fn compose_eld_err(header: &Header, errors: &[ErrorReport]) -> String {
+    let separators = &header.separators;
+    let component = separators.component.to_string();
+    let repeats: Vec<String> = errors
+        .iter()
+        .map(|error| {
+            let location = match error.location {
+                Some(location) => vec![
+                    location.segment.clone(),
+                    "1".to_string(),
+                    location
+                        .field
+                        .map(|field| field.to_string())
+                        .unwrap_or_default(),
+                ],
+                None => vec![String::new(); 3],
+            };
+            let code = format!(
+                "{code}{subcomponent}{text}{subcomponent}HL70357",
+                code = header.escape(error.code),
+                text = error_code_text(error.code),
+                subcomponent = separators.subcomponent,
+            );
+            let mut parts = location;
+            parts.push(code);
+            parts.join(&component)
+        })
+        .collect();
+    format!(
+        "ERR{field}{repeats}",
+        field = separators.field,
+        repeats = repeats.join(&separators.repeat.to_string())
+    )
+}
+
+/// Compose an ERR segment with the error location (ERR-2), code (ERR-3), severity (ERR-4), and
+/// diagnostic information (ERR-7)
+fn compose_err(header: &Header, error: &ErrorReport, args: &AckArgs) -> String {
+    let (field, component) = (header.separators.field, header.separators.component);
+
+    let location = error
+        .location
+        .map(|location| {
+            let mut parts = vec![location.segment.clone(), "1".to_string()];
+            if let Some(field) = location.field {
+                parts.push(field.to_string());
+                if location.repeat.is_some() || location.component.is_some() {
+                    parts.push(location.repeat.map_or(1, |repeat| repeat.get()).to_string());
+                }
+                if let Some(component) = location.component {
+                    parts.push(component.to_string());
+                    if let Some(sub_component) = location.sub_component {
+                        parts.push(sub_component.to_string());
+                    }
+                }
+            }
+            parts.join(&component.to_string())
+        })
+        .unwrap_or_default();
+    let code = format!(
+        "{code}{component}{text}{component}HL70357",
+        code = header.escape(error.code),
+        text = error_code_text(error.code),
+    );
+    let severity = match args.severity {
+        // table 0516 only has fatal errors from v2.7
+        Severity::Fatal if is_before(header.component(12, 1), (2, 7)) => Severity::Error.code(),
+        severity => severity.code(),
+    };
+    let message = error
+        .message
+        .map(|message| header.escape(message))
+        .unwrap_or_default();
+
+    let err = format!(
+        "ERR{field}{field}{location}{field}{code}{field}{severity}{field}{field}{field}{message}"
+    );
+    err.trim_end_matches(field).to_string()
+}
+
+/// An acknowledgment type from HL7 table 0155, used in MSH-15 and MSH-16
 #[derive(Debug, Copy, Clone)]
 enum AckRequest {
     Always,
@@ -119,4 +502,189 @@ impl AckRequest {
             _ => Err(color_eyre::eyre::eyre!("Invalid ACK request")),
         }
     }
+
+    /// Whether an ACK should be sent for a message with the given outcome
+    fn wants(self, outcome: Outcome) -> bool {
+        match self {
+            AckRequest::Always => true,
+            AckRequest::Never => false,
+            AckRequest::Success => outcome == Outcome::Accept,
+            AckRequest::Error => outcome != Outcome::Accept,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn args(args: &[&str]) -> AckArgs {
+        let cli = crate::cli::Cli::parse_from(["hs", "listen"].iter().chain(args));
+        let crate::cli::Command::Listen { ack, .. } = cli.command else {
+            panic!("listen command");
+        };
+        ack
+    }
+
+    fn query(ack: &str, query: &str) -> String {
+        let ack = ParsedMessage::parse(ack, false).expect("can parse ACK");
+        ack.query_value(query)
+            .expect("valid query")
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    fn acks(accept_ack: &str, application_ack: &str, ack_mode: &str) -> Vec<String> {
+        let message = format!(
+            "MSH|^~\\&|A|1|B|2|20050110045504||ADT^A01|599102|P|2.5.1|||{accept_ack}|{application_ack}\rPID|1"
+        );
+        let acks = generate_acks(&message, &args(&["-a", ack_mode])).expect("can generate ACKs");
+        acks.iter().map(|ack| query(ack, "MSA.1")).collect()
+    }
+
+    #[test]
+    fn original_mode_sends_an_application_ack() {
+        assert_eq!(acks("", "", "success"), vec!["AA"]);
+        assert_eq!(acks("", "", "error"), vec!["AE"]);
+        assert_eq!(acks("", "", "reject"), vec!["AR"]);
+    }
+
+    #[test]
+    fn enhanced_mode_follows_msh_15_and_msh_16() {
+        assert_eq!(acks("AL", "AL", "success"), vec!["CA", "AA"]);
+        assert_eq!(acks("AL", "NE", "success"), vec!["CA"]);
+        assert_eq!(acks("NE", "AL", "error"), vec!["AE"]);
+        assert_eq!(acks("NE", "NE", "success"), Vec::<String>::new());
+        assert_eq!(acks("", "SU", "success"), vec!["AA"]);
+        assert_eq!(acks("", "SU", "error"), Vec::<String>::new());
+        assert_eq!(acks("ER", "ER", "success"), Vec::<String>::new());
+        assert_eq!(acks("ER", "ER", "error"), vec!["CE", "AE"]);
+        assert_eq!(acks("ER", "ER", "reject"), vec!["CR", "AR"]);
+    }
+
+    #[test]
+    fn acks_use_the_senders_delimiters() {
+        let message =
+            "MSH#$%@*#APP$1.2.3$ISO#FAC##RAPP#20050110045504##ADT$A01#599102#P#2.5.1\rPID#1";
+        let acks = generate_acks(message, &args(&[])).expect("can generate ACKs");
+        assert_eq!(acks.len(), 1);
+        assert!(acks[0].starts_with("MSH#$%@*##RAPP#APP$1.2.3$ISO#FAC#"));
+        assert!(acks[0].contains("#ACK$A01$ACK#"));
+        assert!(acks[0].ends_with("\rMSA#AA#599102#Message accepted"));
+        assert_eq!(query(&acks[0], "MSA.1"), "AA");
+    }
+
+    #[test]
+    fn acks_keep_the_truncation_character() {
+        let message = "MSH|^~\\&#|APP|FAC|||20050110045504||ADT^A01|599102|P|2.7\rPID|1";
+        let acks = generate_acks(message, &args(&["-a", "error"])).expect("can generate ACKs");
+        assert_eq!(acks.len(), 1);
+        assert!(acks[0].starts_with("MSH|^~\\&#|||APP|FAC|"));
+        assert!(acks[0].contains("\rMSA|AE|599102|Message rejected\rERR|"));
+    }
+
+    #[test]
+    fn error_acks_carry_err_segments() {
+        let message = "MSH|^~\\&|A|1|B|2|20050110045504||ADT^A01|599102|P|2.5.1\rPID|1";
+        let acks = generate_acks(message, &args(&[])).expect("can generate ACKs");
+        assert!(!acks[0].contains("\rERR|"));
+
+        let acks = generate_acks(
+            message,
+            &args(&[
+                "-a",
+                "reject",
+                "--ack-text",
+                "Unknown patient | try again",
+                "--error-code",
+                "204",
+                "--severity",
+                "fatal",
+                "--error-location",
+                "PID.3.1",
+            ]),
+        )
+        .expect("can generate ACKs");
+        let ack = &acks[0];
+        assert_eq!(query(ack, "MSA.1"), "AR");
+        assert_eq!(query(ack, "MSA.3"), "Unknown patient \\F\\ try again");
+        assert_eq!(query(ack, "ERR.2"), "PID^1^3^1^1");
+        assert_eq!(query(ack, "ERR.3"), "204^Unknown key identifier^HL70357");
+        // fatal errors are only in v2.7 and later
+        assert_eq!(query(ack, "ERR.4"), "E");
+
+        let message = message.replace("|2.5.1", "|2.7");
+        let acks = generate_acks(&message, &args(&["-a", "reject", "--severity", "fatal"]))
+            .expect("can generate ACKs");
+        assert_eq!(query(&acks[0], "ERR.4"), "F");
+    }
+
+    #[test]
+    fn validation_failures_are_listed_in_err_segments() {
+        let message = "MSH|^~\\&|A|1|B|2|20050110045504||ADT|599102|X|2.5.1||||XX\rPID|1";
+        let acks = generate_acks(message, &args(&["--validate"])).expect("can generate ACKs");
+        assert_eq!(acks.len(), 1);
+        let errors: Vec<&str> = acks[0].split('\r').skip(2).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "ERR||MSH^1^9^1^2|101^Required field missing^HL70357|E|||Required field MSH-9.2 (trigger event) is missing",
+                "ERR||MSH^1^11^1^1|202^Unsupported processing id^HL70357|E|||Unsupported processing ID \"X\"",
+                "ERR||MSH^1^16|103^Table value not found^HL70357|E|||Invalid application acknowledgment type \"XX\" (table 0155)",
+            ]
+        );
+        assert_eq!(query(&acks[0], "MSA.1"), "AR");
+
+        // failures outside of MSH-9, MSH-11, and MSH-12 are errors rather than rejections
+        let message = "MSH|^~\\&|A|1|B|2|20050110045504||ADT^A01|599102|P|2.5.1||||XX\rPID|1";
+        let acks = generate_acks(message, &args(&["--validate"])).expect("can generate ACKs");
+        assert_eq!(query(&acks[0], "MSA.1"), "AE");
+        let message = "MSH|^~\\&|A|1|B|2|20050110045504||ADT^A01|599102|P|9.9|||AL|AL\rPID|1";
+        let acks = generate_acks(message, &args(&["--validate"])).expect("can generate ACKs");
+        assert_eq!(query(&acks[0], "MSA.1"), "CR");
+        assert_eq!(query(&acks[1], "MSA.1"), "AR");
+
+        let message = "MSH|^~\\&|A|1|B|2|20050110045504||ADT^A01|599102|P|2.5.1";
+        assert!(generate_acks(message, &args(&[])).is_err());
+        let acks = generate_acks(message, &args(&["--validate"])).expect("can generate ACKs");
+        assert!(
+            acks[0].contains("\rERR|||207^Application internal error^HL70357|E|||Failed to parse")
+        );
+    }
+
+    #[test]
+    fn acks_before_v2_5_use_err_1() {
+        let message = "MSH|^~\\&|A|1|B|2|20050110045504||ADT|599102|X|2.4\rPID|1";
+        let acks = generate_acks(message, &args(&["--validate"])).expect("can generate ACKs");
+        let errors: Vec<&str> = acks[0].split('\r').skip(2).collect();
+        assert_eq!(
+            errors,
+            vec!["ERR|MSH^1^9^101&Required field missing&HL70357~MSH^1^11^202&Unsupported processing id&HL70357"]
+        );
+
+        let message = "MSH|^~\\&|A|1|B|2|20050110045504||ADT^A01|599102|P|2.3.1\rPID|1";
+        let acks = generate_acks(message, &args(&["-a", "error"])).expect("can generate ACKs");
+        assert!(acks[0].ends_with("\rERR|^^^207&Application internal error&HL70357"));
+        let acks = generate_acks(
+            message,
+            &args(&[
+                "-a",
+                "reject",
+                "--error-code",
+                "204",
+                "--error-location",
+                "PID.3.1",
+            ]),
+        )
+        .expect("can generate ACKs");
+        assert!(acks[0].ends_with("\rERR|PID^1^3^204&Unknown key identifier&HL70357"));
+
+        assert!(is_before("2.1", (2, 5)));
+        assert!(is_before("2.3.1", (2, 5)));
+        assert!(!is_before("2.5", (2, 5)));
+        assert!(is_before("2.6", (2, 7)));
+        assert!(!is_before("2.8.2", (2, 5)));
+        assert!(!is_before("", (2, 5)));
+    }
 }
@@ -1,4 +1,5 @@
-use clap::{ColorChoice, Parser, Subcommand, ValueEnum};
+use clap::{Args, ColorChoice, Parser, Subcommand, ValueEnum};
+use hl7_parser::LocationQuery;
 use std::{
     net::{SocketAddr, ToSocketAddrs},
     path::PathBuf,
@@ -78,16 +79,67 @@ pub enum Command {
         /// If not specified, the server will run until killed
         message_count: Option<usize>,
 
-        #[arg(short, long, default_value_t = AckMode::Success)]
-        /// The mode to use for sending ACKs
-        ack_mode: AckMode,
+        #[command(flatten)]
+        ack: AckArgs,
+
+        #[arg(short, long)]
+        /// A TOML or YAML file of rules choosing how to respond to each message
+        ///
+        /// Each rule matches messages by their contents (ex: `MSH-9.2=A08` and `PID-3.1~^9`) and
+        /// picks the ACK mode, MSA and ERR contents, and a delay before responding. Messages
+        /// which don't match any rule are responded to according to the other options.
+        rules: Option<PathBuf>,
 
         #[arg(short, long, default_value = "127.0.0.1:2575", value_parser = parse_socket_addr)]
         /// The address to bind to in the form of <host>:<port>
+        ///
+        /// Use port 0 to listen on any free port, which is logged with `-v`
         bind: SocketAddr,
     },
 }
 
+#[derive(Args, Debug, Clone)]
+pub struct AckArgs {
+    #[arg(short, long, default_value_t = AckMode::Success)]
+    /// The mode to use for sending ACKs
+    pub ack_mode: AckMode,
+
+    #[arg(long)]
+    /// The text to send in MSA-3 of the ACKs
+    ///
+    /// Defaults to "Message accepted" for success ACKs and "Message rejected" otherwise
+    pub ack_text: Option<String>,
+
+    #[arg(long, default_value = "207")]
+    /// The HL7 error code (table 0357) to send in ERR-3 of error and reject ACKs (or ERR-1 for
+    /// messages before v2.5)
+    ///
+    /// Example: `207` (application internal error) or `204` (unknown key identifier)
+    pub error_code: String,
+
+    #[arg(long, default_value_t = Severity::Error)]
+    /// The severity to send in ERR-4 of error and reject ACKs (not sent for messages before
+    /// v2.5, which have no severity field, and fatal is sent as an error before v2.7)
+    pub severity: Severity,
+
+    #[arg(long)]
+    /// The location of the error to send in ERR-2 of error and reject ACKs (or ERR-1 for
+    /// messages before v2.5, which only has the segment and field)
+    ///
+    /// Example: `PID.3.1`
+    pub error_location: Option<LocationQuery>,
+
+    #[arg(long, default_value_t = false)]
+    /// Check that the received messages parse and have a valid header
+    ///
+    /// Messages which fail the checks are sent an error ACK (or a reject ACK, if the ACK mode
+    /// is `reject` or the message type, processing ID, or version ID can't be handled) with an
+    /// ERR segment for each failure (or a repeat of ERR-1, for messages before v2.5). Without
+    /// this, a message which fails to parse closes its connection without an ACK, and the
+    /// failure is logged.
+    pub validate: bool,
+}
+
 pub fn cli() -> Cli {
     Cli::parse()
 }
@@ -108,6 +160,9 @@ pub enum AckMode {
     Success,
     /// Parse the received messages and send ACKs as if the message failed to process
     Error,
+    /// Parse the received messages and send ACKs as if the message was rejected without being
+    /// processed
+    Reject,
 }
 
 impl std::fmt::Display for AckMode {
@@ -116,6 +171,43 @@ impl std::fmt::Display for AckMode {
             AckMode::Ignore => write!(f, "ignore"),
             AckMode::Success => write!(f, "success"),
             AckMode::Error => write!(f, "error"),
+            AckMode::Reject => write!(f, "reject"),
+        }
+    }
+}
+
+/// The severity of an error in an ACK (HL7 table 0516)
+#[derive(Debug, ValueEnum, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    /// The message was processed, but with an error (`E`)
+    Error,
+    /// The message was processed, but with a warning (`W`)
+    Warning,
+    /// The message was processed, with some information for the sender (`I`)
+    Information,
+    /// The message could not be processed at all (`F`, HL7 v2.7+)
+    Fatal,
+}
+
+impl Severity {
+    /// The code for the severity in HL7 table 0516
+    pub fn code(&self) -> char {
+        match self {
+            Severity::Error => 'E',
+            Severity::Warning => 'W',
+            Severity::Information => 'I',
+            Severity::Fatal => 'F',
+        }
+    }
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Information => write!(f, "information"),
+            Severity::Fatal => write!(f, "fatal"),
         }
     }
 }
@@ -4,15 +4,21 @@ use futures::{SinkExt, StreamExt};
 use hl7_mllp_codec::MllpCodec;
 use std::fmt::Display;
 use std::io::{IsTerminal, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use termcolor::{Color, ColorSpec, StandardStream, WriteColor};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::task::{JoinError, JoinSet};
 use tokio::time::timeout;
 use tokio_util::codec::Framed;
+use tokio_util::sync::CancellationToken;
 
 mod ack;
 mod cli;
 mod print;
+mod rules;
 
 fn log<S: Display>(s: S, level: u8, stderr: &mut StandardStream) -> Result<()> {
     let mut colour = ColorSpec::new();
@@ -23,6 +29,8 @@ fn log<S: Display>(s: S, level: u8, stderr: &mut StandardStream) -> Result<()> {
         _ => colour.set_fg(Some(Color::White)),
     };
 
+    // lock the stream so lines logged by different connections don't interleave
+    let mut stderr = stderr.lock();
     stderr
         .set_color(colour)
         .wrap_err_with(|| "Failed to set terminal colour")?;
@@ -64,8 +72,8 @@ async fn main() -> Result<()> {
 
     let cli = cli::cli();
     let loglevel = cli.verbose;
-    let mut stdout = open_stdout(&cli);
-    let mut stderr = open_stderr(&cli);
+    let mut stdout = open_stdout(cli.colour);
+    let mut stderr = open_stderr(cli.colour);
 
     match cli.command {
         cli::Command::Send {
@@ -151,104 +159,257 @@ async fn main() -> Result<()> {
         }
         cli::Command::Listen {
             message_count,
-            ack_mode,
+            ack,
+            rules,
             bind,
         } => {
+            let rules = match &rules {
+                Some(rules) => {
+                    debug!(stderr, loglevel, "Reading rules from file: {:?}", rules);
+                    rules::load_rules(rules)?
+                }
+                None => Vec::new(),
+            };
+
             debug!(stderr, loglevel, "Starting to listen on {bind}");
             let listener = TcpListener::bind(&bind)
                 .await
                 .wrap_err_with(|| format!("Failed to start listening on {bind}"))?;
-            info!(stderr, loglevel, "Listening on {bind}");
-
-            let mut received_messages: usize = 0;
-            'accept: loop {
-                let Ok((stream, remote)) = listener.accept().await else {
-                    info!(stderr, loglevel, "Failed to accept connection");
-                    continue 'accept;
-                };
-                trace!(stderr, loglevel, "Remote connection: {:?}", remote);
-
-                let mut transport = Framed::new(stream, MllpCodec::new());
-                'messages: while let Some(result) = transport.next().await {
-                    trace!(stderr, loglevel, "Received message");
-                    trace!(stderr, loglevel, "Message bytes:\n{:?}", result);
-                    let Ok(message) = result else {
-                        break 'messages;
-                    };
-                    let message = String::from_utf8_lossy(&message);
-                    let message = if cli.no_correct_newlines {
-                        trace!(stderr, loglevel, "Not correcting newlines");
-                        message.to_string()
-                    } else {
-                        trace!(stderr, loglevel, "Correcting newlines");
-                        correct_newlines(message.as_ref())
-                    };
-
-                    let ack = match ack_mode {
-                        cli::AckMode::Success => {
-                            debug!(stderr, loglevel, "Generating success ACK");
-                            Some(
-                                ack::generate_ack(&message, cli::AckMode::Success)
-                                    .wrap_err_with(|| "Failed to generate ACK")?,
-                            )
-                        }
-                        cli::AckMode::Error => {
-                            debug!(stderr, loglevel, "Generating error ACK");
-                            Some(
-                                ack::generate_ack(&message, cli::AckMode::Error)
-                                    .wrap_err_with(|| "Failed to generate ACK")?,
-                            )
-                        }
-                        cli::AckMode::Ignore => {
-                            debug!(stderr, loglevel, "Not generating ACK");
-                            None
-                        }
-                    };
-                    let parsed_message = if let Some((ack, parsed_message)) = ack {
-                        info!(stderr, loglevel, "Sending ACK");
-                        debug!(stderr, loglevel, "ACK:\n{}", ack);
-                        transport
-                            .send(BytesMut::from(ack.as_bytes()))
-                            .await
-                            .wrap_err_with(|| "Failed to send ACK")?;
-                        Some(parsed_message)
-                    } else {
-                        None
-                    };
-                    if let Some(parsed_message) = parsed_message {
-                        print::print_message_hl(&mut stdout, parsed_message)
-                            .wrap_err_with(|| "Failed to print message")?;
-                    } else {
-                        print::print_message_nohl(&message)
-                            .wrap_err_with(|| "Failed to print message")?;
-                    }
+            // log the address actually bound to, which is only known after binding to port 0
+            let address = listener
+                .local_addr()
+                .wrap_err_with(|| format!("Failed to get the address bound to for {bind}"))?;
+            info!(stderr, loglevel, "Listening on {address}");
 
-                    received_messages += 1;
-                    trace!(
-                        stderr,
-                        loglevel,
-                        "Received {} messages so far",
-                        received_messages
-                    );
-                    if let Some(message_count) = message_count {
-                        if received_messages >= message_count {
-                            info!(
-                                stderr,
-                                loglevel, "Received {} messages, quitting", received_messages
-                            );
-                            break 'accept;
-                        }
+            let server = Arc::new(Server {
+                colour: cli.colour,
+                loglevel,
+                no_correct_newlines: cli.no_correct_newlines,
+                ack,
+                rules,
+                message_count,
+                received_messages: AtomicUsize::new(0),
+                handled_messages: AtomicUsize::new(0),
+                shutdown: CancellationToken::new(),
+            });
+            let mut connections = JoinSet::new();
+            loop {
+                tokio::select! {
+                    accepted = listener.accept() => {
+                        let (stream, remote) = match accepted {
+                            Ok(accepted) => accepted,
+                            Err(err) => {
+                                info!(stderr, loglevel, "Failed to accept connection: {err}");
+                                // errors such as running out of file descriptors (EMFILE) last
+                                // until connections close, so don't spin retrying straight away
+                                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                                continue;
+                            }
+                        };
+                        debug!(stderr, loglevel, "[{remote}] Accepted connection");
+                        let server = server.clone();
+                        connections.spawn(async move {
+                            (remote, handle_connection(stream, remote, server).await)
+                        });
                     }
+                    Some(result) = connections.join_next(), if !connections.is_empty() => {
+                        log_connection_result(result, &mut stderr)?;
+                    }
+                    _ = server.shutdown.cancelled() => break,
                 }
             }
+
+            // stop accepting connections, but let the open ones finish responding to the
+            // messages they've already received
+            drop(listener);
+            while let Some(result) = connections.join_next().await {
+                log_connection_result(result, &mut stderr)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// How long to wait before accepting connections again after failing to accept one
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The options and state shared by all of the connections to `hs listen`
+struct Server {
+    colour: clap::ColorChoice,
+    loglevel: u8,
+    no_correct_newlines: bool,
+    ack: cli::AckArgs,
+    rules: Vec<rules::Rule>,
+    /// The number of messages to receive across all connections before quitting
+    message_count: Option<usize>,
+    /// The number of messages received, including any still being responded to
+    received_messages: AtomicUsize,
+    /// The number of messages which have been responded to and printed
+    handled_messages: AtomicUsize,
+    /// Cancelled once `message_count` messages have been handled, so that connections stop
+    /// waiting for more messages
+    shutdown: CancellationToken,
+}
+
+/// Log a connection which ended with an error, so that one bad connection doesn't stop the
+/// server from serving the others
+fn log_connection_result(
+    result: std::result::Result<(SocketAddr, Result<()>), JoinError>,
+    stderr: &mut StandardStream,
+) -> Result<()> {
+    match result {
+        Ok((_, Ok(()))) => Ok(()),
+        Ok((remote, Err(err))) => log(format!("[{remote}] Connection failed: {err:#}"), 0, stderr),
+        Err(err) => log(format!("Connection task failed: {err}"), 0, stderr),
+    }
+}
+
+/// Receive messages from a connection, responding to and printing each one, until the sender
+/// disconnects or the server has received enough messages
+async fn handle_connection(
+    stream: TcpStream,
+    remote: SocketAddr,
+    server: Arc<Server>,
+) -> Result<()> {
+    let loglevel = server.loglevel;
+    let mut stderr = open_stderr(server.colour);
+
+    let mut transport = Framed::new(stream, MllpCodec::new());
+    loop {
+        let result = tokio::select! {
+            result = transport.next() => result,
+            _ = server.shutdown.cancelled() => break,
+        };
+        let Some(result) = result else {
+            break;
+        };
+        trace!(stderr, loglevel, "[{remote}] Received message");
+        trace!(stderr, loglevel, "[{remote}] Message bytes:\n{:?}", result);
+        let Ok(message) = result else {
+            break;
+        };
+        // claim one of the `message_count` messages, so that messages arriving on other
+        // connections at the same time can't take the server past it
+        let received_messages = server.received_messages.fetch_add(1, Ordering::SeqCst) + 1;
+        if server
+            .message_count
+            .is_some_and(|message_count| received_messages > message_count)
+        {
+            // other connections received the last messages, and the server is quitting
+            break;
+        }
+
+        let result = handle_message(&mut transport, &message, remote, &server).await;
+
+        // only count the message once it has been responded to and printed, so that the
+        // server doesn't quit while other connections are still responding
+        let handled_messages = server.handled_messages.fetch_add(1, Ordering::SeqCst) + 1;
+        trace!(
+            stderr,
+            loglevel,
+            "[{remote}] Received {} messages so far",
+            handled_messages
+        );
+        if server.message_count == Some(handled_messages) {
+            info!(
+                stderr,
+                loglevel, "[{remote}] Received {} messages, quitting", handled_messages
+            );
+            server.shutdown.cancel();
+        }
+        result?;
+    }
+    trace!(stderr, loglevel, "[{remote}] Connection closed");
+
+    Ok(())
+}
+
+/// Respond to a message as the rules and ACK options say, then print it
+async fn handle_message(
+    transport: &mut Framed<TcpStream, MllpCodec>,
+    message: &[u8],
+    remote: SocketAddr,
+    server: &Server,
+) -> Result<()> {
+    let loglevel = server.loglevel;
+    let mut stdout = open_stdout(server.colour);
+    let mut stderr = open_stderr(server.colour);
+
+    let message = String::from_utf8_lossy(message);
+    let message = if server.no_correct_newlines {
+        trace!(stderr, loglevel, "[{remote}] Not correcting newlines");
+        message.to_string()
+    } else {
+        trace!(stderr, loglevel, "[{remote}] Correcting newlines");
+        correct_newlines(message.as_ref())
+    };
+
+    let (response, delay) = match rules::find_rule(&server.rules, &message) {
+        Some((i, rule)) => {
+            debug!(stderr, loglevel, "[{remote}] Message matched rule #{i}");
+            (rule.ack_args(&server.ack), rule.delay)
+        }
+        None => (server.ack.clone(), None),
+    };
+    let acks = match response.ack_mode {
+        cli::AckMode::Ignore => {
+            debug!(stderr, loglevel, "[{remote}] Not generating ACK");
+            None
+        }
+        ack_mode => {
+            debug!(stderr, loglevel, "[{remote}] Generating {ack_mode} ACKs");
+            Some(
+                ack::generate_acks(&message, &response)
+                    .wrap_err_with(|| "Failed to generate ACK")?,
+            )
+        }
+    };
+    let parsed_message = if let Some(acks) = acks {
+        if acks.is_empty() {
+            info!(
+                stderr,
+                loglevel, "[{remote}] Not sending ACK, none requested by MSH-15/MSH-16"
+            );
         }
+        if let Some(delay) = delay.filter(|_| !acks.is_empty()) {
+            debug!(
+                stderr,
+                loglevel, "[{remote}] Waiting {delay:?} before sending ACK"
+            );
+            tokio::time::sleep(delay).await;
+        }
+        for ack in acks {
+            info!(stderr, loglevel, "[{remote}] Sending ACK");
+            debug!(stderr, loglevel, "[{remote}] ACK:\n{}", ack);
+            transport
+                .send(BytesMut::from(ack.as_bytes()))
+                .await
+                .wrap_err_with(|| "Failed to send ACK")?;
+        }
+        // messages which failed validation, or have a truncation character in MSH-2, can be
+        // ACKed but not highlighted
+        hl7_parser::ParsedMessage::parse(&message, false).ok()
+    } else {
+        None
+    };
+
+    // hold stdout so that messages from different connections don't interleave
+    let _lock = std::io::stdout().lock();
+    // tag each message with the connection it came from
+    log(format!("[{remote}]"), 3, &mut stdout).wrap_err_with(|| "Failed to print message")?;
+    if let Some(parsed_message) = parsed_message {
+        print::print_message_hl(&mut stdout, parsed_message)
+            .wrap_err_with(|| "Failed to print message")?;
+    } else {
+        print::print_message_nohl(&message).wrap_err_with(|| "Failed to print message")?;
     }
 
     Ok(())
 }
 
-fn open_stdout(cli: &cli::Cli) -> StandardStream {
-    let colour = match cli.colour {
+fn open_stdout(colour: clap::ColorChoice) -> StandardStream {
+    let colour = match colour {
         clap::ColorChoice::Auto => termcolor::ColorChoice::Auto,
         clap::ColorChoice::Always => termcolor::ColorChoice::Always,
         clap::ColorChoice::Never => termcolor::ColorChoice::Never,
@@ -261,8 +422,8 @@ fn open_stdout(cli: &cli::Cli) -> StandardStream {
     StandardStream::stdout(colour)
 }
 
-fn open_stderr(cli: &cli::Cli) -> StandardStream {
-    let colour = match cli.colour {
+fn open_stderr(colour: clap::ColorChoice) -> StandardStream {
+    let colour = match colour {
         clap::ColorChoice::Auto => termcolor::ColorChoice::Auto,
         clap::ColorChoice::Always => termcolor::ColorChoice::Always,
         clap::ColorChoice::Never => termcolor::ColorChoice::Never,
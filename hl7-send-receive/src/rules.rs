@@ -0,0 +1,307 @@
+use crate::{
+    ack::without_truncation_character,
+    cli::{AckArgs, AckMode, Severity},
+};
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Context, Result};
+use hl7_common::filter::{parse_test, FilterTest};
+use hl7_parser::{LocationQuery, ParsedMessage};
+use serde::Deserialize;
+use std::{path::Path, str::FromStr, time::Duration};
+
+/// A list of rules choosing how to respond to each message, read from a TOML or YAML file. The
+/// first rule whose conditions all hold for a message is used, and a rule without any
+/// conditions matches every message.
+///
+/// ```toml
+/// [[rules]]
+/// when = ["MSH-9.2=A08", "PID-3.1~^9"]
+/// ack = "AR"
+/// text = "Unknown patient"
+/// error_code = "204"
+/// error_location = "PID.3"
+///
+/// [[rules]]
+/// when = ["MSH-9.1=ORU"]
+/// delay = 5.0
+///
+/// [[rules]]
+/// when = ["PID-3.1=TIMEOUT"]
+/// ack = "ignore"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    rules: Vec<RuleEntry>,
+}
+
+/// A single rule in a rules file. Anything not given is taken from the command line options.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleEntry {
+    /// Conditions which must all hold for the rule to match a message
+    #[serde(default)]
+    when: Vec<String>,
+    /// The ACK mode (`success`, `error`, `reject`, or `ignore` to not respond at all), or the
+    /// ACK code (`AA`, `AE`, or `AR`)
+    ack: Option<String>,
+    /// The text to send in MSA-3
+    text: Option<String>,
+    /// The HL7 error code (table 0357) to send in ERR-3
+    error_code: Option<String>,
+    /// The severity to send in ERR-4
+    severity: Option<String>,
+    /// The location of the error to send in ERR-2
+    error_location: Option<String>,
+    /// The number of seconds to wait before responding
+    delay: Option<f64>,
+}
+
+/// A test of a value in a message, using the same syntax as `hq filter --where` (ex:
+/// `PID-3.1~^9`) but with a plain location rather than an `hq` query
+#[derive(Debug, Clone)]
+pub struct Condition {
+    pub location: LocationQuery,
+    pub test: FilterTest,
+}
+
+impl Condition {
+    /// Whether the condition holds for the message. Missing values are treated as empty.
+    pub fn matches(&self, message: &ParsedMessage) -> bool {
+        let value = message
+            .query_value(&self.location)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        self.test.matches(&[value])
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (location, test) = parse_test(s)?;
+        Ok(Condition {
+            location: LocationQuery::from_str(location)?,
+            test,
+        })
+    }
+}
+
+/// How to respond to the messages matching a set of conditions
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub when: Vec<Condition>,
+    pub ack_mode: Option<AckMode>,
+    pub text: Option<String>,
+    pub error_code: Option<String>,
+    pub severity: Option<Severity>,
+    pub error_location: Option<LocationQuery>,
+    pub delay: Option<Duration>,
+}
+
+impl RuleEntry {
+    fn into_rule(self) -> Result<Rule> {
+        let RuleEntry {
+            when,
+            ack,
+            text,
+            error_code,
+            severity,
+            error_location,
+            delay,
+        } = self;
+
+        let when = when
+            .iter()
+            .map(|when| Condition::from_str(when).map_err(|e| eyre!("Invalid condition: {e}")))
+            .collect::<Result<Vec<_>>>()?;
+        let ack_mode = ack
+            .map(|ack| match ack.as_str() {
+                "AA" => Ok(AckMode::Success),
+                "AE" => Ok(AckMode::Error),
+                "AR" => Ok(AckMode::Reject),
+                ack => AckMode::from_str(ack, true).map_err(|e| eyre!("Invalid ACK mode: {e}")),
+            })
+            .transpose()?;
+        let severity = severity
+            .map(|severity| {
+                Severity::from_str(&severity, true).map_err(|e| eyre!("Invalid severity: {e}"))
+            })
+            .transpose()?;
+        let error_location = error_location
+            .map(|location| {
+                LocationQuery::from_str(&location).map_err(|e| eyre!("Invalid error location: {e}"))
+            })
+            .transpose()?;
+        let delay = delay
+            .map(|delay| {
+                Duration::try_from_secs_f64(delay).map_err(|e| eyre!("Invalid delay: {e}"))
+            })
+            .transpose()?;
+
+        Ok(Rule {
+            when,
+            ack_mode,
+            text,
+            error_code,
+            severity,
+            error_location,
+            delay,
+        })
+    }
+}
+
+impl Rule {
+    /// Whether all of the rule's conditions hold for the message
+    pub fn matches(&self, message: &ParsedMessage) -> bool {
+        self.when.iter().all(|condition| condition.matches(message))
+    }
+
+    /// The ACK options for the messages matching the rule, filling in anything the rule doesn't
+    /// set from the command line options
+    pub fn ack_args(&self, args: &AckArgs) -> AckArgs {
+        let mut args = args.clone();
+        if let Some(ack_mode) = self.ack_mode {
+            args.ack_mode = ack_mode;
+        }
+        if let Some(text) = &self.text {
+            args.ack_text = Some(text.clone());
+        }
+        if let Some(error_code) = &self.error_code {
+            args.error_code = error_code.clone();
+        }
+        if let Some(severity) = self.severity {
+            args.severity = severity;
+        }
+        if let Some(error_location) = &self.error_location {
+            args.error_location = Some(error_location.clone());
+        }
+        args
+    }
+}
+
+/// Find the first rule matching a message, along with its 1-based index. Messages which don't
+/// parse only match rules without any conditions.
+pub fn find_rule<'r>(rules: &'r [Rule], message: &str) -> Option<(usize, &'r Rule)> {
+    let message = without_truncation_character(message);
+    let message = ParsedMessage::parse(&message, false).ok();
+    rules
+        .iter()
+        .enumerate()
+        .find(|(_, rule)| match &message {
+            Some(message) => rule.matches(message),
+            None => rule.when.is_empty(),
+        })
+        .map(|(i, rule)| (i + 1, rule))
+}
+
+/// Read the rules from a rules file. The format is chosen from the file's extension (`.toml`,
+/// `.yaml`, or `.yml`).
+pub fn load_rules(path: &Path) -> Result<Vec<Rule>> {
+    parse_rules(hl7_common::config::load(path, "rules")?)
+}
+
+fn parse_rules(rules: RulesFile) -> Result<Vec<Rule>> {
+    rules
+        .rules
+        .into_iter()
+        .enumerate()
+        .map(|(i, rule)| {
+            rule.into_rule()
+                .wrap_err_with(|| format!("Invalid rule #{} in rules file", i + 1))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn args() -> AckArgs {
+        let cli = crate::cli::Cli::parse_from(["hs", "listen"]);
+        let crate::cli::Command::Listen { ack, .. } = cli.command else {
+            panic!("listen command");
+        };
+        ack
+    }
+
+    fn message(event: &str, mrn: &str) -> String {
+        format!("MSH|^~\\&|A|1|B|2|20050110045504||ADT^{event}|1|P|2.5.1\rPID|1||{mrn}^^^A^MR")
+    }
+
+    #[test]
+    fn can_read_toml_and_yaml_rules() {
+        let toml = r#"
+[[rules]]
+when = ["MSH-9.2=A08", "PID-3.1~^9"]
+ack = "AR"
+text = "Unknown patient"
+error_code = "204"
+severity = "warning"
+error_location = "PID.3"
+
+[[rules]]
+when = ["PID-3.1=TIMEOUT"]
+ack = "ignore"
+delay = 1.5
+
+[[rules]]
+ack = "success"
+"#;
+        let yaml = r#"
+rules:
+  - when: [MSH-9.2=A08, PID-3.1~^9]
+    ack: AR
+    text: Unknown patient
+    error_code: "204"
+    severity: warning
+    error_location: PID.3
+  - when: [PID-3.1=TIMEOUT]
+    ack: ignore
+    delay: 1.5
+  - ack: success
+"#;
+        for rules in [
+            toml::from_str::<RulesFile>(toml).expect("valid TOML"),
+            serde_yaml::from_str::<RulesFile>(yaml).expect("valid YAML"),
+        ] {
+            let rules = parse_rules(rules).expect("valid rules");
+            assert_eq!(rules.len(), 3);
+
+            let find = |message: &str| find_rule(&rules, message).map(|(i, _)| i);
+            assert_eq!(find(&message("A08", "987")), Some(1));
+            assert_eq!(find(&message("A08", "123")), Some(3));
+            assert_eq!(find(&message("A01", "987")), Some(3));
+            assert_eq!(find(&message("A01", "TIMEOUT")), Some(2));
+            assert_eq!(find("MSH|^~\\&|A"), Some(3));
+
+            let ack = rules[0].ack_args(&args());
+            assert!(matches!(ack.ack_mode, AckMode::Reject));
+            assert_eq!(ack.ack_text.as_deref(), Some("Unknown patient"));
+            assert_eq!(ack.error_code, "204");
+            assert_eq!(ack.severity, Severity::Warning);
+            assert_eq!(
+                ack.error_location.map(|location| location.to_string()),
+                Some("PID.3[1]".to_string())
+            );
+            assert!(matches!(rules[1].ack_mode, Some(AckMode::Ignore)));
+            assert_eq!(rules[1].delay, Some(Duration::from_millis(1500)));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        let rules = |yaml: &str| {
+            let rules: RulesFile = serde_yaml::from_str(yaml).expect("valid YAML");
+            parse_rules(rules)
+        };
+        assert!(rules("rules:\n  - ack: XX\n").is_err());
+        assert!(rules("rules:\n  - when: [PID-3~(]\n").is_err());
+        assert!(rules("rules:\n  - severity: bad\n").is_err());
+        assert!(rules("rules:\n  - delay: -1\n").is_err());
+        assert!(serde_yaml::from_str::<RulesFile>("rules:\n  - nope: 1\n").is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+/// Frame a message for MLLP
+fn mllp(message: &str) -> Vec<u8> {
+    let mut frame = vec![0x0b];
+    frame.extend_from_slice(message.as_bytes());
+    frame.extend_from_slice(&[0x1c, 0x0d]);
+    frame
+}
+
+/// Read a single MLLP frame
+fn read_frame(stream: &mut TcpStream) -> String {
+    let mut frame = Vec::new();
+    let mut byte = [0u8; 1];
+    while !frame.ends_with(&[0x1c, 0x0d]) {
+        stream.read_exact(&mut byte).expect("can read ACK");
+        frame.push(byte[0]);
+    }
+    String::from_utf8_lossy(&frame[1..frame.len() - 2]).to_string()
+}
+
+/// A running `hs listen`, with its log lines read as they're written
+struct Listener {
+    hs: Child,
+    /// The address hs is listening on
+    address: String,
+    log: Receiver<String>,
+}
+
+impl Listener {
+    /// Start `hs listen` with debug logging and wait until it's listening. hs picks a free port
+    /// itself, so no other process can take the port before it binds to it.
+    fn start(args: &[&str]) -> Listener {
+        let mut hs = Command::new(env!("CARGO_BIN_EXE_hs"))
+            .args([
+                "--colour",
+                "never",
+                "-v",
+                "-v",
+                "listen",
+                "-b",
+                "127.0.0.1:0",
+            ])
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("can start hs");
+        let stderr = hs.stderr.take().expect("stderr is piped");
+        let (sender, log) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut listener = Listener {
+            hs,
+            address: String::new(),
+            log,
+        };
+        let line = listener.wait_for("Listening on ");
+        listener.address = line["Listening on ".len()..].to_string();
+        listener
+    }
+
+    /// Wait for hs to log a line containing some text, returning the line
+    fn wait_for(&self, text: &str) -> String {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.log.recv_timeout(remaining) {
+                Ok(line) if line.contains(text) => return line,
+                Ok(_) => {}
+                Err(err) => panic!("hs didn't log {text:?}: {err}"),
+            }
+        }
+    }
+
+    fn connect(&self) -> TcpStream {
+        TcpStream::connect(&self.address).expect("can connect to hs")
+    }
+
+    /// Wait for hs to quit, returning its exit status, stdout, and the rest of its log
+    fn finish(self) -> (ExitStatus, String, String) {
+        let output = self.hs.wait_with_output().expect("hs exits");
+        let log: Vec<String> = self.log.iter().collect();
+        (
+            output.status,
+            String::from_utf8_lossy(&output.stdout).to_string(),
+            log.join("\n"),
+        )
+    }
+}
+
+fn message(control_id: &str) -> String {
+    format!("MSH|^~\\&|A|1|B|2|20050110045504||ADT^A01|{control_id}|P|2.5.1\rPID|1")
+}
+
+#[test]
+fn should_answer_every_connection_before_quitting() {
+    let dir = std::env::temp_dir().join(format!("hs-listen-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("can create temp dir");
+    let rules = dir.join("rules.toml");
+    std::fs::write(
+        &rules,
+        "[[rules]]\nwhen = [\"MSH-10=SLOW\"]\ndelay = 1.0\n\n[[rules]]\ndelay = 0.1\n",
+    )
+    .expect("can write rules");
+
+    let rules = rules.to_str().expect("path is UTF-8");
+    let hs = Listener::start(&["-m", "2", "-r", rules]);
+
+    // the first message is answered after the second, which reaches the message count
+    let mut slow = hs.connect();
+    slow.write_all(&mllp(&message("SLOW"))).expect("can send");
+    let slow_address = slow.local_addr().expect("has an address");
+    hs.wait_for(&format!("[{slow_address}] Message matched rule #1"));
+    let mut fast = hs.connect();
+    fast.write_all(&mllp(&message("FAST"))).expect("can send");
+
+    for (stream, control_id) in [(&mut fast, "FAST"), (&mut slow, "SLOW")] {
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .expect("can set timeout");
+        let ack = read_frame(stream);
+        assert!(
+            ack.contains(&format!("\rMSA|AA|{control_id}|")),
+            "unexpected ACK: {ack:?}"
+        );
+    }
+
+    let (status, stdout, _) = hs.finish();
+    assert!(status.success());
+    assert!(stdout.contains("ADT^A01|SLOW|"));
+    assert!(stdout.contains("ADT^A01|FAST|"));
+    // each message is tagged with the connection it came from, even when piped
+    assert_eq!(stdout.matches("[127.0.0.1:").count(), 2, "stdout: {stdout}");
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn should_keep_serving_after_a_connection_fails() {
+    let hs = Listener::start(&["-m", "2"]);
+
+    // a message which can't be parsed fails its connection
+    let mut bad = hs.connect();
+    bad.write_all(&mllp("not a message")).expect("can send");
+    bad.set_read_timeout(Some(Duration::from_secs(10)))
+        .expect("can set timeout");
+    let mut rest = Vec::new();
+    bad.read_to_end(&mut rest).expect("connection is closed");
+    assert!(rest.is_empty(), "unexpected ACK: {rest:?}");
+
+    let mut good = hs.connect();
+    good.write_all(&mllp(&message("GOOD"))).expect("can send");
+    good.set_read_timeout(Some(Duration::from_secs(10)))
+        .expect("can set timeout");
+    let ack = read_frame(&mut good);
+    assert!(ack.contains("\rMSA|AA|GOOD|"), "unexpected ACK: {ack:?}");
+
+    let (status, _, log) = hs.finish();
+    assert!(status.success());
+    assert!(log.contains("] Connection failed: "), "log: {log}");
+}